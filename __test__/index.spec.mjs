@@ -1,4 +1,8 @@
+import { existsSync } from "node:fs";
+import { readFile } from "node:fs/promises";
+import { connect } from "node:net";
 import process from "node:process";
+import { gzipSync } from "node:zlib";
 import test, { registerCompletionHandler } from "ava";
 
 import { AouRequest, AouServer } from "../index.js";
@@ -23,6 +27,118 @@ test.before("setup server", async () => {
     };
   });
 
+  server.get("/stream", async (req) => {
+    async function* chunks() {
+      yield "hello ";
+      yield Buffer.from("world");
+    }
+
+    return {
+      body: null,
+      stream: chunks(),
+    };
+  });
+
+  server.post("/binary", async (req) => {
+    let invalidText = false;
+    try {
+      req.body;
+    } catch {
+      invalidText = true;
+    }
+
+    // Writes go to a copy, the request keeps its body
+    req.buffer().fill(0);
+    new Uint8Array(req.arrayBuffer()).fill(0);
+
+    return {
+      body: {
+        buffer: [...req.buffer()],
+        byteLength: req.arrayBuffer().byteLength,
+        invalidText,
+      },
+    };
+  });
+
+  server.post("/json", async (req) => {
+    const value = await req.json();
+
+    return {
+      body: {
+        value,
+      },
+    };
+  });
+
+  server.get("/files/{file}", async (req) => {
+    return {
+      body: {
+        file: req.params.file,
+        path: req.path,
+        raw: req.rawPath,
+      },
+    };
+  });
+
+  server.get("/cookies", async (req) => {
+    return {
+      headers: {
+        "Set-Cookie": ["session=abc; HttpOnly", "csrf=xyz"],
+        "X-Single": "one",
+      },
+      body: "ok",
+    };
+  });
+
+  server.get("/session", async (req) => {
+    return {
+      cookies: [
+        {
+          name: "session",
+          value: "two words",
+          path: "/",
+          expires: new Date(Date.UTC(2030, 0, 1)),
+          httpOnly: true,
+          sameSite: "Lax",
+        },
+        { name: "seen", value: req.cookies.seen ?? "no" },
+      ],
+      body: req.cookies,
+    };
+  });
+
+  server.get("/query", async (req) => {
+    return {
+      body: {
+        query: req.query,
+        raw: req.rawQuery,
+      },
+    };
+  });
+
+  server.post("/form", async (req) => {
+    return {
+      body: await req.form(),
+    };
+  });
+
+  server.post("/multipart", async (req) => {
+    const { fields, files } = await req.multipart();
+
+    return {
+      body: {
+        fields,
+        files: files.map((file) => ({
+          name: file.name,
+          filename: file.filename,
+          contentType: file.contentType,
+          size: file.size,
+          text: file.buffer?.toString(),
+        })),
+      },
+    };
+  });
+
   const instance = await server.listen(addr, port);
 });
 
@@ -50,6 +166,538 @@ test("req.params , req.query , req.header", async (t) => {
   t.is(body.header, test_header);
 });
 
+test("query strings with repeated and nested keys", async (t) => {
+  const res = await fetch(
+    `http://${addr}:${port}/query?q=two+words&tag=a&tag=b%26c&filter[name]=aou`,
+  );
+
+  t.deepEqual(await res.json(), {
+    query: { q: "two words", tag: ["a", "b&c"], "filter[name]": "aou" },
+    raw: "q=two+words&tag=a&tag=b%26c&filter[name]=aou",
+  });
+
+  const nested_server = new AouServer({ nestedQuery: true });
+  const nested_port = 7074;
+
+  nested_server.get("/query", async (req) => {
+    return {
+      body: req.query,
+    };
+  });
+
+  const instance = await nested_server.listen(addr, nested_port);
+
+  const nested = await fetch(
+    `http://${addr}:${nested_port}/query?filter[name]=aou&filter[roles][]=admin&filter[roles][]=dev`,
+  );
+  t.deepEqual(await nested.json(), {
+    filter: { name: "aou", roles: ["admin", "dev"] },
+  });
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("paths are normalized and params decoded", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/files//my%20doc`);
+
+  t.deepEqual(await res.json(), {
+    file: "my doc",
+    path: "/files/my doc",
+    raw: "/files//my%20doc",
+  });
+
+  const encoded_slash = await fetch(`http://${addr}:${port}/files/a%2Fb`);
+  t.is((await encoded_slash.json()).file, "a/b");
+
+  const slash_server = new AouServer({ trailingSlash: "redirect" });
+  const slash_port = 7075;
+
+  slash_server.get("/users", async (req) => {
+    return {
+      body: "users",
+    };
+  });
+
+  const instance = await slash_server.listen(addr, slash_port);
+
+  const redirect = await fetch(`http://${addr}:${slash_port}/users/?page=2`, {
+    redirect: "manual",
+  });
+  t.is(redirect.status, 308);
+  t.is(redirect.headers.get("location"), "/users?page=2");
+
+  await instance.close({ timeout: 1000 });
+
+  t.throws(() => new AouServer({ trailingSlash: "sometimes" }));
+});
+
+test("array header values are written as separate lines", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/cookies`);
+
+  t.deepEqual(res.headers.getSetCookie(), ["session=abc; HttpOnly", "csrf=xyz"]);
+  t.is(res.headers.get("x-single"), "one");
+});
+
+test("req.cookies and response cookies", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/session`, {
+    headers: { cookie: "seen=yes; name=two%20words" },
+  });
+
+  t.deepEqual(await res.json(), { seen: "yes", name: "two words" });
+  t.deepEqual(res.headers.getSetCookie(), [
+    "session=two%20words; Path=/; Expires=Tue, 01 Jan 2030 00:00:00 GMT; HttpOnly; SameSite=Lax",
+    "seen=yes",
+  ]);
+});
+
+test("cookie sessions", async (t) => {
+  const session_server = new AouServer({
+    session: { secrets: ["a secret that is at least 32 chars long"], encrypt: true },
+  });
+  const session_port = 7076;
+
+  session_server.get("/visits", async (req) => {
+    const visits = (req.session.get("visits") ?? 0) + 1;
+    req.session.set("visits", visits);
+
+    return {
+      body: { visits },
+    };
+  });
+
+  session_server.get("/peek", async (req) => {
+    return {
+      body: req.session.data,
+    };
+  });
+
+  const instance = await session_server.listen(addr, session_port);
+
+  const first = await fetch(`http://${addr}:${session_port}/visits`);
+  t.deepEqual(await first.json(), { visits: 1 });
+  const [cookie] = first.headers.getSetCookie();
+  t.regex(cookie, /^aou\.session=[\w-]+; Path=\/; HttpOnly; SameSite=Lax$/);
+
+  const session_cookie = cookie.split(";")[0];
+  const second = await fetch(`http://${addr}:${session_port}/visits`, {
+    headers: { cookie: session_cookie },
+  });
+  t.deepEqual(await second.json(), { visits: 2 });
+
+  const peek = await fetch(`http://${addr}:${session_port}/peek`, {
+    headers: { cookie: session_cookie },
+  });
+  t.deepEqual(await peek.json(), { visits: 1 });
+  t.deepEqual(peek.headers.getSetCookie(), [], "Unchanged sessions aren't sent again");
+
+  const tampered = await fetch(`http://${addr}:${session_port}/peek`, {
+    headers: { cookie: session_cookie.slice(0, -2) + "AA" },
+  });
+  t.deepEqual(await tampered.json(), {});
+
+  await instance.close({ timeout: 1000 });
+
+  t.throws(() => new AouServer({ session: { secrets: ["short"] } }));
+});
+
+test("response compression", async (t) => {
+  const compression_server = new AouServer({ compression: true });
+  const compression_port = 7077;
+  const text = "compressible ".repeat(200);
+
+  compression_server.get("/text", async () => {
+    return {
+      headers: { "Content-Type": "text/plain; charset=utf-8" },
+      body: text,
+    };
+  });
+
+  compression_server.get("/small", async () => {
+    return {
+      headers: { "Content-Type": "text/plain" },
+      body: "tiny",
+    };
+  });
+
+  compression_server.get("/image", async () => {
+    return {
+      headers: { "Content-Type": "image/png" },
+      buffer: Buffer.alloc(4096),
+    };
+  });
+
+  const instance = await compression_server.listen(addr, compression_port);
+  const url = `http://${addr}:${compression_port}`;
+
+  for (const encoding of ["br", "gzip", "deflate"]) {
+    const res = await fetch(`${url}/text`, { headers: { "accept-encoding": encoding } });
+    t.is(res.headers.get("content-encoding"), encoding);
+    t.is(res.headers.get("vary"), "Accept-Encoding");
+    t.is(await res.text(), text);
+  }
+
+  const identity = await fetch(`${url}/text`, { headers: { "accept-encoding": "identity" } });
+  t.is(identity.headers.get("content-encoding"), null);
+  t.is(identity.headers.get("content-length"), String(text.length));
+  t.is(identity.headers.get("vary"), "Accept-Encoding");
+
+  const small = await fetch(`${url}/small`, { headers: { "accept-encoding": "gzip" } });
+  t.is(small.headers.get("content-encoding"), null);
+  t.is(await small.text(), "tiny");
+
+  const image = await fetch(`${url}/image`, { headers: { "accept-encoding": "gzip" } });
+  t.is(image.headers.get("content-encoding"), null);
+  t.is(image.headers.get("vary"), null);
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("static files", async (t) => {
+  const static_server = new AouServer();
+  const static_port = 7078;
+
+  static_server.static("/assets/{*path}", "fixtures");
+  static_server.static("/app/{*path}", "fixtures", { fallback: "hello_world.txt", maxAge: 60 });
+
+  const instance = await static_server.listen(addr, static_port);
+  const url = `http://${addr}:${static_port}`;
+
+  const text = await fetch(`${url}/assets/hello_world.txt`);
+  t.is(text.status, 200);
+  t.is(text.headers.get("content-type"), "text/plain; charset=utf-8");
+  t.regex(text.headers.get("etag"), /^"[0-9a-f]+-54"$/);
+  t.truthy(text.headers.get("last-modified"));
+  t.regex(await text.text(), /Hello World from File$/);
+
+  const image = await fetch(`${url}/assets/image.png`);
+  t.is(image.headers.get("content-type"), "image/png");
+  const bytes = new Uint8Array(await image.arrayBuffer());
+  t.is(image.headers.get("content-length"), String(bytes.length));
+  t.deepEqual([...bytes.slice(1, 4)], [0x50, 0x4e, 0x47]);
+
+  const head = await fetch(`${url}/assets/image.png`, { method: "HEAD" });
+  t.is(head.headers.get("content-length"), String(bytes.length));
+
+  t.is((await fetch(`${url}/assets/missing.txt`)).status, 404);
+  t.is((await fetch(`${url}/assets/..%2FCargo.toml`)).status, 404);
+
+  const fallback = await fetch(`${url}/app/some/client/route`);
+  t.is(fallback.status, 200);
+  t.is(fallback.headers.get("cache-control"), "public, max-age=60");
+  t.regex(await fallback.text(), /Hello World from File$/);
+
+  t.throws(() => static_server.static("/missing/{*path}", "fixtures/missing"));
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("range requests", async (t) => {
+  const range_server = new AouServer();
+  const range_port = 7079;
+  const data = Buffer.from("0123456789abcdefghij");
+
+  range_server.static("/files/{*path}", "fixtures");
+  range_server.get("/buffer", async () => {
+    return {
+      headers: { "Content-Type": "text/plain", ETag: '"v1"' },
+      body: null,
+      buffer: data,
+    };
+  });
+
+  const instance = await range_server.listen(addr, range_port);
+  const url = `http://${addr}:${range_port}`;
+  const get = (path, headers) => fetch(`${url}${path}`, { headers });
+
+  const single = await get("/buffer", { range: "bytes=2-5" });
+  t.is(single.status, 206);
+  t.is(single.headers.get("content-range"), "bytes 2-5/20");
+  t.is(single.headers.get("accept-ranges"), "bytes");
+  t.is(await single.text(), "2345");
+
+  const suffix = await get("/buffer", { range: "bytes=-3" });
+  t.is(await suffix.text(), "hij");
+
+  const multi = await get("/buffer", { range: "bytes=0-1, 10-" });
+  t.is(multi.status, 206);
+  const [, boundary] = multi.headers.get("content-type").match(/^multipart\/byteranges; boundary=(\w+)$/);
+  t.is(
+    await multi.text(),
+    `--${boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/20\r\n\r\n01` +
+      `\r\n--${boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 10-19/20\r\n\r\nabcdefghij` +
+      `\r\n--${boundary}--\r\n`
+  );
+
+  const unsatisfiable = await get("/buffer", { range: "bytes=20-" });
+  t.is(unsatisfiable.status, 416);
+  t.is(unsatisfiable.headers.get("content-range"), "bytes */20");
+
+  const stale = await get("/buffer", { range: "bytes=0-1", "if-range": '"v0"' });
+  t.is(stale.status, 200);
+  t.is(await stale.text(), data.toString());
+
+  const full = await get("/files/hello_world.txt");
+  const etag = full.headers.get("etag");
+  const file = await full.text();
+
+  const file_range = await get("/files/hello_world.txt", { range: "bytes=-15", "if-range": etag });
+  t.is(file_range.status, 206);
+  t.is(await file_range.text(), file.slice(-15));
+
+  const file_multi = await get("/files/hello_world.txt", { range: "bytes=0-2,4-5" });
+  t.is(file_multi.status, 206);
+  t.regex(await file_multi.text(), /Content-Range: bytes 0-2\/84\r\n\r\nGET\r\n--\w+\r\nContent-Type: text\/plain; charset=utf-8\r\nContent-Range: bytes 4-5\/84\r\n\r\n\/ \r\n/);
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("conditional requests", async (t) => {
+  const etag_server = new AouServer({ etag: "strong", compression: true, compressionMinSize: 0 });
+  const etag_port = 7080;
+  let calls = 0;
+
+  etag_server.get("/poll", async () => {
+    calls += 1;
+    return { headers: { "Content-Type": "application/json", "Cache-Control": "no-cache" }, body: { status: "pending" } };
+  });
+  etag_server.get("/own", async () => {
+    return { headers: { ETag: '"v1"', "Content-Type": "text/plain" }, body: "own" };
+  });
+  etag_server.get("/off", async () => ({ body: "off" }), { etag: false });
+  etag_server.static("/files/{*path}", "fixtures");
+
+  const instance = await etag_server.listen(addr, etag_port);
+  const url = `http://${addr}:${etag_port}`;
+  const get = (path, headers) => fetch(`${url}${path}`, { headers });
+
+  const first = await get("/poll", { "accept-encoding": "identity" });
+  const etag = first.headers.get("etag");
+  t.regex(etag, /^"[\w-]+"$/);
+  t.deepEqual(await first.json(), { status: "pending" });
+
+  const cached = await get("/poll", { "accept-encoding": "identity", "if-none-match": etag });
+  t.is(cached.status, 304);
+  t.is(cached.headers.get("etag"), etag);
+  t.is(cached.headers.get("cache-control"), "no-cache");
+  t.is(cached.headers.get("content-type"), null);
+  t.is(await cached.text(), "");
+  t.is(calls, 2);
+
+  const gzip = await get("/poll", { "accept-encoding": "gzip" });
+  t.is(gzip.headers.get("etag"), etag.replace(/"$/, '-gzip"'));
+  t.is((await get("/poll", { "accept-encoding": "gzip", "if-none-match": etag })).status, 200);
+
+  t.is((await get("/own", { "if-none-match": 'W/"v1"' })).status, 304);
+  t.is((await get("/own", { "if-none-match": '"v2"' })).status, 200);
+
+  const off = await get("/off", { "if-none-match": "*" });
+  t.is(off.status, 200);
+  t.is(off.headers.get("etag"), null);
+
+  const file = await get("/files/hello_world.txt");
+  const not_modified = await get("/files/hello_world.txt", {
+    "if-modified-since": file.headers.get("last-modified"),
+  });
+  t.is(not_modified.status, 304);
+  t.is((await get("/files/hello_world.txt", { "if-none-match": file.headers.get("etag") })).status, 304);
+
+  await instance.close({ timeout: 1000 });
+});
+
+/** Upgrades a raw socket to a WebSocket, frames are read with `next()` and written masked with `send()` */
+async function websocket(url_port, path, headers = "") {
+  const socket = connect(url_port, addr);
+  let buffer = Buffer.alloc(0);
+  let waiting;
+  socket.on("data", (data) => {
+    buffer = Buffer.concat([buffer, data]);
+    waiting?.();
+  });
+  socket.on("close", () => waiting?.());
+  const more = () => new Promise((resolve) => (waiting = resolve));
+
+  socket.write(
+    `GET ${path} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n` +
+      `Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n${headers}\r\n`,
+  );
+  while (!buffer.includes("\r\n\r\n")) await more();
+  const end = buffer.indexOf("\r\n\r\n") + 4;
+  const head = buffer.subarray(0, end).toString();
+  buffer = buffer.subarray(end);
+
+  return {
+    head,
+    send(opcode, payload, fin = true) {
+      payload = Buffer.from(payload);
+      const mask = Buffer.from([1, 2, 3, 4]);
+      const len = payload.length < 126 ? [0x80 | payload.length] : [0x80 | 126, payload.length >> 8, payload.length & 0xff];
+      socket.write(Buffer.concat([
+        Buffer.from([(fin ? 0x80 : 0) | opcode, ...len]),
+        mask,
+        payload.map((byte, i) => byte ^ mask[i % 4]),
+      ]));
+    },
+    async next() {
+      while (buffer.length < 2 || buffer.length < 2 + (buffer[1] === 126 ? 2 : 0) + frameLength()) {
+        if (socket.destroyed) return null;
+        await more();
+      }
+      const offset = buffer[1] === 126 ? 4 : 2;
+      const frame = { opcode: buffer[0] & 0x0f, payload: buffer.subarray(offset, offset + frameLength()) };
+      buffer = buffer.subarray(offset + frame.payload.length);
+      return frame;
+    },
+    write: (data) => socket.write(data),
+    end: () => socket.end(),
+  };
+
+  function frameLength() {
+    return buffer[1] === 126 ? (buffer.length >= 4 ? buffer.readUInt16BE(2) : Infinity) : buffer[1];
+  }
+}
+
+test("websockets", async (t) => {
+  const ws_server = new AouServer();
+  const ws_port = 7081;
+  const closed = [];
+  let closedResolve;
+  const closedOnce = new Promise((resolve) => (closedResolve = resolve));
+
+  ws_server.ws(
+    "/echo/{room}",
+    {
+      open(ws, req) {
+        ws.send(`welcome ${req.params.room} ${ws.protocol}`);
+      },
+      async message(ws, data) {
+        if (data === "bye") return ws.close(4000, "done");
+        ws.send(typeof data === "string" ? data.toUpperCase() : Buffer.concat([data, data]));
+      },
+      close(ws, code, reason) {
+        closed.push([code, reason, ws.closing]);
+        closedResolve();
+      },
+    },
+    { protocols: ["chat"], maxPayloadLength: 1024 },
+  );
+
+  const instance = await ws_server.listen(addr, ws_port);
+
+  const client = await websocket(ws_port, "/echo/lobby", "Sec-WebSocket-Protocol: other, chat\r\n");
+  t.regex(client.head, /^HTTP\/1.1 101 Switching Protocols\r\n/);
+  t.regex(client.head, /Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK\+xOo=\r\n/);
+  t.regex(client.head, /Sec-WebSocket-Protocol: chat\r\n/);
+  t.is((await client.next()).payload.toString(), "welcome lobby chat");
+
+  client.send(0x1, "hello");
+  t.deepEqual(await client.next(), { opcode: 0x1, payload: Buffer.from("HELLO") });
+
+  // Fragmented binary message with a ping in between
+  client.send(0x2, [1, 2], false);
+  client.send(0x9, "ping");
+  client.send(0x0, [3], true);
+  t.deepEqual(await client.next(), { opcode: 0xa, payload: Buffer.from("ping") });
+  t.deepEqual(await client.next(), { opcode: 0x2, payload: Buffer.from([1, 2, 3, 1, 2, 3]) });
+
+  client.send(0x1, "bye");
+  const close = await client.next();
+  t.is(close.opcode, 0x8);
+  t.is(close.payload.readUInt16BE(0), 4000);
+  t.is(close.payload.subarray(2).toString(), "done");
+  client.send(0x8, close.payload.subarray(0, 2));
+  await closedOnce;
+  t.deepEqual(closed, [[4000, "", true]]);
+
+  // Protocol errors close the socket with a status code
+  const too_large = await websocket(ws_port, "/echo/big");
+  await too_large.next();
+  too_large.send(0x1, "x".repeat(2000));
+  const too_large_close = await too_large.next();
+  t.is(too_large_close.payload.readUInt16BE(0), 1009);
+  too_large.end();
+
+  const not_upgrade = await fetch(`http://${addr}:${ws_port}/echo/lobby`);
+  t.is(not_upgrade.status, 426);
+  t.is(not_upgrade.headers.get("upgrade"), "websocket");
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("websocket idle timeout only counts complete frames", async (t) => {
+  const idle_server = new AouServer();
+  const idle_port = 7085;
+
+  idle_server.ws("/idle", {}, { idleTimeout: 200 });
+
+  const instance = await idle_server.listen(addr, idle_port);
+  const client = await websocket(idle_port, "/idle");
+
+  // One byte of a frame that is never finished every 20ms
+  const frame = Buffer.from([0x82, 0x80 | 100, 1, 2, 3, 4, ...new Array(100).fill(0)]);
+  let sent = 0;
+  const trickle = setInterval(() => client.write(frame.subarray(sent, ++sent)), 20);
+
+  t.is((await client.next()).opcode, 0x9, "The partial frame doesn't stop the ping");
+  const close = await client.next();
+  clearInterval(trickle);
+  t.is(close.opcode, 0x8);
+  t.is(close.payload.readUInt16BE(0), 1001);
+  t.is(close.payload.subarray(2).toString(), "Idle timeout");
+  client.end();
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("websocket topics", async (t) => {
+  const topics_server = new AouServer();
+  const topics_port = 7082;
+
+  topics_server.ws("/room/{name}", {
+    open(ws, req) {
+      ws.subscribe(req.params.name);
+      ws.subscribe("all");
+      ws.send(ws.topics.sort().join(","));
+    },
+    message(ws, data) {
+      if (data === "leave") {
+        ws.send(`${ws.unsubscribe("all")} ${ws.isSubscribed("all")}`);
+      } else {
+        ws.send(`published to ${ws.publish("all", data)}`);
+      }
+    },
+  });
+
+  const instance = await topics_server.listen(addr, topics_port);
+
+  const red = await websocket(topics_port, "/room/red");
+  const blue = await websocket(topics_port, "/room/blue");
+  t.is((await red.next()).payload.toString(), "all,red");
+  t.is((await blue.next()).payload.toString(), "all,blue");
+  t.is(topics_server.subscribers("all"), 2);
+
+  t.is(topics_server.publish("red", "only red"), 1);
+  t.is((await red.next()).payload.toString(), "only red");
+  t.is(topics_server.publish("all", Buffer.from([1, 2])), 2);
+  t.deepEqual(await red.next(), { opcode: 0x2, payload: Buffer.from([1, 2]) });
+  t.deepEqual(await blue.next(), { opcode: 0x2, payload: Buffer.from([1, 2]) });
+  t.is(topics_server.publish("green", "nobody"), 0);
+
+  red.send(0x1, "hi");
+  t.is((await blue.next()).payload.toString(), "hi");
+  t.is((await red.next()).payload.toString(), "published to 1");
+
+  blue.send(0x1, "leave");
+  t.is((await blue.next()).payload.toString(), "true false");
+  t.is(topics_server.subscribers("all"), 1);
+
+  red.end();
+  while (topics_server.subscribers("red") > 0) await new Promise((resolve) => setTimeout(resolve, 10));
+  t.is(topics_server.subscribers("all"), 0);
+  blue.end();
+
+  await instance.close({ timeout: 1000 });
+});
+
 test("404", async (t) => {
   const not_found_res = await fetch(`http://${addr}:${port}/invalid-route`);
 
@@ -57,6 +705,373 @@ test("404", async (t) => {
   t.is(not_found_res.statusText, "Not Found");
 });
 
+test("405 with allow header", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/route/f`, {
+    method: "DELETE",
+  });
+
+  t.is(res.status, 405);
+  t.is(res.headers.get("allow"), "GET, HEAD, OPTIONS");
+});
+
+test("501 for unknown methods", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/route/f`, {
+    method: "BREW",
+  });
+
+  t.is(res.status, 501);
+});
+
+test("automatic HEAD", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/route/f`, {
+    method: "HEAD",
+  });
+
+  t.is(res.status, 200);
+  t.not(res.headers.get("content-length"), "0");
+  t.is(await res.text(), "");
+});
+
+test("automatic OPTIONS", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/route/f`, {
+    method: "OPTIONS",
+  });
+
+  t.is(res.status, 204);
+  t.is(res.headers.get("allow"), "GET, HEAD, OPTIONS");
+  t.is(res.headers.get("content-length"), null);
+});
+
+test("streamed response", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/stream`);
+
+  t.is(res.status, 200);
+  t.is(res.headers.get("transfer-encoding"), "chunked");
+  t.is(await res.text(), "hello world");
+});
+
+test("streams are returned when they aren't read to the end", async (t) => {
+  const stream_server = new AouServer();
+  const stream_port = 7084;
+  const returned = new Set();
+
+  stream_server.get("/endless", async (req) => {
+    async function* chunks() {
+      while (true) {
+        yield "chunk ";
+        await new Promise((resolve) => setTimeout(resolve, 10));
+      }
+    }
+
+    const generator = chunks();
+    const iterator = {
+      next: () => generator.next(),
+      return(value) {
+        returned.add(req.query.id);
+        return generator.return(value);
+      },
+    };
+
+    return { body: null, stream: { [Symbol.asyncIterator]: () => iterator } };
+  });
+
+  stream_server.get("/throws", async (req) => {
+    async function* chunks() {
+      yield "partial";
+      throw new Error("stream failed");
+    }
+
+    return { body: null, stream: chunks() };
+  });
+
+  stream_server.get("/throws-sync", async (req) => {
+    const iterator = {
+      next() {
+        throw new Error("next failed");
+      },
+    };
+
+    return { body: null, stream: { [Symbol.asyncIterator]: () => iterator } };
+  });
+
+  const instance = await stream_server.listen(addr, stream_port);
+  const url = `http://${addr}:${stream_port}`;
+  const settle = () => new Promise((resolve) => setTimeout(resolve, 100));
+
+  const head = await fetch(`${url}/endless?id=head`, { method: "HEAD" });
+  t.is(head.status, 200);
+  await settle();
+  t.true(returned.has("head"), "HEAD responses return the stream");
+
+  const abort = new AbortController();
+  const aborted = await fetch(`${url}/endless?id=abort`, { signal: abort.signal });
+  await aborted.body.getReader().read();
+  abort.abort();
+  await settle();
+  t.true(returned.has("abort"), "Client disconnects return the stream");
+
+  const throws = await fetch(`${url}/throws`);
+  t.is(throws.status, 200);
+  t.is(await throws.text(), "partial");
+
+  const throws_sync = await fetch(`${url}/throws-sync`);
+  t.is(throws_sync.status, 200);
+  t.is(await throws_sync.text(), "");
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("instance.close waits for in-flight requests", async (t) => {
+  const closing_server = new AouServer();
+  const closing_port = 7071;
+
+  closing_server.get("/slow", async (req) => {
+    await new Promise((resolve) => setTimeout(resolve, 100));
+    return {
+      body: "done",
+    };
+  });
+
+  closing_server.post("/echo", async (req) => {
+    return {
+      body: req.body,
+    };
+  });
+
+  const instance = await closing_server.listen(addr, closing_port);
+
+  const in_flight = fetch(`http://${addr}:${closing_port}/slow`);
+
+  // A request that is only partly received when the server starts closing
+  const socket = connect(closing_port, addr);
+  let received = "";
+  socket.on("data", (data) => (received += data));
+  const socket_closed = new Promise((resolve) => socket.on("close", resolve));
+  socket.write(`POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nhello`);
+  await new Promise((resolve) => setTimeout(resolve, 20));
+
+  const closing = instance.close({ timeout: 1000 });
+  await new Promise((resolve) => setTimeout(resolve, 20));
+  socket.write("world");
+  await closing;
+
+  const res = await in_flight;
+  t.is(await res.text(), "done");
+  t.is(res.headers.get("connection"), "close");
+
+  await socket_closed;
+  t.regex(received, /^HTTP\/1\.1 200 OK\r\n/);
+  t.regex(received, /\r\nConnection: close\r\n/);
+  t.true(received.endsWith("\r\n\r\nhelloworld"));
+
+  await t.throwsAsync(fetch(`http://${addr}:${closing_port}/slow`));
+});
+
+test("handler timeout answers 504", async (t) => {
+  const timeout_server = new AouServer({ handlerTimeout: 50 });
+  const timeout_port = 7072;
+
+  timeout_server.get("/slow", async (req) => {
+    await new Promise((resolve) => setTimeout(resolve, 200));
+    return {
+      body: "late",
+    };
+  });
+
+  const instance = await timeout_server.listen(addr, timeout_port);
+
+  const res = await fetch(`http://${addr}:${timeout_port}/slow`);
+  t.is(res.status, 504);
+  t.is(res.headers.get("connection"), "close");
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("body size limits with route overrides", async (t) => {
+  const limited_server = new AouServer({ maxBodySize: 8 });
+  const limited_port = 7073;
+
+  limited_server.post(
+    "/upload",
+    async (req) => {
+      return {
+        body: req.body,
+      };
+    },
+    { maxBodySize: 64 },
+  );
+  limited_server.post("/small", async (req) => {
+    return {
+      body: req.body,
+    };
+  });
+
+  const instance = await limited_server.listen(addr, limited_port);
+
+  const upload = await fetch(`http://${addr}:${limited_port}/upload`, {
+    method: "POST",
+    body: "hello world",
+  });
+  t.is(await upload.text(), "hello world");
+
+  const small = await fetch(`http://${addr}:${limited_port}/small`, {
+    method: "POST",
+    body: "hello world",
+  });
+  t.is(small.status, 413);
+
+  await instance.close({ timeout: 1000 });
+});
+
+test("binary request bodies", async (t) => {
+  const bytes = new Uint8Array([0x89, 0x50, 0x4e, 0x47, 0xff, 0x00]);
+
+  const res = await fetch(`http://${addr}:${port}/binary`, {
+    method: "POST",
+    body: bytes,
+  });
+
+  t.deepEqual(await res.json(), {
+    buffer: [...bytes],
+    byteLength: bytes.length,
+    invalidText: true,
+  });
+
+  const empty = await fetch(`http://${addr}:${port}/binary`, { method: "POST" });
+  t.deepEqual(await empty.json(), { buffer: [], byteLength: 0, invalidText: false });
+});
+
+test("req.json parses JSON bodies", async (t) => {
+  const json = (body, contentType) =>
+    fetch(`http://${addr}:${port}/json`, {
+      method: "POST",
+      headers: { "content-type": contentType },
+      body,
+    });
+
+  const ok = await json(`{"valid":true}`, "application/json; charset=utf-8");
+  t.deepEqual(await ok.json(), { value: { valid: true } });
+
+  const malformed = await json(`{"valid":`, "application/json");
+  t.is(malformed.status, 400);
+
+  const wrong_type = await json(`{"valid":true}`, "text/plain");
+  t.is(wrong_type.status, 415);
+});
+
+test("gzip request bodies are decompressed", async (t) => {
+  const gzip = (body, encoding) =>
+    fetch(`http://${addr}:${port}/json`, {
+      method: "POST",
+      headers: { "content-type": "application/json", "content-encoding": encoding },
+      body,
+    });
+
+  const ok = await gzip(gzipSync(`{"compressed":true}`), "gzip");
+  t.deepEqual(await ok.json(), { value: { compressed: true } });
+
+  const unsupported = await gzip(`{"compressed":false}`, "zstd");
+  t.is(unsupported.status, 415);
+});
+
+test("req.form decodes urlencoded bodies", async (t) => {
+  const res = await fetch(`http://${addr}:${port}/form`, {
+    method: "POST",
+    body: new URLSearchParams([
+      ["name", "two words"],
+      ["tag", "a"],
+      ["tag", "b&c"],
+    ]),
+  });
+
+  t.deepEqual(await res.json(), { name: "two words", tag: ["a", "b&c"] });
+
+  const wrong_type = await fetch(`http://${addr}:${port}/form`, {
+    method: "POST",
+    headers: { "content-type": "text/plain" },
+    body: "name=aou",
+  });
+  t.is(wrong_type.status, 415);
+});
+
+test("req.multipart parses fields and files", async (t) => {
+  const form = new FormData();
+  form.append("title", "report");
+  form.append("tag", "a");
+  form.append("tag", "b");
+  form.append("upload", new Blob(["file contents"], { type: "text/plain" }), "notes.txt");
+
+  const res = await fetch(`http://${addr}:${port}/multipart`, {
+    method: "POST",
+    body: form,
+  });
+
+  t.deepEqual(await res.json(), {
+    fields: { title: "report", tag: ["a", "b"] },
+    files: [
+      {
+        name: "upload",
+        filename: "notes.txt",
+        contentType: "text/plain",
+        size: 13,
+        text: "file contents",
+      },
+    ],
+  });
+
+  const wrong_type = await fetch(`http://${addr}:${port}/multipart`, {
+    method: "POST",
+    headers: { "content-type": "application/json" },
+    body: "{}",
+  });
+  t.is(wrong_type.status, 415);
+});
+
+test("large multipart uploads stream to temp files", async (t) => {
+  const upload_server = new AouServer({ multipartMemoryLimit: 1024 });
+  const upload_port = 7083;
+  const contents = "x".repeat(64 * 1024);
+  let path;
+
+  upload_server.post("/upload", async (req) => {
+    const { fields, files } = await req.multipart();
+    const [file] = files;
+    path = file.path;
+
+    return {
+      body: {
+        title: fields.title,
+        inMemory: file.buffer != null,
+        size: file.size,
+        complete: (await readFile(file.path)).toString() === contents,
+        buffered: req.body.length,
+      },
+    };
+  });
+
+  const instance = await upload_server.listen(addr, upload_port);
+
+  const form = new FormData();
+  form.append("title", "large");
+  form.append("upload", new Blob([contents], { type: "text/plain" }), "large.txt");
+  const res = await fetch(`http://${addr}:${upload_port}/upload`, { method: "POST", body: form });
+
+  t.deepEqual(await res.json(), {
+    title: "large",
+    inMemory: false,
+    size: contents.length,
+    complete: true,
+    buffered: 0,
+  });
+
+  for (let i = 0; i < 20 && existsSync(path); i++) {
+    await new Promise((resolve) => setTimeout(resolve, 10));
+  }
+  t.false(existsSync(path), "Temp files are removed once the response is sent");
+
+  await instance.close({ timeout: 1000 });
+});
+
 test("request parsing", async (t) => {
   const request = AouRequest.fromString(
     `GET / HTTP/1.1\r\nHost: localhost:7070\r\n\r\n`
@@ -66,4 +1081,20 @@ test("request parsing", async (t) => {
   t.is(request.path, "/");
 });
 
+test("repeated headers and case-insensitive lookups", async (t) => {
+  const request = AouRequest.fromString(
+    `GET / HTTP/1.1\r\nHost: localhost:7070\r\nX-Forwarded-For: 10.0.0.1\r\nCookie: a=1\r\nx-forwarded-for: 10.0.0.2\r\ncookie: b=2\r\n\r\n`
+  );
+
+  t.is(request.header("X-FORWARDED-FOR"), "10.0.0.1, 10.0.0.2");
+  t.deepEqual(request.headerAll("x-forwarded-for"), ["10.0.0.1", "10.0.0.2"]);
+  t.is(request.header("cookie"), "a=1; b=2");
+  t.is(request.header("x-missing"), null);
+  t.deepEqual(request.headers, {
+    host: "localhost:7070",
+    "x-forwarded-for": "10.0.0.1, 10.0.0.2",
+    cookie: "a=1; b=2",
+  });
+});
+
 registerCompletionHandler(() => process.exit(0));
@@ -21,46 +21,65 @@ export type AouHandler<TParams> = (
 export declare interface AouServer {
   get<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   head<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   post<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   put<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   delete<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   connect<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   options<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   trace<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   patch<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   all<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
 }
 
+export type AouWebSocketHandlers = {
+  /** Called once the handshake is done, before any message is received */
+  open?: (ws: AouWebSocket, req: AouRequest) => void | Promise<void>;
+  /** Text messages are strings and binary ones Buffers, the next message waits for the returned promise */
+  message?: (ws: AouWebSocket, data: string | Buffer) => void | Promise<void>;
+  /** Called once when the socket is closed, `1006` if the connection was lost without a close frame */
+  close?: (ws: AouWebSocket, code: number, reason: string) => void | Promise<void>;
+};
+
 export declare class AouError {
   constructor(error: AouResponse);
 }
@@ -6,48 +6,324 @@
 export interface AouError {
   status?: number;
   statusMessage?: Record<string, string>;
-  headers?: Record<string, string>;
+  headers?: Record<string, string | string[]>;
   body: any;
 }
 export interface AouResponse {
   status?: number;
   statusMessage?: Record<string, string>;
-  headers?: Record<string, string>;
+  /** Arrays are written as one header line per value, e.g. for multiple `Set-Cookie`s */
+  headers?: Record<string, string | string[]>;
+  /** Sent as one `Set-Cookie` header each, after the ones in `headers` */
+  cookies?: Array<AouCookie>;
   body: any | null;
+  /** Only the requested part is sent when a GET request has a `Range` header and `status` is 200 */
   buffer?: Buffer;
+  stream?: AsyncIterable<Buffer | string> | ReadableStream<Uint8Array>;
+}
+/** A cookie set on the client with a `Set-Cookie` header. */
+export interface AouCookie {
+  name: string;
+  /** Percent-encoded when it contains characters not allowed in cookies */
+  value: string;
+  domain?: string;
+  path?: string;
+  expires?: Date | number;
+  /** Seconds until the cookie expires, `0` or less deletes it */
+  maxAge?: number;
+  secure?: boolean;
+  httpOnly?: boolean;
+  sameSite?: 'Strict' | 'Lax' | 'None';
+}
+export interface AouStreamChunk {
+  done?: boolean;
+  value?: string | Buffer;
 }
 export interface AouOptions {
   tracing?: boolean;
+  /** Answer HEAD requests with the GET handler of the route. Defaults to `true` */
+  autoHead?: boolean;
+  /** Answer OPTIONS requests with the methods registered on the route. Defaults to `true` */
+  autoOptions?: boolean;
+  /** Parse `a[b]=c` query keys into nested objects and `a[]=c` into arrays. Defaults to `false` */
+  nestedQuery?: boolean;
+  /** What to do with paths that only match a route once their trailing slash is added or removed. Defaults to `"strict"` */
+  trailingSlash?: 'strict' | 'redirect' | 'lenient';
+  /** Cookie sessions available as `req.session` */
+  session?: AouSessionOptions;
+  /**
+   * Compress `body` and `buffer` responses with the best encoding the client accepts in `Accept-Encoding`,
+   * streamed responses are sent as is. Defaults to `false`
+   */
+  compression?: boolean;
+  /** Bytes a body needs before it is compressed. Defaults to `1024` */
+  compressionMinSize?: number;
+  /**
+   * `Content-Type`s that are compressed, `type/*` matches every subtype.
+   * Defaults to text, JSON, JavaScript, XML, WebAssembly and SVG
+   */
+  compressionTypes?: Array<string>;
+  /**
+   * Add an `ETag` hashed from the body to `body` and `buffer` responses that don't set one, and answer
+   * `GET` and `HEAD` requests whose `If-None-Match` or `If-Modified-Since` match the response with a 304.
+   * Disabled by default
+   */
+  etag?: 'strong' | 'weak';
+  /** Milliseconds an idle keep-alive connection is kept open waiting for the next request. Defaults to `5000` */
+  keepAliveTimeout?: number;
+  /** Milliseconds allowed to receive the request head and headers, answered with a 408 when exceeded. Defaults to `10000` */
+  headerTimeout?: number;
+  /** Milliseconds allowed to receive the whole request body, counted from the end of the headers and answered with a 408 when exceeded. Defaults to `30000` */
+  bodyTimeout?: number;
+  /**
+   * Milliseconds a handler has to respond, answered with a 503 if it couldn't be called in time
+   * or a 504 if it didn't resolve in time. Disabled by default
+   */
+  handlerTimeout?: number;
+  /** Longest request target accepted, answered with a 414 when exceeded. Defaults to `8192` */
+  maxUriLength?: number;
+  /** Bytes allowed for the request headers, answered with a 431 when exceeded. Defaults to `65536` */
+  maxHeaderBytes?: number;
+  /** Header fields allowed in a request, answered with a 431 when exceeded. Defaults to `100` */
+  maxHeaderCount?: number;
+  /** Bytes allowed for the request body, answered with a 413 when exceeded. Defaults to `16777216` */
+  maxBodySize?: number;
+  /**
+   * Bytes allowed for a gzip or deflate `Content-Encoding` body once decompressed, answered with a 413 when exceeded.
+   * Other encodings are answered with a 415. Defaults to `16777216`
+   */
+  maxDecompressedSize?: number;
+  /** Bytes allowed for a single multipart part, `req.multipart()` rejects with a 413 when exceeded. Defaults to `16777216` */
+  multipartMaxPartSize?: number;
+  /** Bytes allowed for all the multipart parts together, `req.multipart()` rejects with a 413 when exceeded. Defaults to `16777216` */
+  multipartMaxTotalSize?: number;
+  /** Uploaded files larger than this are written to a temp file instead of kept in memory. Defaults to `1048576` */
+  multipartMemoryLimit?: number;
+}
+/** Options of a single route, the limits override the ones set in `AouOptions`. */
+export interface AouRouteOptions {
+  maxHeaderBytes?: number;
+  maxHeaderCount?: number;
+  maxBodySize?: number;
+  maxDecompressedSize?: number;
+  compression?: boolean;
+  compressionMinSize?: number;
+  /** Turns `etag` on or off for the route, strong ETags are used when the server has no `etag` mode */
+  etag?: boolean;
+}
+export interface AouMultipartFile {
+  /** Name of the form field */
+  name: string;
+  filename: string;
+  contentType?: string;
+  size: number;
+  /** Contents of files kept in memory */
+  buffer?: Buffer;
+  /** Temp file of files larger than `multipartMemoryLimit`, removed once the response is sent */
+  path?: string;
+}
+export interface AouMultipart {
+  fields: Record<string, string | string[]>;
+  files: Array<AouMultipartFile>;
+}
+/** Cookie sessions, configured with `AouOptions.session`. */
+export interface AouSessionOptions {
+  /**
+   * Keys the session cookie is signed or encrypted with, at least 32 bytes of UTF-8 each.
+   * The first one is used for new cookies and the others are still accepted, so keys can be rotated
+   */
+  secrets: Array<string>;
+  /** Encrypt the session with AES-256-GCM so the client can't read it, instead of only signing it with HMAC-SHA256. Defaults to `false` */
+  encrypt?: boolean;
+  /** Defaults to `"aou.session"` */
+  cookieName?: string;
+  /** Seconds a session lasts after its last change, also enforced inside the cookie. Without it the cookie is dropped when the browser closes */
+  maxAge?: number;
+  domain?: string;
+  /** Defaults to `"/"` */
+  path?: string;
+  /** Defaults to `false` */
+  secure?: boolean;
+  /** Defaults to `true` */
+  httpOnly?: boolean;
+  /** Defaults to `"Lax"` */
+  sameSite?: 'Strict' | 'Lax' | 'None';
+}
+/** Options of `server.static()`. */
+export interface AouStaticOptions {
+  /** File served for requests to a directory. Defaults to `"index.html"` */
+  index?: string;
+  /** File served instead of a 404 for paths that don't match a file, e.g. `"index.html"` for single page apps */
+  fallback?: string;
+  /** Serve files and directories whose name starts with a `.`. Defaults to `false` */
+  dotfiles?: boolean;
+  /** Seconds sent in `Cache-Control: public, max-age=<seconds>`, no `Cache-Control` is sent by default */
+  maxAge?: number;
+}
+export interface AouCloseOptions {
+  /** Milliseconds to wait for in-flight requests before force closing the remaining connections */
+  timeout?: number;
+}
+/** Options of `server.ws()`. */
+export interface AouWebSocketOptions {
+  /**
+   * Bytes allowed for a message once its fragments are joined, the socket is closed with a 1009 when exceeded.
+   * Defaults to `16777216`
+   */
+  maxPayloadLength?: number;
+  /**
+   * Milliseconds without any frame from the client before the socket is closed with a 1001,
+   * a ping is sent halfway through. Defaults to `120000`, `0` disables it
+   */
+  idleTimeout?: number;
+  /** Subprotocols the server speaks, the first one the client offers in `Sec-WebSocket-Protocol` is picked */
+  protocols?: Array<string>;
+  /**
+   * Bytes waiting to be written to a socket before it is a slow consumer, the messages sent
+   * or published to it are then handled by `backpressure`. Defaults to `1048576`, `0` disables it
+   */
+  maxBackpressure?: number;
+  /**
+   * `"close"` closes slow consumers with a 1008 and skips their queued messages,
+   * `"drop"` drops the messages sent to them until they catch up. Defaults to `"close"`
+   */
+  backpressure?: 'close' | 'drop';
 }
 export type Request = AouRequest;
 export declare class AouRequest {
   context: any;
   params: {};
-  query: Record<string, string>;
+  /** The decoded query string, keys repeated in it become arrays */
+  query: Record<string, any>;
   static fromString(request: string): Request;
   get method(): string;
+  /**
+   * The percent-decoded path with its dot segments removed, the one routes are matched against.
+   * `%2F` and `%25` are kept encoded so it splits on `/` like the raw path
+   */
   get path(): string;
+  /** The path as it was received, without the query string */
+  get rawPath(): string;
+  /** The query string after the `?`, still percent-encoded */
+  get rawQuery(): string;
   get httpVersion(): string;
+  /**
+   * Every header keyed by its lowercased name. Repeated headers are combined into one value
+   * separated by `, `, or by `; ` for `Cookie`
+   */
   get headers(): Record<string, string>;
+  /** Cookies sent in the `Cookie` headers, with their values percent-decoded */
+  get cookies(): Record<string, string>;
+  /** The cookie session, `null` unless `AouOptions.session` is set */
+  get session(): AouSession | null;
+  /**
+   * Value of the header named `name`, compared case-insensitively.
+   * Repeated headers are combined like in `headers`
+   */
+  header(name: string): string | null;
+  /** Every value of the header named `name`, compared case-insensitively, in the order they were received */
+  headerAll(name: string): Array<string>;
+  /** The body decoded as UTF-8 text, throws if it isn't valid UTF-8 */
   get body(): string;
+  /** A copy of the raw body bytes as a `Buffer`, writing to it doesn't change the request */
+  buffer(): Buffer;
+  /** A copy of the raw body bytes as an `ArrayBuffer`, writing to it doesn't change the request */
+  arrayBuffer(): ArrayBuffer;
+  /**
+   * Parses the body as JSON off the JS thread.
+   * Rejects with a 415 `AouError` if the `Content-Type` isn't JSON or a 400 one if the body is malformed
+   */
+  json(): Promise<any>;
+  /**
+   * Decodes an `application/x-www-form-urlencoded` body off the JS thread, repeated keys become arrays.
+   * Rejects with a 415 `AouError` if the `Content-Type` isn't a urlencoded form
+   */
+  form(): Promise<Record<string, string | string[]>>;
+  /**
+   * Parses a `multipart/form-data` body off the JS thread into its text fields and files.
+   * Rejects with a 415 `AouError` if the `Content-Type` isn't multipart, a 400 if the body is malformed
+   * or a 413 if a part is over the multipart limits
+   */
+  multipart(): Promise<AouMultipart>;
+}
+/** `req.session`, shared with the server so changes made by the handler are written back to the cookie. */
+export declare class AouSession {
+  get(key: string): any;
+  set(key: string, value: any): void;
+  /** Returns `false` if there was no value at `key` */
+  delete(key: string): boolean;
+  /** Removes every value and expires the session cookie */
+  destroy(): void;
+  /** A copy of every value in the session */
+  get data(): Record<string, any>;
 }
 export declare class AouInstance {
   ip: string;
   port: number;
+  /** Stops accepting connections, closes idle connections and resolves once every in-flight request is done. */
+  close(options?: AouCloseOptions | undefined | null): Promise<void>;
 }
 export declare class AouServer {
   constructor(options?: AouOptions | undefined | null);
   listen(host: string, port: number): Promise<AouInstance>;
-  get(route: void, handler: void): void;
-  head(route: void, handler: void): void;
-  post(route: void, handler: void): void;
-  put(route: void, handler: void): void;
-  delete(route: void, handler: void): void;
-  connect(route: void, handler: void): void;
-  options(route: void, handler: void): void;
-  trace(route: void, handler: void): void;
-  patch(route: void, handler: void): void;
-  all(route: void, handler: void): void;
+  get(route: void, handler: void, options?: AouRouteOptions): void;
+  head(route: void, handler: void, options?: AouRouteOptions): void;
+  post(route: void, handler: void, options?: AouRouteOptions): void;
+  put(route: void, handler: void, options?: AouRouteOptions): void;
+  delete(route: void, handler: void, options?: AouRouteOptions): void;
+  connect(route: void, handler: void, options?: AouRouteOptions): void;
+  options(route: void, handler: void, options?: AouRouteOptions): void;
+  trace(route: void, handler: void, options?: AouRouteOptions): void;
+  patch(route: void, handler: void, options?: AouRouteOptions): void;
+  all(route: void, handler: void, options?: AouRouteOptions): void;
+  /**
+   * Serves the files in `dir` on GET requests to `route`, its last parameter is the path of the file.
+   * Files are streamed to the socket without going through JS
+   */
+  static(route: string, dir: string, options?: AouStaticOptions | undefined | null): void;
+  /**
+   * Upgrades GET requests to `route` to WebSockets, frames are read and written by the server
+   * and `handlers` are called with the messages
+   */
+  ws(route: string, handlers: AouWebSocketHandlers, options?: AouWebSocketOptions): void;
+  /**
+   * Sends a message to every socket subscribed to `topic`, from any `server.ws()` route.
+   * Returns the number of sockets it was queued on, slow consumers are left out by their backpressure policy
+   */
+  publish(topic: string, data: string | Buffer): number;
+  /** Number of sockets subscribed to `topic` */
+  subscribers(topic: string): number;
+}
+/** A WebSocket connection, every handler of the connection gets the same object. */
+export declare class AouWebSocket {
+  /**
+   * Sends a string as a text message and a Buffer as a binary one.
+   * Returns `false` once the socket is closing or when the backpressure policy dropped the message
+   */
+  send(data: string | Buffer): boolean;
+  /** Subscribes the socket to `topic` until it is closed. Returns `false` once the socket is closing */
+  subscribe(topic: string): boolean;
+  /** Returns `false` if the socket wasn't subscribed to `topic` */
+  unsubscribe(topic: string): boolean;
+  isSubscribed(topic: string): boolean;
+  /** Topics the socket is subscribed to */
+  get topics(): Array<string>;
+  /**
+   * Sends the message to every other subscriber of `topic`, like `server.publish()`.
+   * Returns the number of sockets it was queued on
+   */
+  publish(topic: string, data: string | Buffer): number;
+  /**
+   * Starts the closing handshake, `code` defaults to `1000`.
+   * The `close` handler runs once the client answered or the connection is gone
+   */
+  close(code?: number | undefined | null, reason?: string | undefined | null): void;
+  /** Bytes passed to `send()` that weren't written to the connection yet */
+  get bufferedAmount(): number;
+  /** `true` once `close()` was called, the client started closing or the connection was lost */
+  get closing(): boolean;
+  /** Subprotocol picked from the client's `Sec-WebSocket-Protocol` */
+  get protocol(): string | null;
 }
 //FROM - extend.d.ts
 
@@ -72,46 +348,65 @@ export type AouHandler<TParams> = (
 export declare interface AouServer {
   get<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   head<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   post<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   put<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   delete<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   connect<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   options<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   trace<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   patch<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
   all<TRoute extends string, TParams extends ParamsFromRoute<TRoute>>(
     route: TRoute,
-    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>
+    handler: (req: AouRequest & { params: TParams }) => Promise<AouResponse>,
+    options?: AouRouteOptions
   ): void;
 }
 
+export type AouWebSocketHandlers = {
+  /** Called once the handshake is done, before any message is received */
+  open?: (ws: AouWebSocket, req: AouRequest) => void | Promise<void>;
+  /** Text messages are strings and binary ones Buffers, the next message waits for the returned promise */
+  message?: (ws: AouWebSocket, data: string | Buffer) => void | Promise<void>;
+  /** Called once when the socket is closed, `1006` if the connection was lost without a close frame */
+  close?: (ws: AouWebSocket, code: number, reason: string) => void | Promise<void>;
+};
+
 export declare class AouError {
   constructor(error: AouResponse);
 }
@@ -310,11 +310,13 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { AouRequest, AouInstance, AouServer } = nativeBinding
+const { AouRequest, AouInstance, AouServer, AouSession, AouWebSocket } = nativeBinding
 
 module.exports.AouRequest = AouRequest
 module.exports.AouInstance = AouInstance
 module.exports.AouServer = AouServer
+module.exports.AouSession = AouSession
+module.exports.AouWebSocket = AouWebSocket
 //FROM -- ./extend.js
 
 module.exports.AouError = class AouError extends Error {
@@ -3,3 +3,68 @@ pub const CRLF_SIZE: usize = 2;
 
 pub const CR: u8 = b'\r';
 pub const LF: u8 = b'\n';
+
+pub const MAX_URI_LENGTH: usize = 8 * 1024;
+pub const MAX_HEADERS_SIZE: usize = 64 * 1024;
+pub const MAX_HEADER_COUNT: usize = 100;
+pub const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+pub const MULTIPART_MEMORY_LIMIT: usize = 1024 * 1024;
+pub const MULTIPART_READ_SIZE: usize = 64 * 1024;
+/// Fields dropped from chunked trailers, they decide framing, routing, authentication or how the body
+/// is processed, which is settled before the trailers arrive (RFC 9110 §6.5.1)
+pub const FORBIDDEN_TRAILERS: [&str; 22] = [
+  "content-length",
+  "transfer-encoding",
+  "content-encoding",
+  "content-type",
+  "content-range",
+  "trailer",
+  "te",
+  "connection",
+  "keep-alive",
+  "upgrade",
+  "host",
+  "authorization",
+  "proxy-authorization",
+  "cookie",
+  "expect",
+  "max-forwards",
+  "cache-control",
+  "range",
+  "if-match",
+  "if-none-match",
+  "if-modified-since",
+  "if-unmodified-since",
+];
+
+pub const KEEP_ALIVE_TIMEOUT_MS: u64 = 5_000;
+pub const HEADER_TIMEOUT_MS: u64 = 10_000;
+pub const BODY_TIMEOUT_MS: u64 = 30_000;
+
+/// Ranges accepted in a `Range` header, requests with more get the whole body
+pub const MAX_BYTE_RANGES: usize = 32;
+
+pub const COMPRESSION_MIN_SIZE: usize = 1024;
+/// Bodies at least this large are compressed on the blocking pool instead of the connection's worker
+pub const COMPRESSION_BLOCKING_SIZE: usize = 64 * 1024;
+/// Media types compressed unless `AouOptions.compressionTypes` is set, already compressed formats are left out
+pub const COMPRESSION_TYPES: [&str; 6] = [
+  "text/*",
+  "application/json",
+  "application/javascript",
+  "application/xml",
+  "application/wasm",
+  "image/svg+xml",
+];
+
+pub const WEBSOCKET_MAX_PAYLOAD_LENGTH: usize = 16 * 1024 * 1024;
+pub const WEBSOCKET_IDLE_TIMEOUT_MS: u64 = 120_000;
+/// Bytes waiting to be written to a socket before it is a slow consumer
+pub const WEBSOCKET_MAX_BACKPRESSURE: usize = 1024 * 1024;
+/// Time the other side gets to answer a close frame before the connection is dropped
+pub const WEBSOCKET_CLOSE_TIMEOUT_MS: u64 = 5_000;
+
+pub const SESSION_COOKIE_NAME: &str = "aou.session";
+pub const MIN_SESSION_SECRET_LENGTH: usize = 32;
+/// Browsers drop cookies over 4096 bytes, counting the name and the value
+pub const MAX_COOKIE_SIZE: usize = 4096;
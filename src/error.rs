@@ -1,16 +1,16 @@
-use std::collections::HashMap;
+use napi::{Env, JsObject};
+use serde::{Deserialize, Serialize};
 
-use serde::Deserialize;
-
-use crate::response::Response;
+use crate::response::{Response, ResponseHeaders};
 
 #[napi(object, js_name = "AouError")]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct AouError {
   pub status: Option<u32>,
   #[napi(ts_type = "Record<string,string>")]
   pub status_message: Option<String>,
-  pub headers: Option<HashMap<String, String>>,
+  #[napi(ts_type = "Record<string, string | string[]>")]
+  pub headers: Option<ResponseHeaders>,
   pub body: serde_json::Value, //TODO: Make this something else
 }
 
@@ -25,3 +25,15 @@ impl Into<Response> for AouError {
     }
   }
 }
+
+impl AouError {
+  /// Builds a JS `AouError`, a handler rejecting with it is answered like if it threw it.
+  pub fn into_js_error(self, env: &Env) -> napi::Result<napi::Error> {
+    let message = serde_json::to_string(&self)?;
+
+    let mut error: JsObject = env.create_error(napi::Error::from_reason(message))?;
+    error.set_named_property("name", env.create_string("AouError")?)?;
+
+    Ok(napi::Error::from(error.into_unknown()))
+  }
+}
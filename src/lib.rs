@@ -8,4 +8,7 @@ pub mod request;
 pub mod response;
 pub mod route;
 pub mod server;
+pub mod session;
+pub mod static_files;
 pub mod utils;
+pub mod websocket;
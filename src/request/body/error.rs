@@ -0,0 +1,44 @@
+use crate::error::AouError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum BodyParseError {
+  #[error("Expected a {0} body")]
+  UnsupportedMediaType(&'static str),
+  #[error("Invalid JSON body: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("Invalid multipart body: {0}")]
+  Multipart(&'static str),
+  #[error("Request body too large")]
+  PayloadTooLarge,
+  #[error("Failed to write upload {0}")]
+  Io(#[from] std::io::Error),
+}
+
+impl BodyParseError {
+  /// Status code of the `AouError` the body accessor rejects with
+  pub fn status(&self) -> u32 {
+    match self {
+      BodyParseError::UnsupportedMediaType(_) => 415,
+      BodyParseError::Json(_) | BodyParseError::Multipart(_) => 400,
+      BodyParseError::PayloadTooLarge => 413,
+      BodyParseError::Io(_) => 500,
+    }
+  }
+}
+
+impl From<BodyParseError> for AouError {
+  fn from(err: BodyParseError) -> Self {
+    AouError::from(&err)
+  }
+}
+
+impl From<&BodyParseError> for AouError {
+  fn from(err: &BodyParseError) -> Self {
+    AouError {
+      status: Some(err.status()),
+      status_message: None,
+      headers: None,
+      body: serde_json::Value::String(err.to_string()),
+    }
+  }
+}
@@ -0,0 +1,50 @@
+use serde_json::{Map, Value};
+
+use crate::utils::urlencoded;
+
+use super::{media_type, BodyParseError};
+
+pub struct FormBody;
+impl FormBody {
+  /// Decodes an `application/x-www-form-urlencoded` body with the same rules as the query string.
+  pub fn parse(
+    content_type: Option<&[u8]>,
+    body: &[u8],
+  ) -> Result<Map<String, Value>, BodyParseError> {
+    if !content_type.is_some_and(Self::is_form) {
+      return Err(BodyParseError::UnsupportedMediaType(
+        "application/x-www-form-urlencoded",
+      ));
+    }
+
+    Ok(urlencoded::decode_object(body))
+  }
+
+  pub fn is_form(content_type: &[u8]) -> bool {
+    media_type(content_type).eq_ignore_ascii_case(b"application/x-www-form-urlencoded")
+  }
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use serde_json::{json, Value};
+
+  use crate::request::FormBody;
+
+  #[test]
+  fn parse_form_body() {
+    let form = FormBody::parse(
+      Some(b"application/x-www-form-urlencoded; charset=UTF-8"),
+      b"name=two+words&tag=a&tag=b%26c",
+    )
+    .unwrap();
+
+    assert_eq!(
+      Value::Object(form),
+      json!({ "name": "two words", "tag": ["a", "b&c"] })
+    );
+
+    let err = FormBody::parse(Some(b"application/json"), b"a=1").unwrap_err();
+    assert_eq!(err.status(), 415);
+  }
+}
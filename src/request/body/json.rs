@@ -0,0 +1,61 @@
+use super::{media_type, BodyParseError};
+
+pub struct JsonBody;
+impl JsonBody {
+  /// Parses a JSON body, the `Content-Type` has to be `application/json` or a `+json` suffix.
+  pub fn parse(
+    content_type: Option<&[u8]>,
+    body: &[u8],
+  ) -> Result<serde_json::Value, BodyParseError> {
+    if !content_type.is_some_and(Self::is_json) {
+      return Err(BodyParseError::UnsupportedMediaType("application/json"));
+    }
+
+    Ok(serde_json::from_slice(body)?)
+  }
+
+  pub fn is_json(content_type: &[u8]) -> bool {
+    let media_type = media_type(content_type);
+
+    let Some(slash) = media_type.iter().position(|b| b == &b'/') else {
+      return false;
+    };
+    let (kind, subtype) = (&media_type[..slash], &media_type[slash + 1..]);
+
+    kind.eq_ignore_ascii_case(b"application")
+      && (subtype.eq_ignore_ascii_case(b"json")
+        || (subtype.len() > b"+json".len()
+          && subtype[subtype.len() - b"+json".len()..].eq_ignore_ascii_case(b"+json")))
+  }
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use crate::request::{BodyParseError, JsonBody};
+
+  #[test]
+  fn json_media_types() {
+    assert!(JsonBody::is_json(b"application/json"));
+    assert!(JsonBody::is_json(b"Application/JSON; charset=utf-8"));
+    assert!(JsonBody::is_json(b"application/problem+json"));
+    assert!(!JsonBody::is_json(b"text/plain"));
+    assert!(!JsonBody::is_json(b"text/json+xml"));
+  }
+
+  #[test]
+  fn parse_json_body() {
+    let value = JsonBody::parse(Some(b"application/json"), br#"{"valid":true}"#).unwrap();
+    assert_eq!(value, serde_json::json!({ "valid": true }));
+
+    let err = JsonBody::parse(Some(b"text/plain"), br#"{"valid":true}"#).unwrap_err();
+    assert!(matches!(err, BodyParseError::UnsupportedMediaType(_)));
+    assert_eq!(err.status(), 415);
+
+    let err = JsonBody::parse(None, br#"{"valid":true}"#).unwrap_err();
+    assert_eq!(err.status(), 415, "A missing Content-Type isn't JSON");
+
+    let err = JsonBody::parse(Some(b"application/json"), br#"{"valid":"#).unwrap_err();
+    assert!(matches!(err, BodyParseError::Json(_)));
+    assert_eq!(err.status(), 400);
+  }
+}
@@ -0,0 +1,18 @@
+mod error;
+mod form;
+mod json;
+mod multipart;
+
+pub use error::*;
+pub use form::*;
+pub use json::*;
+pub use multipart::*;
+
+/// The media type of a `Content-Type` header, without its parameters
+pub fn media_type(content_type: &[u8]) -> &[u8] {
+  content_type
+    .split(|b| b == &b';')
+    .next()
+    .unwrap_or_default()
+    .trim_ascii()
+}
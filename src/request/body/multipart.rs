@@ -0,0 +1,624 @@
+use std::{
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use napi::{Env, JsBuffer};
+use serde_json::{Map, Value};
+use tokio::{
+  fs::{File, OpenOptions},
+  io::AsyncWriteExt,
+};
+use tracing::error;
+
+use crate::{
+  constants::{CRLF, CRLF_SIZE, MAX_BODY_SIZE, MAX_HEADERS_SIZE, MULTIPART_MEMORY_LIMIT},
+  utils::urlencoded,
+};
+
+use super::{media_type, BodyParseError};
+
+/// Size limits of `multipart/form-data` bodies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultipartLimits {
+  /// Bytes allowed for a single part
+  pub max_part_size: usize,
+  /// Bytes allowed for all the parts together
+  pub max_total_size: usize,
+  /// Files larger than this are written to a temp file instead of kept in memory
+  pub memory_limit: usize,
+}
+
+impl Default for MultipartLimits {
+  fn default() -> Self {
+    Self {
+      max_part_size: MAX_BODY_SIZE,
+      max_total_size: MAX_BODY_SIZE,
+      memory_limit: MULTIPART_MEMORY_LIMIT,
+    }
+  }
+}
+
+/// Headers of a part
+#[derive(Debug)]
+pub struct MultipartPart {
+  pub name: String,
+  pub filename: Option<String>,
+  pub content_type: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FileData {
+  Memory(Bytes),
+  Disk(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct MultipartFile {
+  pub name: String,
+  pub filename: String,
+  pub content_type: Option<String>,
+  pub size: usize,
+  pub data: FileData,
+}
+
+/// Fields and files of a parsed `multipart/form-data` body
+#[derive(Debug)]
+pub struct Multipart {
+  pub fields: Map<String, Value>,
+  pub files: Vec<MultipartFile>,
+}
+
+#[napi(object, js_name = "AouMultipartFile")]
+pub struct UploadedFile {
+  /// Name of the form field
+  pub name: String,
+  pub filename: String,
+  pub content_type: Option<String>,
+  pub size: i64,
+  /// Contents of files kept in memory
+  pub buffer: Option<JsBuffer>,
+  /// Temp file of files larger than `multipartMemoryLimit`, removed once the response is sent
+  pub path: Option<String>,
+}
+
+#[napi(object, js_name = "AouMultipart")]
+pub struct MultipartForm {
+  #[napi(ts_type = "Record<string, string | string[]>")]
+  pub fields: Value,
+  pub files: Vec<UploadedFile>,
+}
+
+/// Parses a `multipart/form-data` body as it's received, a chunk at a time.
+/// A file is kept in memory until it's larger than `memory_limit`, from then on it's written to a temp file.
+pub struct MultipartStream {
+  delimiter: Vec<u8>,
+  part_delimiter: Vec<u8>,
+  limits: MultipartLimits,
+  temp_files: TempFiles,
+  /// Received bytes that can't be parsed yet, like a delimiter cut between two chunks
+  pending: Vec<u8>,
+  state: StreamState,
+  total_size: usize,
+  fields: Map<String, Value>,
+  files: Vec<MultipartFile>,
+}
+
+enum StreamState {
+  Preamble,
+  Delimiter,
+  Headers,
+  Data {
+    part: MultipartPart,
+    size: usize,
+    data: PartData,
+  },
+  Done,
+}
+
+enum PartData {
+  Memory(Vec<u8>),
+  Disk(PathBuf, Box<File>),
+}
+
+impl MultipartStream {
+  /// Rejects with a 415 if the `Content-Type` isn't multipart or has no boundary
+  pub fn new(
+    content_type: Option<&[u8]>,
+    limits: &MultipartLimits,
+    temp_files: TempFiles,
+  ) -> Result<Self, BodyParseError> {
+    let boundary = content_type
+      .filter(|content_type| media_type(content_type).eq_ignore_ascii_case(b"multipart/form-data"))
+      .and_then(MultipartBody::boundary)
+      .ok_or(BodyParseError::UnsupportedMediaType("multipart/form-data"))?;
+
+    let delimiter = [b"--", boundary.as_slice()].concat();
+    let part_delimiter = [CRLF, delimiter.as_slice()].concat();
+
+    Ok(MultipartStream {
+      delimiter,
+      part_delimiter,
+      limits: *limits,
+      temp_files,
+      pending: Vec::new(),
+      state: StreamState::Preamble,
+      total_size: 0,
+      fields: Map::new(),
+      files: Vec::new(),
+    })
+  }
+
+  pub fn temp_files(&self) -> &TempFiles {
+    &self.temp_files
+  }
+
+  /// Parses the next chunk of the body
+  pub async fn feed(&mut self, chunk: &[u8]) -> Result<(), BodyParseError> {
+    let mut buf = std::mem::take(&mut self.pending);
+    buf.extend_from_slice(chunk);
+
+    let parsed = self.parse(&buf).await?;
+    buf.drain(..parsed);
+    self.pending = buf;
+
+    Ok(())
+  }
+
+  /// The fields and files once the whole body was fed, a 400 if it ended before the closing delimiter
+  pub fn finish(self) -> Result<Multipart, BodyParseError> {
+    match self.state {
+      StreamState::Done => Ok(Multipart {
+        fields: self.fields,
+        files: self.files,
+      }),
+      StreamState::Preamble => Err(BodyParseError::Multipart("Missing boundary")),
+      StreamState::Headers => Err(BodyParseError::Multipart("Part headers are not terminated")),
+      StreamState::Delimiter | StreamState::Data { .. } => Err(BodyParseError::Multipart(
+        "Part is not terminated by a boundary",
+      )),
+    }
+  }
+
+  /// Returns how many bytes of `buf` were parsed, the rest is kept for the next chunk
+  async fn parse(&mut self, buf: &[u8]) -> Result<usize, BodyParseError> {
+    let mut cursor = 0;
+
+    loop {
+      match &self.state {
+        // The preamble before the first delimiter is ignored.
+        StreamState::Preamble => {
+          let Some(start) = find(buf, &self.delimiter, cursor) else {
+            return Ok(partial_match_start(buf, &self.delimiter, cursor));
+          };
+          cursor = start + self.delimiter.len();
+          self.state = StreamState::Delimiter;
+        }
+        StreamState::Delimiter => {
+          let rest = &buf[cursor..];
+          if rest.len() < CRLF_SIZE {
+            return Ok(cursor);
+          }
+          if rest.starts_with(b"--") {
+            self.state = StreamState::Done;
+            continue;
+          }
+          if !rest.starts_with(CRLF) {
+            return Err(BodyParseError::Multipart(
+              "Boundary is not followed by CRLF",
+            ));
+          }
+          cursor += CRLF_SIZE;
+          self.state = StreamState::Headers;
+        }
+        StreamState::Headers => {
+          let Some(headers_end) = find(buf, b"\r\n\r\n", cursor) else {
+            if buf.len() - cursor > MAX_HEADERS_SIZE {
+              return Err(BodyParseError::Multipart("Part headers are too large"));
+            }
+            return Ok(cursor);
+          };
+
+          self.state = StreamState::Data {
+            part: MultipartBody::part_headers(&buf[cursor..headers_end])?,
+            size: 0,
+            data: PartData::Memory(Vec::new()),
+          };
+          cursor = headers_end + CRLF_SIZE * 2;
+        }
+        StreamState::Data { .. } => {
+          let Some(data_end) = find(buf, &self.part_delimiter, cursor) else {
+            // The end of the chunk could be the start of the delimiter
+            let data_end = partial_match_start(buf, &self.part_delimiter, cursor);
+            self.write(&buf[cursor..data_end]).await?;
+            return Ok(data_end);
+          };
+
+          self.write(&buf[cursor..data_end]).await?;
+          self.collect().await?;
+          cursor = data_end + self.part_delimiter.len();
+        }
+        // The epilogue after the closing delimiter is ignored too.
+        StreamState::Done => return Ok(buf.len()),
+      }
+    }
+  }
+
+  async fn write(&mut self, chunk: &[u8]) -> Result<(), BodyParseError> {
+    let StreamState::Data { part, size, data } = &mut self.state else {
+      return Ok(());
+    };
+    if chunk.is_empty() {
+      return Ok(());
+    }
+
+    *size += chunk.len();
+    self.total_size += chunk.len();
+    if *size > self.limits.max_part_size || self.total_size > self.limits.max_total_size {
+      return Err(BodyParseError::PayloadTooLarge);
+    }
+
+    if let PartData::Memory(memory) = data {
+      if part.filename.is_some() && *size > self.limits.memory_limit {
+        let (path, mut file) = self.temp_files.create().await?;
+        file.write_all(memory).await?;
+        *data = PartData::Disk(path, Box::new(file));
+      }
+    }
+
+    match data {
+      PartData::Memory(memory) => memory.extend_from_slice(chunk),
+      PartData::Disk(_, file) => file.write_all(chunk).await?,
+    }
+
+    Ok(())
+  }
+
+  /// Adds the part that was just read to the fields or the files
+  async fn collect(&mut self) -> Result<(), BodyParseError> {
+    let StreamState::Data { part, size, data } =
+      std::mem::replace(&mut self.state, StreamState::Delimiter)
+    else {
+      return Ok(());
+    };
+
+    let data = match (part.filename.is_some(), data) {
+      (false, PartData::Memory(data)) => {
+        let value = String::from_utf8_lossy(&data).into_owned();
+        urlencoded::insert_repeated(&mut self.fields, part.name, value);
+        return Ok(());
+      }
+      (_, PartData::Memory(data)) => FileData::Memory(Bytes::from(data)),
+      (_, PartData::Disk(path, mut file)) => {
+        file.flush().await?;
+        FileData::Disk(path)
+      }
+    };
+
+    self.files.push(MultipartFile {
+      name: part.name,
+      filename: part.filename.unwrap_or_default(),
+      content_type: part.content_type,
+      size,
+      data,
+    });
+
+    Ok(())
+  }
+}
+
+pub struct MultipartBody;
+impl MultipartBody {
+  /// Parses a `multipart/form-data` body that's already in memory, so its files stay there too.
+  pub async fn parse(
+    content_type: Option<&[u8]>,
+    body: &[u8],
+    limits: &MultipartLimits,
+  ) -> Result<Multipart, BodyParseError> {
+    let limits = MultipartLimits {
+      memory_limit: usize::MAX,
+      ..*limits
+    };
+    let mut stream = MultipartStream::new(content_type, &limits, TempFiles::default())?;
+    stream.feed(body).await?;
+
+    stream.finish()
+  }
+
+  fn boundary(content_type: &[u8]) -> Option<Vec<u8>> {
+    let boundary = Self::parameters(content_type)
+      .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+      .map(|(_, value)| value.into_bytes())?;
+
+    (!boundary.is_empty() && boundary.len() <= 70).then_some(boundary)
+  }
+
+  fn part_headers(headers: &[u8]) -> Result<MultipartPart, BodyParseError> {
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+
+    for line in headers.split(|b| b == &b'\n') {
+      let line = line.strip_suffix(b"\r").unwrap_or(line);
+      let Some(colon) = line.iter().position(|b| b == &b':') else {
+        return Err(BodyParseError::Multipart("Invalid part header"));
+      };
+      let (header, value) = (&line[..colon], line[colon + 1..].trim_ascii());
+
+      if header.eq_ignore_ascii_case(b"content-disposition") {
+        for (key, value) in Self::parameters(value) {
+          match key.to_ascii_lowercase().as_str() {
+            "name" => name = Some(value),
+            "filename" => filename = Some(value),
+            _ => (),
+          }
+        }
+      } else if header.eq_ignore_ascii_case(b"content-type") {
+        content_type = Some(String::from_utf8_lossy(value).into_owned());
+      }
+    }
+
+    Ok(MultipartPart {
+      name: name.ok_or(BodyParseError::Multipart("Part without a name"))?,
+      filename,
+      content_type,
+    })
+  }
+
+  /// The `key=value` parameters after the first `;` of a header, quoted values are unescaped
+  fn parameters(value: &[u8]) -> impl Iterator<Item = (String, String)> + '_ {
+    value.split(|b| b == &b';').skip(1).filter_map(|parameter| {
+      let parameter = parameter.trim_ascii();
+      let equals = parameter.iter().position(|b| b == &b'=')?;
+      let key = String::from_utf8_lossy(parameter[..equals].trim_ascii()).into_owned();
+      let value = parameter[equals + 1..].trim_ascii();
+
+      let value = match value
+        .strip_prefix(b"\"")
+        .and_then(|v| v.strip_suffix(b"\""))
+      {
+        Some(quoted) => {
+          let mut unescaped = Vec::with_capacity(quoted.len());
+          let mut escaped = false;
+          for b in quoted {
+            if !escaped && b == &b'\\' {
+              escaped = true;
+              continue;
+            }
+            escaped = false;
+            unescaped.push(*b);
+          }
+          unescaped
+        }
+        None => value.to_vec(),
+      };
+
+      Some((key, String::from_utf8_lossy(&value).into_owned()))
+    })
+  }
+}
+
+impl Multipart {
+  pub fn to_form(&self, env: &Env) -> napi::Result<MultipartForm> {
+    let files = self
+      .files
+      .iter()
+      .map(|file| {
+        let (buffer, path) = match &file.data {
+          FileData::Memory(data) => (
+            Some(env.create_buffer_with_data(data.to_vec())?.into_raw()),
+            None,
+          ),
+          FileData::Disk(path) => (None, Some(path.to_string_lossy().into_owned())),
+        };
+
+        Ok(UploadedFile {
+          name: file.name.clone(),
+          filename: file.filename.clone(),
+          content_type: file.content_type.clone(),
+          size: i64::try_from(file.size).unwrap_or(i64::MAX),
+          buffer,
+          path,
+        })
+      })
+      .collect::<napi::Result<Vec<_>>>()?;
+
+    Ok(MultipartForm {
+      fields: Value::Object(self.fields.clone()),
+      files,
+    })
+  }
+}
+
+/// Temp files written for a request. They're removed once its response is sent,
+/// or once the request and its parsed body are dropped if that happens first.
+#[derive(Debug, Clone, Default)]
+pub struct TempFiles(Arc<TempPaths>);
+
+#[derive(Debug, Default)]
+pub struct TempPaths(Mutex<Vec<PathBuf>>);
+
+impl TempFiles {
+  pub async fn create(&self) -> Result<(PathBuf, File), BodyParseError> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |time| time.subsec_nanos());
+    let path = std::env::temp_dir().join(format!(
+      "aou-upload-{}-{nanos:x}-{}",
+      std::process::id(),
+      COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let file = OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .open(&path)
+      .await?;
+    self.0.add(path.clone());
+
+    Ok((path, file))
+  }
+
+  /// Removes every file that wasn't moved away by the handler
+  pub fn remove_all(&self) {
+    self.0.remove_all();
+  }
+}
+
+impl TempPaths {
+  fn add(&self, path: PathBuf) {
+    self.0.lock().expect("Temp files lock poisoned").push(path);
+  }
+
+  fn remove_all(&self) {
+    let paths = std::mem::take(&mut *self.0.lock().expect("Temp files lock poisoned"));
+
+    for path in paths {
+      match std::fs::remove_file(&path) {
+        Ok(_) => (),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => error!("Failed to remove temp file {} {err}", path.display()),
+      }
+    }
+  }
+}
+
+impl Drop for TempPaths {
+  fn drop(&mut self) {
+    self.remove_all();
+  }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+  haystack
+    .get(from..)?
+    .windows(needle.len())
+    .position(|window| window == needle)
+    .map(|position| from + position)
+}
+
+/// Where the bytes that could be the start of `needle` begin at the end of `haystack`
+fn partial_match_start(haystack: &[u8], needle: &[u8], from: usize) -> usize {
+  (haystack.len().saturating_sub(needle.len() - 1)..haystack.len())
+    .find(|start| needle.starts_with(&haystack[*start..]))
+    .unwrap_or(haystack.len())
+    .max(from)
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use crate::request::{
+    BodyParseError, FileData, MultipartBody, MultipartLimits, MultipartStream, TempFiles,
+  };
+
+  const CONTENT_TYPE: &[u8] = b"multipart/form-data; boundary=\"----aou\"";
+  const BODY: &[u8] = b"preamble\r\n------aou\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n------aou\r\nContent-Disposition: form-data; name=\"tag\"\r\n\r\na\r\n------aou\r\nContent-Disposition: form-data; name=\"tag\"\r\n\r\nb\r\n------aou\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\nContent-Type: text/plain\r\n\r\nline 1\r\nline 2\r\n------aou--\r\n";
+
+  #[tokio::test]
+  async fn parse_multipart_body() {
+    let limits = MultipartLimits::default();
+    let form = MultipartBody::parse(Some(CONTENT_TYPE), BODY, &limits)
+      .await
+      .unwrap();
+
+    assert_eq!(
+      serde_json::Value::Object(form.fields),
+      serde_json::json!({ "title": "hello", "tag": ["a", "b"] })
+    );
+
+    let file = &form.files[0];
+    assert_eq!(file.name, "file");
+    assert_eq!(file.filename, "a \"b\".txt");
+    assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+    assert_eq!(file.size, 14);
+    assert!(
+      matches!(&file.data, FileData::Memory(data) if data.as_ref() == b"line 1\r\nline 2"),
+      "Bodies already in memory keep their files there {:?}",
+      file.data
+    );
+  }
+
+  #[tokio::test]
+  async fn large_files_stream_to_disk() {
+    let limits = MultipartLimits {
+      memory_limit: 4,
+      ..Default::default()
+    };
+    let temp_files = TempFiles::default();
+    let mut stream = MultipartStream::new(Some(CONTENT_TYPE), &limits, temp_files.clone()).unwrap();
+
+    // Delimiters cut between chunks are still found
+    for chunk in BODY.chunks(3) {
+      stream.feed(chunk).await.unwrap();
+    }
+    let form = stream.finish().unwrap();
+
+    assert_eq!(
+      serde_json::Value::Object(form.fields),
+      serde_json::json!({ "title": "hello", "tag": ["a", "b"] }),
+      "Fields stay in memory"
+    );
+    let FileData::Disk(path) = &form.files[0].data else {
+      panic!("File over the memory limit should be on disk");
+    };
+    assert_eq!(form.files[0].size, 14);
+    assert_eq!(std::fs::read(path).unwrap(), b"line 1\r\nline 2");
+
+    temp_files.remove_all();
+    assert!(!path.exists(), "Temp files should be removed");
+  }
+
+  #[tokio::test]
+  async fn temp_files_are_removed_on_drop() {
+    let temp_files = TempFiles::default();
+    let (path, _file) = temp_files.create().await.unwrap();
+    assert!(path.exists());
+
+    drop(temp_files);
+    assert!(
+      !path.exists(),
+      "Temp files should be removed with the request"
+    );
+  }
+
+  #[tokio::test]
+  async fn multipart_limits() {
+    let parse = |limits: MultipartLimits, content_type: &'static [u8], body: &'static [u8]| async move {
+      MultipartBody::parse(Some(content_type), body, &limits)
+        .await
+        .unwrap_err()
+    };
+
+    let limits = MultipartLimits {
+      max_part_size: 8,
+      ..Default::default()
+    };
+    let err = parse(limits, CONTENT_TYPE, BODY).await;
+    assert_eq!(err.status(), 413, "Part over max_part_size should be a 413");
+
+    let limits = MultipartLimits {
+      max_total_size: 10,
+      ..Default::default()
+    };
+    let err = parse(limits, CONTENT_TYPE, BODY).await;
+    assert_eq!(
+      err.status(),
+      413,
+      "Parts over max_total_size should be a 413"
+    );
+
+    let limits = MultipartLimits::default();
+    let err = parse(limits, b"multipart/form-data", BODY).await;
+    assert!(matches!(err, BodyParseError::UnsupportedMediaType(_)));
+
+    let err = parse(limits, CONTENT_TYPE, &BODY[..60]).await;
+    assert_eq!(err.status(), 400, "Truncated body should be a 400");
+  }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use percent_encoding::percent_decode;
+
+/// Parses the `name=value` pairs of every `Cookie` header.
+///
+/// Quoted values are unquoted and `%XX` escapes decoded, the first cookie sent with a name wins
+/// since browsers send the most specific one (longest path) first.
+pub fn parse_cookies<'a>(headers: impl Iterator<Item = &'a [u8]>) -> HashMap<String, String> {
+  let mut cookies = HashMap::new();
+
+  for header in headers {
+    for pair in header.split(|b| b == &b';') {
+      let Some(equals) = pair.iter().position(|b| b == &b'=') else {
+        continue;
+      };
+
+      let name = pair[..equals].trim_ascii();
+      if name.is_empty() {
+        continue;
+      }
+
+      let value = pair[equals + 1..].trim_ascii();
+      let value = value
+        .strip_prefix(b"\"")
+        .and_then(|value| value.strip_suffix(b"\""))
+        .unwrap_or(value);
+
+      cookies
+        .entry(String::from_utf8_lossy(name).into_owned())
+        .or_insert_with(|| percent_decode(value).decode_utf8_lossy().into_owned());
+    }
+  }
+
+  cookies
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use super::parse_cookies;
+
+  #[test]
+  fn parse_cookie_headers() {
+    let headers: [&[u8]; 2] = [
+      b"session=abc; theme=\"dark\"; name=two%20words; broken; =nameless",
+      b"session=shadowed; csrf=x=y",
+    ];
+
+    let cookies = parse_cookies(headers.into_iter());
+
+    assert_eq!(cookies.len(), 4);
+    assert_eq!(cookies["session"], "abc");
+    assert_eq!(cookies["theme"], "dark");
+    assert_eq!(cookies["name"], "two words");
+    assert_eq!(cookies["csrf"], "x=y");
+  }
+}
@@ -1,90 +1,158 @@
 pub type VecOffset = (usize, usize);
 
-use anyhow::anyhow;
 use tracing::error;
 
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::{
+  io::{AsyncRead, AsyncReadExt, AsyncWrite},
+  time::Instant,
+};
 
-use super::{ParserResult, ParserState, ParserStateError, ParserStatus, Request, RequestParser};
+use super::{
+  BodyParseError, Multipart, MultipartStream, ParserResult, ParserState, ParserStateError,
+  ParserStatus, Request, RequestParseError, RequestParser, RequestTimeouts, ResolveLimits,
+  TempFiles,
+};
+use crate::constants::MULTIPART_READ_SIZE;
 
 #[derive(thiserror::Error, Debug)]
 pub enum HandleRequestError {
   #[error("Connection Timeout")]
   Timeout,
+  #[error("Request headers were not received in time")]
+  HeaderTimeout,
+  #[error("Request body was not received in time")]
+  BodyTimeout,
   #[error("End of File")]
   EOF,
   #[error(transparent)]
-  Invalid(#[from] anyhow::Error),
+  Invalid(#[from] RequestParseError),
+  #[error("Error reading buffer {0}")]
+  Read(#[from] std::io::Error),
+  #[error(transparent)]
+  State(#[from] ParserStateError),
+}
+
+impl HandleRequestError {
+  /// Status to answer with before closing the connection, `None` when it should be closed silently.
+  pub fn status(&self) -> Option<u32> {
+    match self {
+      HandleRequestError::HeaderTimeout | HandleRequestError::BodyTimeout => Some(408),
+      HandleRequestError::Invalid(err) => Some(err.status()),
+      _ => None,
+    }
+  }
+}
+
+pub async fn handle_request<T>(
+  stream: &mut T,
+  timeouts: &RequestTimeouts,
+  limits: &impl ResolveLimits,
+) -> Result<Request, HandleRequestError>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+{
+  let buf = read_request_start(stream, timeouts).await?;
+  read_request(stream, buf, timeouts, limits).await
 }
 
-impl From<ParserStateError> for HandleRequestError {
-  fn from(err: ParserStateError) -> Self {
-    HandleRequestError::Invalid(anyhow!(err))
+/// Waits for the first bytes of the next request, `Timeout` once the connection stays idle
+/// for the keep-alive timeout.
+pub async fn read_request_start<T>(
+  stream: &mut T,
+  timeouts: &RequestTimeouts,
+) -> Result<Vec<u8>, HandleRequestError>
+where
+  T: AsyncRead + Unpin,
+{
+  let mut buf = Vec::new();
+  let read = tokio::select! {
+    read = stream.read_buf(&mut buf) => read.map_err(HandleRequestError::Read)?,
+    _ = tokio::time::sleep(timeouts.keep_alive) => return Err(HandleRequestError::Timeout),
+  };
+
+  match read {
+    0 => Err(HandleRequestError::EOF),
+    _ => Ok(buf),
   }
 }
 
-pub async fn handle_request<T>(stream: &mut T) -> Result<Request, HandleRequestError>
+/// Reads the rest of the request whose first bytes `read_request_start` returned.
+pub async fn read_request<T>(
+  stream: &mut T,
+  buf: Vec<u8>,
+  timeouts: &RequestTimeouts,
+  limits: &impl ResolveLimits,
+) -> Result<Request, HandleRequestError>
 where
   T: AsyncRead + AsyncWrite + Unpin,
 {
+  let mut spilled = None;
   let mut _result: Result<ParserResult, HandleRequestError> = {
     let mut iter = 0;
-    let mut buf = Some(Vec::new());
+    let mut buf = Some(buf);
     let mut state = ParserState::Start { read_until: None };
+    let header_deadline = Instant::now() + timeouts.header;
+    // Set once the headers are parsed, the whole body has to arrive before it
+    let mut body_deadline: Option<Instant> = None;
 
     loop {
       iter += 1;
       let mut taken = buf.take().expect("Taken None Buf");
       let prev_until = state.read_until().to_owned();
 
-      /*
-      TODO: Move into Config
-        5   = ReadTimeout
-        200 = KeepAliveTimeout
-      */
-      let sleep_ms = if iter == 0 { 5 } else { 200 };
-
-      let read = tokio::select! {
-        read_buf = stream.read_buf(&mut taken) => {
-          match read_buf {
-            Ok(read) => read,
-            Err(err) => break Err(HandleRequestError::Invalid(anyhow!("Error reading buffer {err}")))
-          }
-        },
-        _ = tokio::time::sleep(tokio::time::Duration::from_millis(sleep_ms)) => {
-          if state.is_body() {
-            break Ok(state.into_parser_result(taken)?);
+      // The first bytes are already there
+      if iter > 1 {
+        let (deadline, timeout_error) = if state.is_body() || state.is_chunked_body() {
+          (
+            *body_deadline.get_or_insert_with(|| Instant::now() + timeouts.body),
+            HandleRequestError::BodyTimeout,
+          )
+        } else {
+          (header_deadline, HandleRequestError::HeaderTimeout)
+        };
+
+        let read = tokio::select! {
+          read_buf = stream.read_buf(&mut taken) => {
+            match read_buf {
+              Ok(read) => read,
+              Err(err) => break Err(HandleRequestError::Read(err))
+            }
+          },
+          _ = tokio::time::sleep_until(deadline) => {
+            break Err(timeout_error)
           }
-          break Err(HandleRequestError::Timeout)
-        }
-      };
+        };
 
-      let buf_len = taken.len();
-
-      if read == 0 && !state.is_body() {
-        break Err(HandleRequestError::EOF);
-      } else if read == 0 && state.is_body() {
-        break Ok(state.into_parser_result(taken)?);
-      } else if buf_len == 0 && read == 0 {
-        break Err(HandleRequestError::Invalid(anyhow!("No new Reads")));
-      } else if buf_len == 0 {
-        continue;
+        if read == 0 {
+          break Err(HandleRequestError::EOF);
+        }
       }
 
-      let (new_buf, new_state) = match RequestParser::parse_request(taken, state) {
+      let (new_buf, new_state) = match RequestParser::parse_request(taken, state, limits) {
         ParserStatus::Incomplete(state) => state,
         ParserStatus::Success(parser) => break Ok(parser),
-        ParserStatus::Invalid(reason) => break Err(HandleRequestError::Invalid(anyhow!(reason))),
+        ParserStatus::Invalid(reason) => break Err(HandleRequestError::Invalid(reason)),
       };
 
+      if let Some(multipart) = multipart_stream(&new_buf, &new_state, limits) {
+        let deadline = *body_deadline.get_or_insert_with(|| Instant::now() + timeouts.body);
+        break spill_multipart(stream, new_buf, new_state, multipart, deadline)
+          .await
+          .map(|(result, multipart)| {
+            spilled = Some(multipart);
+            result
+          });
+      }
+
       match new_state {
         ParserState::Start { .. } => (),
         ParserState::Head { read_until, .. }
         | ParserState::Headers { read_until, .. }
-        | ParserState::Body { read_until, .. } => {
+        | ParserState::Body { read_until, .. }
+        | ParserState::ChunkedBody { read_until, .. } => {
           if prev_until != 0 && prev_until == read_until {
             error!("Parser returned incomplete twice at : {read_until} | iter : {iter}");
-            break Err(HandleRequestError::Invalid(anyhow!("Incomplete Twice")));
+            break Err(HandleRequestError::Invalid(RequestParseError::Malformed));
           }
         }
       };
@@ -95,17 +163,183 @@ where
   };
 
   let result = _result?;
+  let route_limits = RequestParser::route_limits(&result.buf, &result.head, limits);
+  let result = result.decode_body(&route_limits).await?;
+
+  let mut request = result.into_request();
+  if let Some((multipart, temp_files)) = spilled {
+    request.set_spilled_multipart(multipart, temp_files);
+  }
+
+  Ok(request)
+}
+
+/// A `multipart/form-data` body sent with a `Content-Length` over the multipart memory limit is parsed
+/// while it's read instead of buffered, so its large files go straight to temp files.
+fn multipart_stream(
+  buf: &[u8],
+  state: &ParserState,
+  limits: &impl ResolveLimits,
+) -> Option<MultipartStream> {
+  let ParserState::Body {
+    head,
+    headers,
+    header_options,
+    ..
+  } = state
+  else {
+    return None;
+  };
+
+  let route_limits = RequestParser::route_limits(buf, head, limits);
+  if header_options.content_length? <= route_limits.multipart.memory_limit {
+    return None;
+  }
+
+  let header = |name: &[u8]| {
+    headers
+      .iter()
+      .find(|(key, _)| buf[key.0..key.1].eq_ignore_ascii_case(name))
+      .map(|(_, value)| &buf[value.0..value.1])
+  };
+  // Encoded bodies are decoded as a whole first
+  if header(b"content-encoding").is_some() {
+    return None;
+  }
+
+  MultipartStream::new(
+    header(b"content-type"),
+    &route_limits.multipart,
+    TempFiles::default(),
+  )
+  .ok()
+}
+
+/// Reads the rest of the body into `multipart`, only the head stays in `buf`.
+/// A body that fails to parse is still read to its end, the error is kept for `req.multipart()`.
+async fn spill_multipart<T>(
+  stream: &mut T,
+  mut buf: Vec<u8>,
+  state: ParserState,
+  mut multipart: MultipartStream,
+  deadline: Instant,
+) -> Result<(ParserResult, SpilledMultipart), HandleRequestError>
+where
+  T: AsyncRead + Unpin,
+{
+  let ParserState::Body {
+    head,
+    headers,
+    header_options,
+    body,
+    ..
+  } = state
+  else {
+    return Err(ParserStateError::NotBody.into());
+  };
+  let content_length = header_options.content_length.unwrap_or_default();
+
+  let mut parsed = multipart.feed(&buf[body.0..body.1]).await;
+  let mut received = body.1 - body.0;
+  buf.truncate(body.0);
+
+  let mut chunk = vec![0; MULTIPART_READ_SIZE];
+  while received < content_length {
+    let until = chunk.len().min(content_length - received);
+    let read = tokio::select! {
+      read = stream.read(&mut chunk[..until]) => read?,
+      _ = tokio::time::sleep_until(deadline) => return Err(HandleRequestError::BodyTimeout),
+    };
+
+    if read == 0 {
+      return Err(HandleRequestError::EOF);
+    }
+    received += read;
+
+    if parsed.is_ok() {
+      parsed = multipart.feed(&chunk[..read]).await;
+    }
+  }
 
-  Ok(result.into_request())
+  let temp_files = multipart.temp_files().clone();
+  let parsed = parsed.and_then(|_| multipart.finish());
+
+  Ok((
+    ParserResult {
+      buf,
+      head,
+      headers,
+      body: (body.0, body.0),
+      header_options,
+    },
+    (parsed, temp_files),
+  ))
 }
 
+type SpilledMultipart = (Result<Multipart, BodyParseError>, TempFiles);
+
 #[cfg(test)]
 mod unit_tests {
 
   use crate::{
-    request::{self, Connection},
+    constants::{MAX_HEADERS_SIZE, MAX_URI_LENGTH},
+    request::{
+      self, Connection, HandleRequestError, RequestLimits, RequestParseError, RequestTimeouts,
+      ResolveLimits,
+    },
     utils::test::BuilderWithBody,
   };
+  use tokio::time::Duration;
+
+  fn small_limits() -> RequestLimits {
+    RequestLimits {
+      max_uri_length: 16,
+      max_header_bytes: 128,
+      max_header_count: 2,
+      max_body_size: 8,
+      max_decompressed_size: 16,
+      multipart: Default::default(),
+    }
+  }
+
+  /// Allows bigger bodies on `/upload` only
+  struct UploadLimits(RequestLimits);
+  impl ResolveLimits for UploadLimits {
+    fn limits(&self) -> &RequestLimits {
+      &self.0
+    }
+
+    fn route_limits(&self, _method: &str, path: &str) -> RequestLimits {
+      match path {
+        "/upload" => RequestLimits {
+          max_body_size: 64,
+          ..self.0
+        },
+        _ => self.0,
+      }
+    }
+  }
+
+  fn short_timeouts() -> RequestTimeouts {
+    RequestTimeouts {
+      keep_alive: Duration::from_millis(50),
+      header: Duration::from_millis(50),
+      body: Duration::from_millis(50),
+    }
+  }
+
+  async fn invalid_request_status(request: &[u8]) -> u32 {
+    invalid_request_status_with(request, &RequestLimits::default()).await
+  }
+
+  async fn invalid_request_status_with(request: &[u8], limits: &impl ResolveLimits) -> u32 {
+    let mut mock = tokio_test::io::Builder::new().read(request).build();
+
+    match request::handle_request(&mut mock, &RequestTimeouts::default(), limits).await {
+      Err(HandleRequestError::Invalid(err)) => err.status(),
+      r => panic!("Request should be invalid {r:?}"),
+    }
+  }
 
   #[tokio::test]
   async fn incomplete_once() {
@@ -115,7 +349,12 @@ mod unit_tests {
       .read(b"")
       .build();
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
 
     assert!(
       r.is_ok(),
@@ -127,12 +366,93 @@ mod unit_tests {
   async fn should_timeout() {
     let mut mock = tokio_test::io::Builder::new()
       .read(b"GET /server_error123 HTTP/1.1\r\nHost: localhost:7070\r\nUser-Agent:")
-      .wait(tokio::time::Duration::from_millis(1000))
+      .wait(Duration::from_millis(1000))
+      .build();
+
+    let r = request::handle_request(&mut mock, &short_timeouts(), &RequestLimits::default()).await;
+
+    assert!(
+      matches!(r, Err(HandleRequestError::HeaderTimeout)),
+      "Request should timeout {r:?}"
+    );
+    assert_eq!(r.unwrap_err().status(), Some(408));
+  }
+
+  #[tokio::test]
+  async fn body_timeout() {
+    let mut mock = tokio_test::io::Builder::new()
+      .read(b"POST /upload HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: 10\r\n\r\nhello")
+      .wait(Duration::from_millis(1000))
       .build();
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(&mut mock, &short_timeouts(), &RequestLimits::default()).await;
 
-    assert!(r.is_err(), "Request should timeout")
+    assert!(
+      matches!(r, Err(HandleRequestError::BodyTimeout)),
+      "Incomplete body should timeout {r:?}"
+    );
+    assert_eq!(r.unwrap_err().status(), Some(408));
+  }
+
+  #[tokio::test]
+  async fn keep_alive_timeout() {
+    let mut mock = tokio_test::io::Builder::new()
+      .wait(Duration::from_millis(1000))
+      .build();
+
+    let r = request::handle_request(&mut mock, &short_timeouts(), &RequestLimits::default()).await;
+
+    assert!(
+      matches!(r, Err(HandleRequestError::Timeout)),
+      "Idle connection should timeout {r:?}"
+    );
+    assert_eq!(
+      r.unwrap_err().status(),
+      None,
+      "Idle connections are closed silently"
+    );
+  }
+
+  #[tokio::test]
+  async fn slow_body_within_timeout() {
+    let mut mock = tokio_test::io::Builder::new()
+      .read(b"POST /upload HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: 11\r\n\r\nhello")
+      .wait(Duration::from_millis(10))
+      .read(b" worl")
+      .wait(Duration::from_millis(10))
+      .read(b"d")
+      .build();
+
+    let r = request::handle_request(&mut mock, &short_timeouts(), &RequestLimits::default()).await;
+
+    assert!(r.is_ok(), "Body within the timeout should be read {r:?}");
+  }
+
+  #[tokio::test]
+  async fn trickled_body_times_out() {
+    use tokio::io::AsyncWriteExt;
+
+    let (mut client, mut server) = tokio::io::duplex(1024);
+    let trickle = tokio::spawn(async move {
+      client
+        .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: 11\r\n\r\n")
+        .await?;
+      for byte in b"hello world" {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.write_all(&[*byte]).await?;
+      }
+      std::io::Result::Ok(())
+    });
+
+    let r =
+      request::handle_request(&mut server, &short_timeouts(), &RequestLimits::default()).await;
+
+    assert!(
+      matches!(r, Err(HandleRequestError::BodyTimeout)),
+      "Body timeout should be a deadline for the whole body {r:?}"
+    );
+    drop(server);
+    let _ = trickle.await;
   }
 
   #[tokio::test]
@@ -143,16 +463,25 @@ mod unit_tests {
       )
       .build();
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
 
     assert!(
       r.is_ok(),
       "Request should return true even though it erroed once {r:?}",
     );
 
-    let mut r = r.unwrap();
+    let r = r.unwrap();
 
-    assert_eq!(r.body().len(), 0, "Request body should be of length 0")
+    assert_eq!(
+      r.body_str().unwrap().len(),
+      0,
+      "Request body should be of length 0"
+    )
   }
 
   #[tokio::test]
@@ -164,7 +493,12 @@ mod unit_tests {
       .read(b"")
       .build();
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
 
     assert!(
       r.is_err(),
@@ -182,7 +516,12 @@ mod unit_tests {
       .with_body(b"{\"valid\":\"json\"}")
       .build();
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
 
     assert!(
       r.is_ok(),
@@ -208,7 +547,12 @@ mod unit_tests {
       .read(b"")
       .build();
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
 
     assert!(
       r.is_ok(),
@@ -224,7 +568,12 @@ mod unit_tests {
       )
       .build();
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
 
     let mut sink = tokio::io::sink();
     tokio::io::copy(&mut mock, &mut sink).await.unwrap();
@@ -252,10 +601,356 @@ mod unit_tests {
       .read(b"")
       .build();
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
     assert!(r.is_ok(), "First Request should be parsed correctly");
 
-    let r = request::handle_request(&mut mock).await;
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
     assert!(r.is_ok(), "Second Request should be parsed correctly");
   }
+
+  #[tokio::test]
+  async fn chunked_body_across_reads() {
+    let mut mock = tokio_test::io::Builder::new()
+      .read(b"POST /upload HTTP/1.1\r\nHost: localhost:7070\r\nTransfer-Encoding: chunked\r\n\r\n")
+      .read(b"5\r\nhel")
+      .read(b"lo\r\n6;name=value\r\n world\r\n")
+      .read(b"0\r\n\r\n")
+      .build();
+
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
+
+    assert!(r.is_ok(), "Chunked request should be parsed {r:?}");
+
+    let r = r.unwrap();
+
+    assert_eq!(
+      r.body_str().unwrap(),
+      "hello world",
+      "Chunked body should be decoded"
+    );
+  }
+
+  #[tokio::test]
+  async fn gzip_body_is_decoded() {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"hello world").unwrap();
+    let gzip = encoder.finish().unwrap();
+
+    let mut request = format!(
+      "POST /upload HTTP/1.1\r\nHost: localhost:7070\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n{:X}\r\n",
+      gzip.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&gzip);
+    request.extend_from_slice(b"\r\n0\r\nX-Trailer: yes\r\n\r\n");
+
+    let mut mock = tokio_test::io::Builder::new().read(&request).build();
+
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
+
+    let r = r.expect("Gzip request should be parsed");
+    assert_eq!(r.body_str().unwrap(), "hello world");
+    assert_eq!(r.header("content-encoding".to_owned()), None);
+    assert_eq!(r.header("x-trailer".to_owned()), Some("yes".to_owned()));
+
+    let status = invalid_request_status(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nContent-Encoding: br\r\nContent-Length: 5\r\n\r\nhello",
+    )
+    .await;
+    assert_eq!(status, 415, "Unsupported encodings should be a 415");
+
+    let status = invalid_request_status(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nContent-Encoding: gzip\r\nContent-Length: 5\r\n\r\nhello",
+    )
+    .await;
+    assert_eq!(status, 400, "Corrupt gzip bodies should be a 400");
+
+    let mut request = format!(
+      "POST / HTTP/1.1\r\nHost: localhost:7070\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+      gzip.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&gzip);
+
+    let limits = RequestLimits {
+      max_decompressed_size: 5,
+      ..Default::default()
+    };
+    let status = invalid_request_status_with(&request, &limits).await;
+    assert_eq!(
+      status, 413,
+      "Bodies over max_decompressed_size once decompressed should be a 413"
+    );
+
+    let mut mock = tokio_test::io::Builder::new().read(&request).build();
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await
+    .expect("Gzip request with a Content-Length should be parsed");
+    assert_eq!(r.body_str().unwrap(), "hello world");
+    assert_eq!(
+      r.header("content-length".to_owned()),
+      None,
+      "The received Content-Length doesn't describe the decoded body"
+    );
+  }
+
+  #[tokio::test]
+  async fn bad_head_is_400() {
+    let status = invalid_request_status(b"GET /json\r\nHost: localhost:7070\r\n\r\n").await;
+    assert_eq!(status, 400, "Head without HTTP version should be a 400");
+
+    let status =
+      invalid_request_status(b"GET /json HTTP/1.1 extra\r\nHost: localhost:7070\r\n\r\n").await;
+    assert_eq!(status, 400, "Head with extra parts should be a 400");
+  }
+
+  #[tokio::test]
+  async fn conflicting_content_length_is_400() {
+    let status = invalid_request_status(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: 3\r\nContent-Length: 10\r\n\r\nabc",
+    )
+    .await;
+    assert_eq!(
+      status, 400,
+      "Conflicting Content-Length values should be a 400"
+    );
+  }
+
+  #[tokio::test]
+  async fn invalid_content_length() {
+    let status = invalid_request_status(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: abc\r\n\r\n",
+    )
+    .await;
+    assert_eq!(status, 400, "Non numeric Content-Length should be a 400");
+
+    let status = invalid_request_status(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: 99999999999999999999999\r\n\r\n",
+    )
+    .await;
+    assert_eq!(status, 413, "Overflowing Content-Length should be a 413");
+  }
+
+  #[tokio::test]
+  async fn missing_host_is_400() {
+    let mut mock = tokio_test::io::Builder::new()
+      .read(b"GET /json HTTP/1.1\r\naccept: */*\r\n\r\n")
+      .build();
+
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
+
+    assert!(
+      matches!(
+        r,
+        Err(HandleRequestError::Invalid(RequestParseError::MissingHost))
+      ),
+      "Request without host should be invalid {r:?}"
+    );
+    assert_eq!(RequestParseError::MissingHost.status(), 400);
+  }
+
+  #[tokio::test]
+  async fn uri_too_long_is_414() {
+    let request = format!(
+      "GET /{} HTTP/1.1\r\nHost: localhost:7070\r\n\r\n",
+      "a".repeat(MAX_URI_LENGTH)
+    );
+
+    let status = invalid_request_status(request.as_bytes()).await;
+    assert_eq!(status, 414, "URI longer than the limit should be a 414");
+  }
+
+  #[tokio::test]
+  async fn forbidden_trailers_are_dropped() {
+    let mut mock = tokio_test::io::Builder::new()
+      .read(b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nContent-Encoding: gzip\r\nContent-Length: 99\r\nX-Checksum: 1234\r\n\r\n")
+      .build();
+
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
+
+    let r = r.expect("Forbidden trailers should be dropped, not decoded");
+    assert_eq!(r.body_str().unwrap(), "hello");
+    assert_eq!(r.header("content-encoding".to_owned()), None);
+    assert_eq!(r.header("content-length".to_owned()), None);
+    assert_eq!(r.header("x-checksum".to_owned()), Some("1234".to_owned()));
+
+    let status = invalid_request_status(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nContent-Length: abc\r\n\r\n",
+    )
+    .await;
+    assert_eq!(
+      status, 400,
+      "Invalid Content-Length trailers should be a 400"
+    );
+
+    let status = invalid_request_status_with(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-Trailer: yes\r\n\r\n",
+      &small_limits(),
+    )
+    .await;
+    assert_eq!(
+      status, 431,
+      "Trailers past max_header_count should be a 431"
+    );
+  }
+
+  #[tokio::test]
+  async fn headers_too_large_is_431() {
+    let request = format!(
+      "GET /json HTTP/1.1\r\nHost: localhost:7070\r\nx-large: {}\r\n",
+      "a".repeat(MAX_HEADERS_SIZE)
+    );
+
+    let status = invalid_request_status(request.as_bytes()).await;
+    assert_eq!(status, 431, "Headers larger than the limit should be a 431");
+  }
+
+  #[tokio::test]
+  async fn unsupported_version_is_505() {
+    let status =
+      invalid_request_status(b"GET /json HTTP/1.0\r\nHost: localhost:7070\r\n\r\n").await;
+    assert_eq!(status, 505, "HTTP/1.0 should be a 505");
+  }
+
+  #[tokio::test]
+  async fn head_split_across_reads() {
+    let mut mock = tokio_test::io::Builder::new()
+      .read(b"GET /json HTTP/1.")
+      .read(b"1\r\nHost: localhost:7070\r\n\r\n")
+      .build();
+
+    let r = request::handle_request(
+      &mut mock,
+      &RequestTimeouts::default(),
+      &RequestLimits::default(),
+    )
+    .await;
+
+    assert!(
+      r.is_ok(),
+      "Incomplete HTTP version should wait for more data {r:?}"
+    );
+  }
+
+  #[tokio::test]
+  async fn configured_limits() {
+    let limits = small_limits();
+
+    let status = invalid_request_status_with(
+      b"GET /a-very-long-path HTTP/1.1\r\nHost: localhost:7070\r\n\r\n",
+      &limits,
+    )
+    .await;
+    assert_eq!(
+      status, 414,
+      "URI longer than max_uri_length should be a 414"
+    );
+
+    let status = invalid_request_status_with(
+      b"GET / HTTP/1.1\r\nHost: localhost:7070\r\na: 1\r\nb: 2\r\n\r\n",
+      &limits,
+    )
+    .await;
+    assert_eq!(
+      status, 431,
+      "More headers than max_header_count should be a 431"
+    );
+
+    let status = invalid_request_status_with(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: 9\r\n\r\n",
+      &limits,
+    )
+    .await;
+    assert_eq!(
+      status, 413,
+      "Content-Length over max_body_size should be a 413 before reading the body"
+    );
+
+    let status = invalid_request_status_with(
+      b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n5\r\n",
+      &limits,
+    )
+    .await;
+    assert_eq!(status, 413, "Chunks over max_body_size should be a 413");
+  }
+
+  #[tokio::test]
+  async fn chunk_framing_is_limited() {
+    use tokio::io::AsyncWriteExt;
+
+    let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+    client
+      .write_all(b"POST / HTTP/1.1\r\nHost: localhost:7070\r\nTransfer-Encoding: chunked\r\n\r\n")
+      .await
+      .unwrap();
+    let chunk = format!("1;{}\r\na\r\n", "x".repeat(100));
+    for _ in 0..8 {
+      client.write_all(chunk.as_bytes()).await.unwrap();
+    }
+
+    let r =
+      request::handle_request(&mut server, &RequestTimeouts::default(), &small_limits()).await;
+    assert_eq!(
+      r.map(|_| ()).unwrap_err().status(),
+      Some(413),
+      "Chunk extensions larger than the body limit should be a 413"
+    );
+  }
+
+  #[tokio::test]
+  async fn route_limits_override() {
+    let limits = UploadLimits(small_limits());
+
+    let mut mock = tokio_test::io::Builder::new()
+      .read(
+        b"POST /upload HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: 11\r\n\r\nhello world",
+      )
+      .build();
+
+    let r = request::handle_request(&mut mock, &RequestTimeouts::default(), &limits).await;
+    assert!(r.is_ok(), "Route override should allow the body {r:?}");
+
+    let status = invalid_request_status_with(
+      b"POST /other HTTP/1.1\r\nHost: localhost:7070\r\nContent-Length: 11\r\n\r\nhello world",
+      &limits,
+    )
+    .await;
+    assert_eq!(status, 413, "Other routes keep the default limits");
+  }
 }
@@ -10,4 +10,8 @@ pub enum RequestHeadParseError {
   NoHTTPVersion,
   #[error("Invalid HTTP Version")]
   InvalidHTTPVersion,
+  #[error("Invalid Request Head")]
+  InvalidHead,
+  #[error("URI too long")]
+  UriTooLong,
 }
@@ -6,6 +6,7 @@ pub use error::*;
 use super::VecOffset;
 
 const HTTP1_1: &[u8] = b"HTTP/1.1\r";
+const HTTP_PREFIX: &[u8] = b"HTTP/";
 
 #[derive(Debug, Default)]
 pub struct RequestHead {
@@ -18,10 +19,20 @@ impl RequestHead {
   pub fn from_split_iter<'a>(
     iter: &mut std::slice::Split<'a, u8, impl FnMut(&u8) -> bool>,
     vec: &'a [u8],
+    max_uri_length: usize,
   ) -> Result<(usize, RequestHead), RequestHeadParseError> {
     let mut offset: usize = 0;
 
     let head = iter.next().ok_or(RequestHeadParseError::NoHead)?;
+
+    // Without a \n the head line hasn't been fully read yet.
+    if head.len() == vec.len() {
+      if head.len() > max_uri_length {
+        return Err(RequestHeadParseError::UriTooLong);
+      }
+      return Err(RequestHeadParseError::NoHead);
+    }
+
     offset = offset.wrapping_add(head.len() + 1); // Add size of Head + \n to offset
 
     let mut head_split = head.split(|b| b == &b' ');
@@ -32,9 +43,24 @@ impl RequestHead {
       .next()
       .ok_or(RequestHeadParseError::NoHTTPVersion)?;
 
+    if method.is_empty() || path.is_empty() || head_split.next().is_some() {
+      return Err(RequestHeadParseError::InvalidHead);
+    }
+
+    if path.len() > max_uri_length {
+      return Err(RequestHeadParseError::UriTooLong);
+    }
+
+    // The method and path are read as `str` without checking them again
+    if !method.is_ascii() || std::str::from_utf8(path).is_err() {
+      return Err(RequestHeadParseError::InvalidHead);
+    }
+
     if http_version != HTTP1_1 {
-      //TODO: This should throw a invalid head http error
-      return Err(RequestHeadParseError::InvalidHTTPVersion);
+      if http_version.starts_with(HTTP_PREFIX) && http_version.ends_with(b"\r") {
+        return Err(RequestHeadParseError::InvalidHTTPVersion);
+      }
+      return Err(RequestHeadParseError::InvalidHead);
     }
 
     let method = range_from_subslice(vec, method);
@@ -4,7 +4,7 @@ use crate::utils::range_from_subslice;
 
 use super::{
   options::{Connection, HeaderOptions},
-  VecOffset,
+  RequestParseError, VecOffset,
 };
 
 pub type RequestHeaders = Vec<RequestHeaderVec>;
@@ -14,6 +14,8 @@ pub type RequestHeaderVec = (VecOffset, VecOffset);
 pub enum HeaderParseError {
   Incomplete,
   Invalid,
+  /// The headers are complete enough to tell the request can't be answered
+  Rejected(RequestParseError),
 }
 
 #[derive(Debug)]
@@ -36,6 +38,8 @@ impl HeaderParser {
     let mut options = HeaderOptions {
       connection: Connection::KeepAlive,
       content_length: None,
+      chunked: false,
+      transfer_encoding: false,
       has_host: false,
     };
 
@@ -70,9 +74,20 @@ impl HeaderParser {
         options.connection = Connection::Close
       };
 
-      if options.content_length.is_none() && header.eq_ignore_ascii_case(b"content-length") {
-        let st = str::from_utf8(&value[1..(value.len() - 1)]).expect("parse content length value"); //TODO : remove unwrap
-        options.content_length = Some(st.parse::<usize>().expect("parse content-length"));
+      if header.eq_ignore_ascii_case(b"content-length") {
+        let length = content_length(&value[1..value.len() - 1])?;
+        options
+          .set_content_length(length)
+          .map_err(HeaderParseError::Rejected)?;
+      }
+
+      if header.eq_ignore_ascii_case(b"transfer-encoding") {
+        options.transfer_encoding = true;
+        // Only the final coding of the last header decides the framing, e.g. `gzip, chunked`
+        options.chunked = value[1..value.len() - 1]
+          .rsplit(|b| b == &b',')
+          .next()
+          .is_some_and(|coding| coding.trim_ascii().eq_ignore_ascii_case(b"chunked"));
       }
 
       let header = range_from_subslice(buf, header);
@@ -93,6 +108,23 @@ impl HeaderParser {
   }
 }
 
+/// A `Content-Length` is only digits, one too large for a `usize` can't fit any body limit
+fn content_length(value: &[u8]) -> Result<usize, HeaderParseError> {
+  let value = value.trim_ascii();
+  if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+    return Err(HeaderParseError::Rejected(
+      RequestParseError::InvalidContentLength,
+    ));
+  }
+
+  str::from_utf8(value)
+    .ok()
+    .and_then(|value| value.parse().ok())
+    .ok_or(HeaderParseError::Rejected(
+      RequestParseError::PayloadTooLarge,
+    ))
+}
+
 #[cfg(test)]
 mod unit_tests {
   use crate::request::{
@@ -246,4 +278,22 @@ mod unit_tests {
     let connection = parser.options.connection;
     assert_eq!(connection, Connection::Close, "Connection should be CLOSE");
   }
+
+  #[tokio::test]
+  async fn transfer_encoding_chunked() {
+    let buf = b"Host: localhost:3000\r\nTransfer-Encoding: gzip, Chunked\r\n\r\n";
+    let mut lines = RequestParser::split_buf_lines(buf);
+    let parser = HeaderParser::parse_headers(buf, &mut lines).unwrap();
+
+    assert!(parser.options.chunked, "Last coding should be chunked");
+
+    let buf = b"Host: localhost:3000\r\nTransfer-Encoding: chunked, gzip\r\n\r\n";
+    let mut lines = RequestParser::split_buf_lines(buf);
+    let parser = HeaderParser::parse_headers(buf, &mut lines).unwrap();
+
+    assert!(
+      !parser.options.chunked,
+      "Chunked should only be considered as the last coding"
+    );
+  }
 }
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpMethod {
   GET,
   HEAD,
@@ -25,6 +25,18 @@ impl Display for HttpMethod {
 }
 
 impl HttpMethod {
+  pub const METHODS: [HttpMethod; 9] = [
+    HttpMethod::GET,
+    HttpMethod::HEAD,
+    HttpMethod::POST,
+    HttpMethod::PUT,
+    HttpMethod::DELETE,
+    HttpMethod::CONNECT,
+    HttpMethod::OPTIONS,
+    HttpMethod::TRACE,
+    HttpMethod::PATCH,
+  ];
+
   pub fn from_str(slice: &str) -> Result<HttpMethod, HttpMethodError> {
     match slice {
       "GET" => Ok(HttpMethod::GET),
@@ -34,8 +46,8 @@ impl HttpMethod {
       "DELETE" => Ok(HttpMethod::DELETE),
       "CONNECT" => Ok(HttpMethod::CONNECT),
       "OPTIONS" => Ok(HttpMethod::OPTIONS),
-      "TRACE" => Ok(HttpMethod::OPTIONS),
-      "PATCH" => Ok(HttpMethod::TRACE),
+      "TRACE" => Ok(HttpMethod::TRACE),
+      "PATCH" => Ok(HttpMethod::PATCH),
       _ => Err(HttpMethodError::InvalidMethod),
     }
   }
@@ -49,8 +61,8 @@ impl HttpMethod {
       b"DELETE" => Ok(HttpMethod::DELETE),
       b"CONNECT" => Ok(HttpMethod::CONNECT),
       b"OPTIONS" => Ok(HttpMethod::OPTIONS),
-      b"TRACE" => Ok(HttpMethod::OPTIONS),
-      b"PATCH" => Ok(HttpMethod::TRACE),
+      b"TRACE" => Ok(HttpMethod::TRACE),
+      b"PATCH" => Ok(HttpMethod::PATCH),
       _ => Err(HttpMethodError::InvalidMethod),
     }
   }
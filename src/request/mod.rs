@@ -1,3 +1,5 @@
+mod body;
+mod cookies;
 mod handle;
 mod head;
 mod headers;
@@ -6,6 +8,8 @@ mod options;
 mod parser;
 mod request;
 
+pub use body::*;
+pub use cookies::*;
 pub use handle::*;
 pub use head::*;
 pub use headers::*;
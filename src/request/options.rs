@@ -1,3 +1,12 @@
+use std::time::Duration;
+
+use crate::constants::{
+  BODY_TIMEOUT_MS, HEADER_TIMEOUT_MS, KEEP_ALIVE_TIMEOUT_MS, MAX_BODY_SIZE, MAX_HEADERS_SIZE,
+  MAX_HEADER_COUNT, MAX_URI_LENGTH,
+};
+
+use super::{MultipartLimits, RequestParseError};
+
 #[derive(Debug, PartialEq)]
 pub enum Connection {
   KeepAlive,
@@ -13,6 +22,9 @@ pub struct RequestOptions {
 pub struct HeaderOptions {
   pub connection: Connection,
   pub content_length: Option<usize>,
+  pub chunked: bool,
+  /// A `Transfer-Encoding` header was sent, the body must then be chunked
+  pub transfer_encoding: bool,
   pub has_host: bool, //TODO add content type and ... in this struct
 }
 
@@ -21,23 +33,113 @@ impl Default for HeaderOptions {
     Self {
       connection: Connection::KeepAlive,
       content_length: None,
+      chunked: false,
+      transfer_encoding: false,
       has_host: false,
     }
   }
 }
 
 impl HeaderOptions {
-  pub fn merge(&mut self, other: HeaderOptions) {
+  pub fn merge(&mut self, other: HeaderOptions) -> Result<(), RequestParseError> {
     if other.connection != Connection::KeepAlive {
       self.connection = other.connection;
     }
 
-    if self.content_length.is_none() && other.content_length.is_some() {
-      self.content_length = other.content_length;
+    if let Some(content_length) = other.content_length {
+      self.set_content_length(content_length)?;
+    }
+
+    if other.transfer_encoding {
+      self.transfer_encoding = true;
+      self.chunked = other.chunked;
     }
 
     if !self.has_host && other.has_host {
       self.has_host = true;
     }
+
+    Ok(())
+  }
+
+  /// Repeated `Content-Length` headers must agree, otherwise the body's end is ambiguous
+  pub fn set_content_length(&mut self, content_length: usize) -> Result<(), RequestParseError> {
+    match self.content_length {
+      Some(previous) if previous != content_length => Err(RequestParseError::InvalidFraming(
+        "conflicting Content-Length values were sent",
+      )),
+      _ => {
+        self.content_length = Some(content_length);
+        Ok(())
+      }
+    }
+  }
+}
+
+/// How long a connection may take to send each part of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestTimeouts {
+  /// Idle time allowed before the first byte of a request
+  pub keep_alive: Duration,
+  /// Time allowed to receive the head and headers, counted from the first byte
+  pub header: Duration,
+  /// Time allowed to receive the whole body, counted from the end of the headers
+  pub body: Duration,
+}
+
+impl Default for RequestTimeouts {
+  fn default() -> Self {
+    Self {
+      keep_alive: Duration::from_millis(KEEP_ALIVE_TIMEOUT_MS),
+      header: Duration::from_millis(HEADER_TIMEOUT_MS),
+      body: Duration::from_millis(BODY_TIMEOUT_MS),
+    }
+  }
+}
+
+/// Size limits enforced while a request is parsed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestLimits {
+  /// Longest request target accepted, answered with a 414
+  pub max_uri_length: usize,
+  /// Bytes allowed for the header section (and chunked trailers), answered with a 431
+  pub max_header_bytes: usize,
+  /// Header fields allowed, answered with a 431
+  pub max_header_count: usize,
+  /// Bytes allowed for the (decoded) body, answered with a 413
+  pub max_body_size: usize,
+  /// Bytes allowed for the body once its `Content-Encoding` is undone, answered with a 413
+  pub max_decompressed_size: usize,
+  /// Multipart bodies larger than `memory_limit` are parsed while they're read
+  pub multipart: MultipartLimits,
+}
+
+impl Default for RequestLimits {
+  fn default() -> Self {
+    Self {
+      max_uri_length: MAX_URI_LENGTH,
+      max_header_bytes: MAX_HEADERS_SIZE,
+      max_header_count: MAX_HEADER_COUNT,
+      max_body_size: MAX_BODY_SIZE,
+      max_decompressed_size: MAX_BODY_SIZE,
+      multipart: MultipartLimits::default(),
+    }
+  }
+}
+
+/// Resolves the limits of a request, once its head is parsed the route it targets can override them.
+pub trait ResolveLimits {
+  /// Limits used until the head is parsed
+  fn limits(&self) -> &RequestLimits;
+
+  /// Limits for the headers and body of a request to `path`
+  fn route_limits(&self, _method: &str, _path: &str) -> RequestLimits {
+    *self.limits()
+  }
+}
+
+impl ResolveLimits for RequestLimits {
+  fn limits(&self) -> &RequestLimits {
+    self
   }
 }
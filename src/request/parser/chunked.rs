@@ -0,0 +1,183 @@
+use crate::{
+  constants::{CRLF, CRLF_SIZE, FORBIDDEN_TRAILERS},
+  request::{HeaderParseError, HeaderParser, HeaderParserResult, RequestHeaders, RequestLimits},
+};
+
+use super::{ParserResult, ParserState, ParserStatus, RequestParseError, RequestParser};
+
+/// Decodes `Transfer-Encoding: chunked` bodies.
+///
+/// Chunks are decoded in place: every complete chunk is moved back to the end of the
+/// decoded body, so the reassembled body stays a single [`crate::request::VecOffset`]
+/// into the request buffer. `cursor` always points at the next unparsed chunk-size line.
+pub struct ChunkedParser;
+impl ChunkedParser {
+  pub fn parse_chunks(
+    mut buf: Vec<u8>,
+    state: ParserState,
+    limits: &RequestLimits,
+  ) -> ParserStatus {
+    let ParserState::ChunkedBody {
+      mut cursor,
+      head,
+      mut headers,
+      header_options,
+      mut body,
+      ..
+    } = state
+    else {
+      return ParserStatus::Invalid(RequestParseError::Malformed);
+    };
+
+    // The framing of consumed chunks stays in `buf` until the request is done, so it can't take
+    // more room than the body itself, e.g. 1 byte chunks with long extensions.
+    let max_wire_size = limits
+      .max_body_size
+      .saturating_mul(2)
+      .saturating_add(limits.max_header_bytes);
+    if buf.len() - body.0 > max_wire_size {
+      return ParserStatus::Invalid(RequestParseError::PayloadTooLarge);
+    }
+
+    while let Some(line_end) = Self::find_crlf(&buf, cursor) {
+      let size = match Self::chunk_size(&buf[cursor..line_end]) {
+        Some(size) => size,
+        None => {
+          return ParserStatus::Invalid(RequestParseError::InvalidChunk("Invalid chunk size"))
+        }
+      };
+
+      if size > limits.max_body_size.saturating_sub(body.1 - body.0) {
+        return ParserStatus::Invalid(RequestParseError::PayloadTooLarge);
+      }
+
+      let data_start = line_end + CRLF_SIZE;
+
+      if size == 0 {
+        let max_trailers = limits.max_header_count.saturating_sub(headers.len());
+        match Self::parse_trailers(&buf, data_start, max_trailers) {
+          Ok(Some(mut trailers)) => {
+            headers.append(&mut trailers);
+
+            return ParserStatus::Success(ParserResult {
+              buf,
+              head,
+              headers,
+              body,
+              header_options,
+            });
+          }
+          Ok(None) if buf.len() - data_start > limits.max_header_bytes => {
+            return ParserStatus::Invalid(RequestParseError::HeadersTooLarge)
+          }
+          Ok(None) => break,
+          Err(reason) => return ParserStatus::Invalid(reason),
+        }
+      }
+
+      let data_end = match data_start.checked_add(size) {
+        Some(data_end) => data_end,
+        None => {
+          return ParserStatus::Invalid(RequestParseError::InvalidChunk("Invalid chunk size"))
+        }
+      };
+
+      if buf.len() < data_end + CRLF_SIZE {
+        break;
+      }
+
+      if &buf[data_end..data_end + CRLF_SIZE] != CRLF {
+        return ParserStatus::Invalid(RequestParseError::InvalidChunk(
+          "Chunk data is not terminated by CRLF",
+        ));
+      }
+
+      buf.copy_within(data_start..data_end, body.1);
+      body.1 += size;
+      cursor = data_end + CRLF_SIZE;
+    }
+
+    // Whatever is left after the last complete chunk has to fit a chunk size line.
+    if Self::find_crlf(&buf, cursor).is_none() && buf.len() - cursor > limits.max_header_bytes {
+      return ParserStatus::Invalid(RequestParseError::InvalidChunk("Chunk size line too long"));
+    }
+
+    let read_until = buf.len();
+
+    ParserStatus::Incomplete((
+      buf,
+      ParserState::ChunkedBody {
+        cursor,
+        read_until,
+        head,
+        headers,
+        header_options,
+        body,
+      },
+    ))
+  }
+
+  /// Parses the hex size of a chunk, skipping any chunk extensions (`1a;name=value`)
+  fn chunk_size(line: &[u8]) -> Option<usize> {
+    let size = line
+      .split(|b| b == &b';')
+      .next()
+      .unwrap_or_default()
+      .trim_ascii();
+
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+      return None;
+    }
+
+    usize::from_str_radix(std::str::from_utf8(size).ok()?, 16).ok()
+  }
+
+  /// Returns `Ok(None)` while the trailer section isn't terminated by an empty line yet.
+  /// Trailers count against the header limits, fields that aren't allowed in trailers are dropped.
+  fn parse_trailers(
+    buf: &[u8],
+    start: usize,
+    max_trailers: usize,
+  ) -> Result<Option<RequestHeaders>, RequestParseError> {
+    if buf.len() < start + CRLF_SIZE {
+      return Ok(None);
+    }
+
+    if &buf[start..start + CRLF_SIZE] == CRLF {
+      return Ok(Some(Vec::new()));
+    }
+
+    let end = match buf[start..].windows(4).position(|w| w == b"\r\n\r\n") {
+      Some(position) => start + position + CRLF_SIZE * 2,
+      None => return Ok(None),
+    };
+
+    let mut lines = RequestParser::split_buf_lines(&buf[start..end]);
+
+    let mut trailers = match HeaderParser::parse_headers(buf, &mut lines) {
+      Ok(HeaderParserResult { headers, .. }) => headers,
+      Err(HeaderParseError::Rejected(reason)) => return Err(reason),
+      Err(_) => return Err(RequestParseError::InvalidChunk("Invalid chunked trailers")),
+    };
+
+    if trailers.len() > max_trailers {
+      return Err(RequestParseError::HeadersTooLarge);
+    }
+
+    trailers.retain(|(name, _)| {
+      let name = &buf[name.0..name.1];
+      !FORBIDDEN_TRAILERS
+        .iter()
+        .any(|forbidden| name.eq_ignore_ascii_case(forbidden.as_bytes()))
+    });
+
+    Ok(Some(trailers))
+  }
+
+  fn find_crlf(buf: &[u8], from: usize) -> Option<usize> {
+    buf[from..]
+      .windows(CRLF_SIZE)
+      .position(|w| w == CRLF)
+      .map(|position| from + position)
+  }
+}
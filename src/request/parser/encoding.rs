@@ -0,0 +1,176 @@
+use std::io::Read;
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+
+use super::RequestParseError;
+
+/// Content codings a request body can be sent with
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ContentCoding {
+  Gzip,
+  Deflate,
+}
+
+impl ContentCoding {
+  /// `None` for `identity`, which leaves the body as is
+  fn parse(name: &[u8]) -> Result<Option<Self>, RequestParseError> {
+    if name.eq_ignore_ascii_case(b"gzip") || name.eq_ignore_ascii_case(b"x-gzip") {
+      Ok(Some(ContentCoding::Gzip))
+    } else if name.eq_ignore_ascii_case(b"deflate") {
+      Ok(Some(ContentCoding::Deflate))
+    } else if name.eq_ignore_ascii_case(b"identity") {
+      Ok(None)
+    } else {
+      Err(RequestParseError::UnsupportedEncoding(
+        String::from_utf8_lossy(name).into_owned(),
+      ))
+    }
+  }
+
+  fn as_str(&self) -> &'static str {
+    match self {
+      ContentCoding::Gzip => "gzip",
+      ContentCoding::Deflate => "deflate",
+    }
+  }
+
+  fn decode(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>, RequestParseError> {
+    let limit = max_size as u64 + 1;
+    let mut decoded = Vec::new();
+
+    let read = match self {
+      ContentCoding::Gzip => MultiGzDecoder::new(data)
+        .take(limit)
+        .read_to_end(&mut decoded),
+      ContentCoding::Deflate if is_zlib(data) => {
+        ZlibDecoder::new(data).take(limit).read_to_end(&mut decoded)
+      }
+      // Some clients send raw deflate data instead of the zlib format HTTP asks for
+      ContentCoding::Deflate => DeflateDecoder::new(data)
+        .take(limit)
+        .read_to_end(&mut decoded),
+    };
+
+    if decoded.len() > max_size {
+      return Err(RequestParseError::DecodedTooLarge);
+    }
+    read.map_err(|_| RequestParseError::InvalidEncoding(self.as_str()))?;
+
+    Ok(decoded)
+  }
+}
+
+/// A zlib stream starts with a deflate method byte and a header checksum
+fn is_zlib(data: &[u8]) -> bool {
+  match data {
+    [cmf, flg, ..] => cmf & 0x0F == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+    _ => false,
+  }
+}
+
+/// Decodes a body sent with the codings of the `Content-Encoding` headers, which are undone last to first.
+///
+/// Every step stops once it produced more than `max_size` bytes, so a small compressed body can't
+/// expand until it exhausts memory. `None` when the body isn't encoded.
+pub fn decode_content<'a>(
+  body: &[u8],
+  content_encoding: impl Iterator<Item = &'a [u8]>,
+  max_size: usize,
+) -> Result<Option<Vec<u8>>, RequestParseError> {
+  let mut codings = Vec::new();
+  for header in content_encoding {
+    for name in header.split(|b| b == &b',').map(|name| name.trim_ascii()) {
+      if name.is_empty() {
+        continue;
+      }
+      if let Some(coding) = ContentCoding::parse(name)? {
+        codings.push(coding);
+      }
+    }
+  }
+
+  if codings.is_empty() || body.is_empty() {
+    return Ok(None);
+  }
+
+  let mut decoded = codings.pop().unwrap().decode(body, max_size)?;
+  while let Some(coding) = codings.pop() {
+    decoded = coding.decode(&decoded, max_size)?;
+  }
+
+  Ok(Some(decoded))
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use std::io::Write;
+
+  use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+  use flate2::Compression;
+
+  use super::decode_content;
+  use crate::request::RequestParseError;
+
+  fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  #[test]
+  fn decode_codings() {
+    let body = b"{\"hello\":\"world\"}";
+    let decode = |data: &[u8], header: &[u8]| decode_content(data, [header].into_iter(), 1024);
+
+    assert_eq!(decode(&gzip(body), b"gzip").unwrap().unwrap(), body);
+    assert_eq!(decode(&gzip(body), b"X-GZIP").unwrap().unwrap(), body);
+    assert_eq!(
+      decode(&gzip(&gzip(body)), b"gzip, identity, gzip")
+        .unwrap()
+        .unwrap(),
+      body
+    );
+
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(body).unwrap();
+    assert_eq!(
+      decode(&zlib.finish().unwrap(), b"deflate")
+        .unwrap()
+        .unwrap(),
+      body
+    );
+
+    let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+    raw.write_all(body).unwrap();
+    assert_eq!(
+      decode(&raw.finish().unwrap(), b"deflate").unwrap().unwrap(),
+      body
+    );
+
+    assert_eq!(decode(body, b"identity"), Ok(None));
+    assert_eq!(decode(b"", b"gzip"), Ok(None));
+    assert_eq!(decode_content(body, std::iter::empty(), 1024), Ok(None));
+  }
+
+  #[test]
+  fn reject_invalid_bodies() {
+    let decode = |data: &[u8], header: &[u8]| decode_content(data, [header].into_iter(), 1024);
+
+    assert_eq!(
+      decode(b"data", b"br"),
+      Err(RequestParseError::UnsupportedEncoding("br".to_owned()))
+    );
+    assert_eq!(
+      decode(b"not gzip", b"gzip"),
+      Err(RequestParseError::InvalidEncoding("gzip"))
+    );
+    assert_eq!(
+      decode(&gzip(&[0; 4096]), b"gzip"),
+      Err(RequestParseError::DecodedTooLarge)
+    );
+    assert_eq!(
+      decode(&gzip(&[0; 1024]), b"gzip").unwrap().unwrap().len(),
+      1024
+    );
+  }
+}
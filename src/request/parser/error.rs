@@ -0,0 +1,49 @@
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum RequestParseError {
+  #[error("Invalid request head")]
+  InvalidHead,
+  #[error("Host header not found")]
+  MissingHost,
+  #[error("Invalid chunked body: {0}")]
+  InvalidChunk(&'static str),
+  #[error("Invalid message framing: {0}")]
+  InvalidFraming(&'static str),
+  #[error("Invalid Content-Length")]
+  InvalidContentLength,
+  #[error("Malformed request")]
+  Malformed,
+  #[error("URI too long")]
+  UriTooLong,
+  #[error("Request header fields too large")]
+  HeadersTooLarge,
+  #[error("Request body too large")]
+  PayloadTooLarge,
+  #[error("Unsupported content encoding {0:?}")]
+  UnsupportedEncoding(String),
+  #[error("Invalid {0} request body")]
+  InvalidEncoding(&'static str),
+  #[error("Decompressed request body too large")]
+  DecodedTooLarge,
+  #[error("HTTP version not supported")]
+  UnsupportedVersion,
+}
+
+impl RequestParseError {
+  /// Status code of the response sent before closing the connection
+  pub fn status(&self) -> u32 {
+    match self {
+      RequestParseError::InvalidHead
+      | RequestParseError::MissingHost
+      | RequestParseError::InvalidChunk(_)
+      | RequestParseError::InvalidEncoding(_)
+      | RequestParseError::InvalidFraming(_)
+      | RequestParseError::InvalidContentLength
+      | RequestParseError::Malformed => 400,
+      RequestParseError::PayloadTooLarge | RequestParseError::DecodedTooLarge => 413,
+      RequestParseError::UriTooLong => 414,
+      RequestParseError::UnsupportedEncoding(_) => 415,
+      RequestParseError::HeadersTooLarge => 431,
+      RequestParseError::UnsupportedVersion => 505,
+    }
+  }
+}
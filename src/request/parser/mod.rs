@@ -1,8 +1,14 @@
+mod chunked;
+mod encoding;
+mod error;
 mod parser;
 mod result;
 mod state;
 mod status;
 
+pub use chunked::*;
+pub use encoding::*;
+pub use error::*;
 pub use parser::*;
 pub use result::*;
 pub use state::*;
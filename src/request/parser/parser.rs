@@ -2,16 +2,31 @@ use crate::{
   constants::{CRLF_SIZE, LF},
   request::{
     HeaderParseError, HeaderParser, HeaderParserResult, RequestHead, RequestHeadParseError,
+    RequestLimits, ResolveLimits,
   },
   utils,
 };
 use std::slice::Split;
 
-use super::{FullParserState, ParserResult, ParserState, ParserStatus};
+use super::{
+  ChunkedParser, FullParserState, ParserResult, ParserState, ParserStatus, RequestParseError,
+};
 
 pub struct RequestParser;
 impl RequestParser {
-  pub fn parse_request(buf: Vec<u8>, _state: ParserState) -> ParserStatus {
+  pub fn parse_request(
+    buf: Vec<u8>,
+    _state: ParserState,
+    limits: &impl ResolveLimits,
+  ) -> ParserStatus {
+    if _state.is_chunked_body() {
+      let route_limits = match _state.head() {
+        Some(head) => Self::route_limits(&buf, head, limits),
+        None => *limits.limits(),
+      };
+      return ChunkedParser::parse_chunks(buf, _state, &route_limits);
+    }
+
     let buf_len = buf.len();
     let mut offset: usize = 0;
     let mut lines = RequestParser::split_buf_lines(&buf);
@@ -34,7 +49,8 @@ impl RequestParser {
       })
       .map_or_else(
         || {
-          let (size, head) = RequestHead::from_split_iter(&mut lines, &buf)?;
+          let (size, head) =
+            RequestHead::from_split_iter(&mut lines, &buf, limits.limits().max_uri_length)?;
           offset = offset + size;
 
           return Ok::<RequestHead, RequestHeadParseError>(head);
@@ -42,10 +58,7 @@ impl RequestParser {
         |ok| Ok(ok),
       ) {
       Ok(head) => head,
-      Err(RequestHeadParseError::InvalidHTTPVersion) => {
-        return ParserStatus::Invalid("HTTP Version Not supported".into());
-      }
-      Err(_) => {
+      Err(RequestHeadParseError::NoHead) => {
         return ParserStatus::Incomplete((
           buf,
           ParserState::Start {
@@ -53,8 +66,19 @@ impl RequestParser {
           },
         ));
       }
+      Err(RequestHeadParseError::InvalidHTTPVersion) => {
+        return ParserStatus::Invalid(RequestParseError::UnsupportedVersion);
+      }
+      Err(RequestHeadParseError::UriTooLong) => {
+        return ParserStatus::Invalid(RequestParseError::UriTooLong);
+      }
+      Err(_) => {
+        return ParserStatus::Invalid(RequestParseError::InvalidHead);
+      }
     };
 
+    let route_limits = Self::route_limits(&buf, &head, limits);
+
     let (headers, header_options) = match (headers, header_options) {
       (Some(mut headers), Some(mut header_options)) => {
         offset = cursor.unwrap_or(offset);
@@ -74,12 +98,18 @@ impl RequestParser {
             }) => {
               offset = offset + size;
 
-              header_options.merge(options);
+              if let Err(reason) = header_options.merge(options) {
+                return ParserStatus::Invalid(reason);
+              }
 
               headers.append(&mut headers2);
               (headers, header_options)
             }
+            Err(HeaderParseError::Rejected(reason)) => return ParserStatus::Invalid(reason),
             Err(HeaderParseError::Incomplete) | Err(HeaderParseError::Invalid) => {
+              if Self::headers_too_large(buf_len, &head, &route_limits) {
+                return ParserStatus::Invalid(RequestParseError::HeadersTooLarge);
+              }
               return ParserStatus::Incomplete((
                 buf,
                 ParserState::Head {
@@ -101,7 +131,11 @@ impl RequestParser {
           offset = offset + size;
           (headers, options)
         }
+        Err(HeaderParseError::Rejected(reason)) => return ParserStatus::Invalid(reason),
         Err(HeaderParseError::Incomplete) | Err(HeaderParseError::Invalid) => {
+          if Self::headers_too_large(buf_len, &head, &route_limits) {
+            return ParserStatus::Invalid(RequestParseError::HeadersTooLarge);
+          }
           return ParserStatus::Incomplete((
             buf,
             ParserState::Head {
@@ -114,14 +148,20 @@ impl RequestParser {
       },
     };
 
+    if headers.len() > route_limits.max_header_count {
+      return ParserStatus::Invalid(RequestParseError::HeadersTooLarge);
+    }
+
     if !header_options.has_host {
-      //TODO: Make this a real error
-      return ParserStatus::Invalid("Invalid Headers".into());
+      return ParserStatus::Invalid(RequestParseError::MissingHost);
     }
 
     let buf_len = buf.len();
 
     if offset >= buf_len {
+      if Self::headers_too_large(buf_len, &head, &route_limits) {
+        return ParserStatus::Invalid(RequestParseError::HeadersTooLarge);
+      }
       return ParserStatus::Incomplete((
         buf,
         ParserState::Headers {
@@ -147,6 +187,9 @@ impl RequestParser {
     let mut lines = lines.peekable();
 
     if let None = lines.peek() {
+      if Self::headers_too_large(buf_len, &head, &route_limits) {
+        return ParserStatus::Invalid(RequestParseError::HeadersTooLarge);
+      }
       return ParserStatus::Incomplete((
         buf,
         ParserState::Headers {
@@ -159,16 +202,67 @@ impl RequestParser {
       ));
     };
 
+    // A body framed in two ways can be read differently by a proxy in front, RFC 9112 section 6.1
+    if header_options.transfer_encoding {
+      if !header_options.chunked {
+        return ParserStatus::Invalid(RequestParseError::InvalidFraming(
+          "the final transfer coding isn't chunked",
+        ));
+      }
+      if header_options.content_length.is_some() {
+        return ParserStatus::Invalid(RequestParseError::InvalidFraming(
+          "both Transfer-Encoding and Content-Length were sent",
+        ));
+      }
+    }
+
+    if header_options.chunked {
+      if buf_len < offset + CRLF_SIZE {
+        return ParserStatus::Incomplete((
+          buf,
+          ParserState::Headers {
+            cursor: offset,
+            read_until: buf_len,
+            head,
+            headers,
+            header_options,
+          },
+        ));
+      }
+
+      let body_start = offset + CRLF_SIZE;
+
+      return ChunkedParser::parse_chunks(
+        buf,
+        ParserState::ChunkedBody {
+          cursor: body_start,
+          read_until: buf_len,
+          head,
+          headers,
+          header_options,
+          body: (body_start, body_start),
+        },
+        &route_limits,
+      );
+    }
+
     let (body, content_length) = if let Some(content_length) = header_options.content_length {
+      if content_length > route_limits.max_body_size {
+        return ParserStatus::Invalid(RequestParseError::PayloadTooLarge);
+      }
+
       if buf_len < offset + CRLF_SIZE + content_length {
+        let body_start = offset + CRLF_SIZE;
+
         return ParserStatus::Incomplete((
           buf,
-          ParserState::Headers {
+          ParserState::Body {
             cursor: offset,
             read_until: buf_len,
             head,
             headers,
             header_options,
+            body: (body_start, buf_len.max(body_start)),
           },
         ));
       }
@@ -207,6 +301,26 @@ impl RequestParser {
     ));
   }
 
+  /// Everything after the head line counts towards the headers size until the empty line is read.
+  fn headers_too_large(buf_len: usize, head: &RequestHead, limits: &RequestLimits) -> bool {
+    buf_len > head.http_version.1 + 1 + limits.max_header_bytes
+  }
+
+  /// Limits of the route the request targets
+  pub fn route_limits(
+    buf: &[u8],
+    head: &RequestHead,
+    limits: &impl ResolveLimits,
+  ) -> RequestLimits {
+    let method = std::str::from_utf8(&buf[head.method.0..head.method.1]);
+    let path = std::str::from_utf8(&buf[head.path.0..head.path.1]);
+
+    match (method, path) {
+      (Ok(method), Ok(path)) => limits.route_limits(method, path),
+      _ => *limits.limits(),
+    }
+  }
+
   pub fn split_buf_lines<'a>(buf: &'a [u8]) -> Split<'a, u8, impl FnMut(&u8) -> bool + Clone> {
     buf.split(|c| c == &LF)
   }
@@ -214,13 +328,17 @@ impl RequestParser {
 
 #[cfg(test)]
 mod unit_tests {
-  use crate::request::{ParserState, RequestParser};
+  use crate::request::{ParserState, ParserStatus, Request, RequestLimits, RequestParser};
 
   #[tokio::test]
   async fn parser_invalid_header_error() {
     let buf = b"GET /server_error123 HTTP/1.1\r\nHost: localhost:7070\r\nUser-Agent:";
 
-    let parse = RequestParser::parse_request(buf.into(), ParserState::Start { read_until: None });
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
 
     assert!(
       !parse.is_success(),
@@ -233,7 +351,11 @@ mod unit_tests {
     let buf =
       b"GET / HTTP/1.0\r\nHost: localhost:3000\r\nThe empty line before the body is missing";
 
-    let parse = RequestParser::parse_request(buf.into(), ParserState::Start { read_until: None });
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
 
     assert!(
       !parse.is_success(),
@@ -246,7 +368,11 @@ mod unit_tests {
     let buf =
       b"GET / HTTP/1.1\r\nHost: localhost:3000\r\nx-custom-header:invalid\r\nThe empty line before the body is missing";
 
-    let parse = RequestParser::parse_request(buf.into(), ParserState::Start { read_until: None });
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
 
     assert!(!parse.is_success(), "Parse should return a Invalid HEADER");
   }
@@ -256,7 +382,11 @@ mod unit_tests {
     let buf =
       b"GET / HTTP/1.1\r\nHost: localhost:3000\r\nThe empty line before the body is missing";
 
-    let parse = RequestParser::parse_request(buf.into(), ParserState::Start { read_until: None });
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
 
     assert!(
       parse.is_incomplete(),
@@ -269,7 +399,11 @@ mod unit_tests {
     let buf =
       b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nContent-Length: 14\r\n\r\n{\"valid\":true}";
 
-    let parse = RequestParser::parse_request(buf.into(), ParserState::Start { read_until: None });
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
 
     assert!(
       parse.is_success(),
@@ -281,7 +415,11 @@ mod unit_tests {
   async fn respect_content_length_incomplete() {
     let buf = b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nContent-Length: 14\r\n\r\n{\"vali";
 
-    let parse = RequestParser::parse_request(buf.into(), ParserState::Start { read_until: None });
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
 
     assert!(
       parse.is_incomplete(),
@@ -293,8 +431,162 @@ mod unit_tests {
   async fn invalid_head_version() {
     let buf = b"POST / HTTP/1.0\r\nHost: localhost:3000\r\nContent-Length: 14\r\n\r\n{\"vali";
 
-    let parse = RequestParser::parse_request(buf.into(), ParserState::Start { read_until: None });
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
 
     assert!(parse.is_invalid(), "Header should be invalid");
   }
+
+  #[tokio::test]
+  async fn chunked_body() {
+    let buf = b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nTransfer-Encoding: chunked\r\n\r\n7\r\n{\"valid\r\n7;ext=\"1\"\r\n\":true}\r\n0\r\n\r\n";
+
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
+
+    let ParserStatus::Success(result) = parse else {
+      panic!("Chunked request should be complete {parse:?}");
+    };
+
+    let req = result.into_request();
+
+    assert_eq!(
+      req.body_str().unwrap(),
+      "{\"valid\":true}",
+      "Chunks should be reassembled"
+    );
+  }
+
+  #[tokio::test]
+  async fn chunked_body_incomplete() {
+    let buf = b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nTransfer-Encoding: chunked\r\n\r\n7\r\n{\"valid\r\n7\r\n\":t";
+
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
+
+    let ParserStatus::Incomplete((mut buf, state)) = parse else {
+      panic!("Chunked request should be incomplete {parse:?}");
+    };
+
+    buf.extend_from_slice(b"rue}\r\n0\r\nx-checksum: 1234\r\n\r\n");
+
+    let ParserStatus::Success(result) =
+      RequestParser::parse_request(buf, state, &RequestLimits::default())
+    else {
+      panic!("Chunked request should be complete after the last chunk");
+    };
+
+    let mut req = result.into_request();
+
+    assert_eq!(
+      req.body_str().unwrap(),
+      "{\"valid\":true}",
+      "Chunks should be reassembled"
+    );
+    assert_eq!(
+      req.headers().get("x-checksum").map(String::as_str),
+      Some("1234"),
+      "Trailers should be added to the headers"
+    );
+  }
+
+  #[tokio::test]
+  async fn chunked_invalid_size() {
+    let buf = b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nhello\r\n0\r\n\r\n";
+
+    let parse = RequestParser::parse_request(
+      buf.into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
+
+    assert!(parse.is_invalid(), "Chunk size should be invalid");
+  }
+
+  #[tokio::test]
+  async fn non_utf8_target() {
+    let parse = RequestParser::parse_request(
+      b"GET /caf\xe9?q=\xff HTTP/1.1\r\nHost: localhost:3000\r\n\r\n".into(),
+      ParserState::Start { read_until: None },
+      &RequestLimits::default(),
+    );
+
+    let ParserStatus::Invalid(err) = parse else {
+      panic!("A target that isn't UTF-8 should be invalid");
+    };
+    assert_eq!(err.status(), 400);
+  }
+
+  #[tokio::test]
+  async fn ambiguous_framing() {
+    let parse = |buf: &[u8]| {
+      RequestParser::parse_request(
+        buf.into(),
+        ParserState::Start { read_until: None },
+        &RequestLimits::default(),
+      )
+    };
+
+    let not_chunked = parse(b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nTransfer-Encoding: chunked, gzip\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(not_chunked.is_invalid(), "The final coding must be chunked");
+
+    let both = parse(b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+    assert!(
+      both.is_invalid(),
+      "Content-Length can't be sent with Transfer-Encoding"
+    );
+
+    let overridden = parse(b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n0\r\n\r\n");
+    assert!(
+      overridden.is_invalid(),
+      "The last Transfer-Encoding header decides"
+    );
+  }
+
+  #[tokio::test]
+  async fn decoded_query() {
+    let mut req = Request::from_string(
+      "GET /search?q=two+words&tag=a&tag=b%26c&empty HTTP/1.1\r\nHost: localhost:3000\r\n\r\n"
+        .to_owned(),
+    );
+
+    assert_eq!(req.raw_query(), "q=two+words&tag=a&tag=b%26c&empty");
+    assert_eq!(
+      serde_json::Value::Object(std::mem::take(&mut req.query)),
+      serde_json::json!({ "q": "two words", "tag": ["a", "b&c"], "empty": "" })
+    );
+  }
+
+  #[tokio::test]
+  async fn duplicate_headers() {
+    let mut req = Request::from_string(
+      "GET / HTTP/1.1\r\nHost: localhost:3000\r\nAccept: text/html\r\nCookie: a=1\r\naccept: application/json\r\nCOOKIE: b=2\r\n\r\n"
+        .to_owned(),
+    );
+
+    assert_eq!(
+      req.header("ACCEPT".to_owned()).as_deref(),
+      Some("text/html, application/json")
+    );
+    assert_eq!(req.header("cookie".to_owned()).as_deref(), Some("a=1; b=2"));
+    assert_eq!(
+      req.header_all("Accept".to_owned()),
+      ["text/html", "application/json"]
+    );
+    assert_eq!(req.header("x-missing".to_owned()), None);
+
+    let headers = req.headers();
+    assert_eq!(headers["accept"], "text/html, application/json");
+    assert_eq!(headers["cookie"], "a=1; b=2");
+    assert_eq!(headers["host"], "localhost:3000");
+  }
 }
@@ -1,6 +1,11 @@
 use std::collections::HashMap;
 
-use crate::request::{HeaderOptions, Request, RequestHead, RequestHeaders, VecOffset};
+use crate::{
+  request::{HeaderOptions, Request, RequestHead, RequestHeaders, RequestLimits, VecOffset},
+  utils::urlencoded,
+};
+
+use super::{decode_content, RequestParseError};
 
 #[derive(Debug)]
 pub struct ParserResult {
@@ -12,19 +17,75 @@ pub struct ParserResult {
 }
 
 impl ParserResult {
+  /// Replaces a body sent with a `Content-Encoding` by its decoded bytes and drops the
+  /// `Content-Encoding` and `Content-Length` headers, which no longer describe it.
+  /// Decoding runs with `spawn_blocking` so inflating a large body doesn't stall the worker.
+  pub async fn decode_body(self, limits: &RequestLimits) -> Result<Self, RequestParseError> {
+    if !self
+      .headers
+      .iter()
+      .any(|header| is_content_encoding(&self.buf, header))
+    {
+      return Ok(self);
+    }
+
+    let max_size = limits.max_decompressed_size;
+    tokio::task::spawn_blocking(move || self.decode_body_blocking(max_size))
+      .await
+      .map_err(|_| RequestParseError::Malformed)?
+  }
+
+  fn decode_body_blocking(mut self, max_size: usize) -> Result<Self, RequestParseError> {
+    let content_encoding = self
+      .headers
+      .iter()
+      .filter(|header| is_content_encoding(&self.buf, header))
+      .map(|(_, value)| &self.buf[value.0..value.1]);
+
+    let (start, end) = self.body;
+    let Some(decoded) = decode_content(&self.buf[start..end], content_encoding, max_size)? else {
+      return Ok(self);
+    };
+
+    // The decoded body takes the place of the encoded one, chunked trailers after it are moved
+    let mut buf = Vec::with_capacity(self.buf.len() - (end - start) + decoded.len());
+    buf.extend_from_slice(&self.buf[..start]);
+    buf.extend_from_slice(&decoded);
+    buf.extend_from_slice(&self.buf[end..]);
+
+    let shift = |offset: &mut usize| {
+      if *offset >= end {
+        *offset = *offset - (end - start) + decoded.len();
+      }
+    };
+
+    self.headers.retain(|header| {
+      let (name, _) = header;
+      !is_content_encoding(&self.buf, header)
+        && !self.buf[name.0..name.1].eq_ignore_ascii_case(b"content-length")
+    });
+    for (name, value) in self.headers.iter_mut() {
+      shift(&mut name.0);
+      shift(&mut name.1);
+      shift(&mut value.0);
+      shift(&mut value.1);
+    }
+
+    self.body = (start, start + decoded.len());
+    self.buf = buf;
+
+    Ok(self)
+  }
+
   pub fn into_request(self) -> Request {
+    // Safety: `RequestHead` only accepts UTF-8 paths
     let path =
       unsafe { std::str::from_utf8_unchecked(&self.buf[self.head.path.0..self.head.path.1]) };
 
     let query = {
       let (_, query) = path.split_once('?').unwrap_or(("", ""));
 
-      query
-        .to_owned()
-        .split("&")
-        .map(|p| p.split_once("=").unwrap_or((p, "")))
-        .map(|(k, v)| (k.to_owned(), v.to_owned()))
-        .collect::<HashMap<String, String>>()
+      urlencoded::decode_object(query.as_bytes())
     };
 
     return Request::new(
@@ -40,3 +101,7 @@ impl ParserResult {
     );
   }
 }
+
+fn is_content_encoding(buf: &[u8], (name, _): &(VecOffset, VecOffset)) -> bool {
+  buf[name.0..name.1].eq_ignore_ascii_case(b"content-encoding")
+}
@@ -33,6 +33,14 @@ pub enum ParserState {
     header_options: HeaderOptions,
     body: VecOffset,
   },
+  ChunkedBody {
+    cursor: usize,
+    read_until: usize,
+    head: RequestHead,
+    headers: RequestHeaders,
+    header_options: HeaderOptions,
+    body: VecOffset,
+  },
 }
 
 pub struct FullParserState {
@@ -94,6 +102,14 @@ impl FullParserState {
         headers,
         header_options,
         body,
+      }
+      | ParserState::ChunkedBody {
+        cursor,
+        read_until,
+        head,
+        headers,
+        header_options,
+        body,
       } => FullParserState {
         cursor: Some(cursor),
         read_until: Some(read_until),
@@ -112,6 +128,7 @@ impl ParserState {
       ParserState::Head { read_until, .. } => *read_until,
       ParserState::Headers { read_until, .. } => *read_until,
       ParserState::Body { read_until, .. } => *read_until,
+      ParserState::ChunkedBody { read_until, .. } => *read_until,
     }
   }
 
@@ -122,27 +139,34 @@ impl ParserState {
     }
   }
 
+  pub fn is_chunked_body(&self) -> bool {
+    matches!(self, ParserState::ChunkedBody { .. })
+  }
+
   pub fn head(&self) -> Option<&RequestHead> {
     match self {
       ParserState::Head { head, .. }
       | ParserState::Headers { head, .. }
-      | ParserState::Body { head, .. } => Some(head),
+      | ParserState::Body { head, .. }
+      | ParserState::ChunkedBody { head, .. } => Some(head),
       _ => None,
     }
   }
 
   pub fn headers(&self) -> Option<&RequestHeaders> {
     match self {
-      ParserState::Headers { headers, .. } | ParserState::Body { headers, .. } => Some(headers),
+      ParserState::Headers { headers, .. }
+      | ParserState::Body { headers, .. }
+      | ParserState::ChunkedBody { headers, .. } => Some(headers),
       _ => None,
     }
   }
 
   pub fn header_options(&self) -> Option<&HeaderOptions> {
     match self {
-      ParserState::Headers { header_options, .. } | ParserState::Body { header_options, .. } => {
-        Some(header_options)
-      }
+      ParserState::Headers { header_options, .. }
+      | ParserState::Body { header_options, .. }
+      | ParserState::ChunkedBody { header_options, .. } => Some(header_options),
       _ => None,
     }
   }
@@ -1,10 +1,10 @@
-use super::{ParserResult, ParserState};
+use super::{ParserResult, ParserState, RequestParseError};
 
 #[derive(Debug)]
 pub enum ParserStatus {
   Success(ParserResult),
   Incomplete((Vec<u8>, ParserState)),
-  Invalid(String),
+  Invalid(RequestParseError),
 }
 
 impl ParserStatus {
@@ -1,9 +1,16 @@
 use core::str;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{btree_map::Entry, BTreeMap, HashMap};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use napi::{Env, JsArrayBuffer, JsBuffer, JsObject, Status};
 
 use super::{
-  options::Connection, RequestHead, RequestHeaders, RequestOptions, RequestParser, VecOffset,
+  options::Connection, parse_cookies, BodyParseError, FormBody, JsonBody, Multipart, MultipartBody,
+  MultipartLimits, RequestHead, RequestHeaders, RequestOptions, RequestParser, TempFiles,
+  VecOffset,
 };
+use crate::{error::AouError, session::AouSession, utils};
 
 use napi_derive::napi;
 use serde_json::Map;
@@ -11,7 +18,7 @@ use serde_json::Map;
 #[napi(js_name = "AouRequest")]
 #[derive(Debug)]
 pub struct Request {
-  buf: Vec<u8>,
+  buf: Bytes,
   head: RequestHead,
   headers: RequestHeaders,
   body: VecOffset,
@@ -19,9 +26,16 @@ pub struct Request {
   pub context: serde_json::Value,
   #[napi(ts_type = "{}")]
   pub params: HashMap<String, String>,
-  pub query: HashMap<String, String>,
+  /// The decoded query string, keys repeated in it become arrays
+  #[napi(ts_type = "Record<string, any>")]
+  pub query: Map<String, serde_json::Value>,
   options: RequestOptions,
   cache: RequestFieldCache,
+  multipart_limits: MultipartLimits,
+  /// Multipart body parsed while it was read, `body` is empty then
+  spilled_multipart: Option<Arc<Result<Multipart, BodyParseError>>>,
+  temp_files: TempFiles,
+  session: Option<AouSession>,
 }
 
 #[derive(Debug)]
@@ -30,7 +44,7 @@ struct RequestFieldCache {
   method: Option<String>,
   http_version: Option<String>,
   headers: Option<BTreeMap<String, String>>,
-  body: Option<String>,
+  cookies: Option<HashMap<String, String>>,
 }
 impl Default for RequestFieldCache {
   fn default() -> Self {
@@ -39,7 +53,7 @@ impl Default for RequestFieldCache {
       method: None,
       http_version: None,
       headers: None,
-      body: None,
+      cookies: None,
     }
   }
 }
@@ -58,6 +72,10 @@ impl Default for Request {
         connection: Connection::KeepAlive,
       },
       cache: Default::default(),
+      multipart_limits: Default::default(),
+      spilled_multipart: None,
+      temp_files: Default::default(),
+      session: None,
     }
   }
 }
@@ -69,12 +87,12 @@ impl Request {
     head: RequestHead,
     headers: RequestHeaders,
     body: VecOffset,
-    query: HashMap<String, String>,
+    query: Map<String, serde_json::Value>,
     params: HashMap<String, String>,
     options: RequestOptions,
   ) -> Request {
     Request {
-      buf,
+      buf: Bytes::from(buf),
       head,
       headers,
       body,
@@ -91,6 +109,7 @@ impl Request {
     let parse = RequestParser::parse_request(
       Vec::from(request.as_bytes()),
       super::ParserState::Start { read_until: None },
+      &super::RequestLimits::default(),
     );
 
     let req = match parse {
@@ -110,6 +129,7 @@ impl Request {
     if self.cache.method.is_some() {
       return self.cache.method.as_ref().unwrap();
     }
+    // Safety: `RequestHead` only accepts ASCII methods
     let method =
       unsafe { std::str::from_utf8_unchecked(&self.buf[self.head.method.0..self.head.method.1]) };
 
@@ -118,17 +138,37 @@ impl Request {
     method
   }
 
+  /// The percent-decoded path with its dot segments removed, the one routes are matched against.
+  /// `%2F` and `%25` are kept encoded so it splits on `/` like the raw path
   #[napi(getter)]
   pub fn path(&mut self) -> &str {
-    if self.cache.path.is_some() {
-      return self.cache.path.as_ref().unwrap();
+    if self.cache.path.is_none() {
+      let raw_path = self.raw_path();
+      let path = utils::path::normalize(raw_path).unwrap_or_else(|_| raw_path.to_owned());
+
+      self.cache.path = Some(path);
     }
-    let path =
-      unsafe { std::str::from_utf8_unchecked(&self.buf[self.head.path.0..self.head.path.1]) };
 
-    self.cache.path = Some(path.to_string());
+    self.cache.path.as_ref().unwrap()
+  }
+
+  pub fn set_path(&mut self, path: String) {
+    self.cache.path = Some(path);
+  }
+
+  /// The path as it was received, without the query string
+  #[napi(getter)]
+  pub fn raw_path(&self) -> &str {
+    self
+      .target()
+      .split_once('?')
+      .map_or(self.target(), |(path, _)| path)
+  }
 
-    path
+  /// The query string after the `?`, still percent-encoded
+  #[napi(getter)]
+  pub fn raw_query(&self) -> &str {
+    self.target().split_once('?').map_or("", |(_, query)| query)
   }
 
   #[napi(getter)]
@@ -145,20 +185,30 @@ impl Request {
     http_version
   }
 
+  /// Every header keyed by its lowercased name. Repeated headers are combined into one value
+  /// separated by `, `, or by `; ` for `Cookie`
   #[napi(getter)]
   pub fn headers(&mut self) -> BTreeMap<String, String> {
-    if self.cache.headers.is_some() {
-      return self.cache.headers.as_ref().unwrap().clone();
+    if let Some(headers) = &self.cache.headers {
+      return headers.clone();
     }
 
     let mut map = BTreeMap::<String, String>::new();
-    unsafe {
-      self.headers.iter().for_each(|v| {
-        map.insert(
-          std::str::from_utf8_unchecked(&self.buf[v.0 .0..v.0 .1]).to_string(),
-          std::str::from_utf8_unchecked(&self.buf[v.1 .0..v.1 .1]).to_string(),
-        );
-      });
+    for (name, value) in &self.headers {
+      let name = String::from_utf8_lossy(&self.buf[name.0..name.1]).to_ascii_lowercase();
+      let value = String::from_utf8_lossy(&self.buf[value.0..value.1]);
+
+      match map.entry(name) {
+        Entry::Occupied(mut entry) => {
+          let separator = header_separator(entry.key().as_bytes());
+          let combined = entry.get_mut();
+          combined.push_str(separator);
+          combined.push_str(&value);
+        }
+        Entry::Vacant(entry) => {
+          entry.insert(value.into_owned());
+        }
+      }
     }
 
     self.cache.headers = Some(map.clone());
@@ -166,19 +216,190 @@ impl Request {
     map
   }
 
+  /// Cookies sent in the `Cookie` headers, with their values percent-decoded
   #[napi(getter)]
-  pub fn body(&mut self) -> &str {
-    if self.cache.body.is_some() {
-      return self.cache.body.as_ref().unwrap();
+  pub fn cookies(&mut self) -> HashMap<String, String> {
+    if let Some(cookies) = &self.cache.cookies {
+      return cookies.clone();
     }
 
-    let body = unsafe { std::str::from_utf8_unchecked(&self.buf[self.body.0..self.body.1]) };
-    self.cache.body = Some(body.to_string());
+    let cookies = parse_cookies(self.header_values(b"cookie"));
+    self.cache.cookies = Some(cookies.clone());
+
+    cookies
+  }
+
+  /// The cookie session, `null` unless `AouOptions.session` is set
+  #[napi(getter)]
+  pub fn session(&self) -> Option<AouSession> {
+    self.session.clone()
+  }
 
-    body
+  pub fn set_session(&mut self, session: AouSession) {
+    self.session = Some(session);
+  }
+
+  /// Value of the header named `name`, compared case-insensitively.
+  /// Repeated headers are combined like in `headers`
+  #[napi]
+  pub fn header(&self, name: String) -> Option<String> {
+    let values = self.header_all(name.clone());
+
+    (!values.is_empty()).then(|| values.join(header_separator(name.as_bytes())))
+  }
+
+  /// Every value of the header named `name`, compared case-insensitively, in the order they were received
+  #[napi]
+  pub fn header_all(&self, name: String) -> Vec<String> {
+    self
+      .header_values(name.as_bytes())
+      .map(|value| String::from_utf8_lossy(value).into_owned())
+      .collect()
+  }
+
+  /// The body decoded as UTF-8 text, throws if it isn't valid UTF-8
+  #[napi(getter)]
+  pub fn body(&self) -> napi::Result<&str> {
+    self.body_str().map_err(|err| {
+      napi::Error::new(
+        Status::InvalidArg,
+        format!("Request body is not valid UTF-8: {err}"),
+      )
+    })
+  }
+
+  /// A copy of the raw body bytes as a `Buffer`, writing to it doesn't change the request
+  #[napi]
+  pub fn buffer(&self, env: Env) -> napi::Result<JsBuffer> {
+    // `create_buffer_copy` can't copy an empty body
+    let body = self.body_bytes().to_vec();
+
+    Ok(env.create_buffer_with_data(body)?.into_raw())
+  }
+
+  /// A copy of the raw body bytes as an `ArrayBuffer`, writing to it doesn't change the request
+  #[napi]
+  pub fn array_buffer(&self, env: Env) -> napi::Result<JsArrayBuffer> {
+    let body = self.body_bytes().to_vec();
+
+    Ok(env.create_arraybuffer_with_data(body)?.into_raw())
+  }
+
+  /// Parses the body as JSON off the JS thread.
+  /// Rejects with a 415 `AouError` if the `Content-Type` isn't JSON or a 400 one if the body is malformed
+  #[napi(ts_return_type = "Promise<any>")]
+  pub fn json(&self, env: Env) -> napi::Result<JsObject> {
+    let content_type = self.find_header(b"content-type").map(<[u8]>::to_vec);
+    let body = self.body_bytes();
+
+    env.execute_tokio_future(
+      async move { Ok(JsonBody::parse(content_type.as_deref(), &body)) },
+      |env, parsed| match parsed {
+        Ok(value) => Ok(value),
+        Err(err) => Err(AouError::from(err).into_js_error(env)?),
+      },
+    )
+  }
+
+  /// Decodes an `application/x-www-form-urlencoded` body off the JS thread, repeated keys become arrays.
+  /// Rejects with a 415 `AouError` if the `Content-Type` isn't a urlencoded form
+  #[napi(ts_return_type = "Promise<Record<string, string | string[]>>")]
+  pub fn form(&self, env: Env) -> napi::Result<JsObject> {
+    let content_type = self.find_header(b"content-type").map(<[u8]>::to_vec);
+    let body = self.body_bytes();
+
+    env.execute_tokio_future(
+      async move { Ok(FormBody::parse(content_type.as_deref(), &body)) },
+      |env, parsed| match parsed {
+        Ok(form) => Ok(serde_json::Value::Object(form)),
+        Err(err) => Err(AouError::from(err).into_js_error(env)?),
+      },
+    )
+  }
+
+  /// Parses a `multipart/form-data` body off the JS thread into its text fields and files.
+  /// Rejects with a 415 `AouError` if the `Content-Type` isn't multipart, a 400 if the body is malformed
+  /// or a 413 if a part is over the multipart limits
+  #[napi(ts_return_type = "Promise<AouMultipart>")]
+  pub fn multipart(&self, env: Env) -> napi::Result<JsObject> {
+    let content_type = self.find_header(b"content-type").map(<[u8]>::to_vec);
+    let body = self.body_bytes();
+    let limits = self.multipart_limits;
+    let spilled = self.spilled_multipart.clone();
+
+    env.execute_tokio_future(
+      async move {
+        match spilled {
+          Some(parsed) => Ok(parsed),
+          None => Ok(Arc::new(
+            MultipartBody::parse(content_type.as_deref(), &body, &limits).await,
+          )),
+        }
+      },
+      |env, parsed| match parsed.as_ref() {
+        Ok(multipart) => multipart.to_form(env),
+        Err(err) => Err(AouError::from(err).into_js_error(env)?),
+      },
+    )
+  }
+
+  pub fn set_multipart_limits(&mut self, limits: MultipartLimits) {
+    self.multipart_limits = limits;
+  }
+
+  pub fn set_spilled_multipart(
+    &mut self,
+    parsed: Result<Multipart, BodyParseError>,
+    temp_files: TempFiles,
+  ) {
+    self.spilled_multipart = Some(Arc::new(parsed));
+    self.temp_files = temp_files;
+  }
+
+  /// Temp files written while reading the request
+  pub fn temp_files(&self) -> &TempFiles {
+    &self.temp_files
+  }
+
+  /// The request target as received, path and query string
+  fn target(&self) -> &str {
+    // Safety: `RequestHead` only accepts UTF-8 paths
+    unsafe { std::str::from_utf8_unchecked(&self.buf[self.head.path.0..self.head.path.1]) }
+  }
+
+  /// Value of the first header named `name`, compared case-insensitively
+  fn find_header<'a>(&'a self, name: &'a [u8]) -> Option<&'a [u8]> {
+    self.header_values(name).next()
+  }
+
+  /// Values of every header named `name`, compared case-insensitively
+  pub fn header_values<'a>(&'a self, name: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+    self
+      .headers
+      .iter()
+      .filter(move |(header, _)| self.buf[header.0..header.1].eq_ignore_ascii_case(name))
+      .map(|(_, value)| &self.buf[value.0..value.1])
+  }
+
+  pub fn body_str(&self) -> Result<&str, str::Utf8Error> {
+    str::from_utf8(&self.buf[self.body.0..self.body.1])
+  }
+
+  /// A cheap reference counted slice of the body
+  pub fn body_bytes(&self) -> Bytes {
+    self.buf.slice(self.body.0..self.body.1)
   }
 
   pub fn get_connection(&self) -> &Connection {
     &self.options.connection
   }
 }
+
+/// `Cookie` pairs are separated by `; `, every other repeated header is a comma-separated list
+fn header_separator(name: &[u8]) -> &'static str {
+  if name.eq_ignore_ascii_case(b"cookie") {
+    "; "
+  } else {
+    ", "
+  }
+}
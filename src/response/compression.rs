@@ -0,0 +1,227 @@
+use std::io::{self, Write};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::constants::COMPRESSION_BLOCKING_SIZE;
+
+/// Quality of brotli compression (0-11), higher levels are too slow for responses compressed on every request
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Content codings the server can compress responses with, in order of preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+  Brotli,
+  Gzip,
+  Deflate,
+}
+
+impl ContentEncoding {
+  const PREFERENCE: [ContentEncoding; 3] = [
+    ContentEncoding::Brotli,
+    ContentEncoding::Gzip,
+    ContentEncoding::Deflate,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ContentEncoding::Brotli => "br",
+      ContentEncoding::Gzip => "gzip",
+      ContentEncoding::Deflate => "deflate",
+    }
+  }
+
+  pub fn compress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+    match self {
+      ContentEncoding::Brotli => {
+        let mut encoder = brotli::CompressorWriter::new(
+          Vec::new(),
+          BROTLI_BUFFER_SIZE,
+          BROTLI_QUALITY,
+          BROTLI_WINDOW,
+        );
+        encoder.write_all(buf)?;
+        Ok(encoder.into_inner())
+      }
+      ContentEncoding::Gzip => {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(buf)?;
+        encoder.finish()
+      }
+      // HTTP's `deflate` is the zlib format, not raw deflate
+      ContentEncoding::Deflate => {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(buf)?;
+        encoder.finish()
+      }
+    }
+  }
+
+  /// Compresses bodies of at least `COMPRESSION_BLOCKING_SIZE` bytes with `spawn_blocking`,
+  /// so a large response doesn't stall the other connections of the worker
+  pub async fn compress_blocking(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+    if buf.len() < COMPRESSION_BLOCKING_SIZE {
+      return self.compress(buf);
+    }
+
+    let (encoding, buf) = (*self, buf.to_vec());
+    tokio::task::spawn_blocking(move || encoding.compress(&buf))
+      .await
+      .map_err(io::Error::other)?
+  }
+
+  /// Picks the coding with the highest `q` value in the `Accept-Encoding` headers,
+  /// ties are broken by the server's preference. `None` when only `identity` is acceptable.
+  pub fn negotiate<'a>(accept_encoding: impl Iterator<Item = &'a [u8]>) -> Option<ContentEncoding> {
+    let mut qualities: [Option<f32>; 3] = [None; 3];
+    let mut wildcard = None;
+
+    for header in accept_encoding {
+      for coding in header.split(|b| b == &b',') {
+        let mut parts = coding.split(|b| b == &b';');
+        let name = parts.next().unwrap_or_default().trim_ascii();
+        let quality = parts
+          .filter_map(|param| param.trim_ascii().strip_prefix(b"q="))
+          .find_map(|q| std::str::from_utf8(q).ok()?.trim().parse::<f32>().ok())
+          .unwrap_or(1.0);
+
+        if name == b"*" {
+          wildcard = Some(quality);
+          continue;
+        }
+
+        if let Some(index) = Self::PREFERENCE
+          .iter()
+          .position(|encoding| name.eq_ignore_ascii_case(encoding.as_str().as_bytes()))
+        {
+          qualities[index] = Some(quality);
+        }
+      }
+    }
+
+    let mut best: Option<(ContentEncoding, f32)> = None;
+    for (encoding, quality) in Self::PREFERENCE.into_iter().zip(qualities) {
+      let Some(quality) = quality.or(wildcard) else {
+        continue;
+      };
+      if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+        best = Some((encoding, quality));
+      }
+    }
+
+    best.map(|(encoding, _)| encoding)
+  }
+}
+
+/// How a response may be compressed, resolved from the server and route options and the request
+#[derive(Debug, Clone, Copy)]
+pub struct Compression<'a> {
+  /// Negotiated from `Accept-Encoding`, `None` if the client only accepts the identity encoding
+  pub encoding: Option<ContentEncoding>,
+  /// Smaller bodies are sent as is
+  pub min_size: usize,
+  /// Media types to compress, `type/*` matches every subtype
+  pub content_types: &'a [String],
+}
+
+impl Compression<'_> {
+  /// Whether responses with this `Content-Type` are compressed, parameters like `charset` are ignored
+  pub fn compressible(&self, content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+    self
+      .content_types
+      .iter()
+      .any(|pattern| match pattern.strip_suffix("/*") {
+        Some(kind) => media_type
+          .split_once('/')
+          .is_some_and(|(media_kind, _)| media_kind.eq_ignore_ascii_case(kind)),
+        None => media_type.eq_ignore_ascii_case(pattern),
+      })
+  }
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use std::io::Read;
+
+  use super::{Compression, ContentEncoding};
+
+  #[test]
+  fn negotiate_encoding() {
+    let negotiate = |header: &[u8]| ContentEncoding::negotiate([header].into_iter());
+
+    assert_eq!(
+      negotiate(b"gzip, compress, deflate, br"),
+      Some(ContentEncoding::Brotli)
+    );
+    assert_eq!(
+      negotiate(b"gzip;q=0.8, deflate"),
+      Some(ContentEncoding::Deflate)
+    );
+    assert_eq!(negotiate(b"br;q=0, *"), Some(ContentEncoding::Gzip));
+    assert_eq!(negotiate(b"GZIP"), Some(ContentEncoding::Gzip));
+    assert_eq!(negotiate(b"identity"), None);
+    assert_eq!(negotiate(b"*;q=0"), None);
+    assert_eq!(ContentEncoding::negotiate(std::iter::empty()), None);
+  }
+
+  #[test]
+  fn compress_round_trip() {
+    let body = "hello compression ".repeat(100);
+
+    let mut decoded = String::new();
+    let compressed = ContentEncoding::Gzip.compress(body.as_bytes()).unwrap();
+    flate2::read::GzDecoder::new(&compressed[..])
+      .read_to_string(&mut decoded)
+      .unwrap();
+    assert_eq!(decoded, body);
+    assert!(compressed.len() < body.len());
+
+    let mut decoded = String::new();
+    let compressed = ContentEncoding::Deflate.compress(body.as_bytes()).unwrap();
+    flate2::read::ZlibDecoder::new(&compressed[..])
+      .read_to_string(&mut decoded)
+      .unwrap();
+    assert_eq!(decoded, body);
+
+    let mut decoded = String::new();
+    let compressed = ContentEncoding::Brotli.compress(body.as_bytes()).unwrap();
+    brotli::Decompressor::new(&compressed[..], 4096)
+      .read_to_string(&mut decoded)
+      .unwrap();
+    assert_eq!(decoded, body);
+  }
+
+  #[tokio::test]
+  async fn compress_large_bodies_off_the_worker() {
+    let body = "hello compression ".repeat(10_000);
+    let compressed = ContentEncoding::Gzip
+      .compress_blocking(body.as_bytes())
+      .await
+      .unwrap();
+
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(&compressed[..])
+      .read_to_string(&mut decoded)
+      .unwrap();
+    assert_eq!(decoded, body);
+  }
+
+  #[test]
+  fn compressible_content_types() {
+    let content_types = ["text/*".to_owned(), "application/json".to_owned()];
+    let compression = Compression {
+      encoding: Some(ContentEncoding::Gzip),
+      min_size: 0,
+      content_types: &content_types,
+    };
+
+    assert!(compression.compressible("text/html; charset=utf-8"));
+    assert!(compression.compressible("Application/JSON"));
+    assert!(!compression.compressible("image/png"));
+    assert!(!compression.compressible("application/jsonp"));
+    assert!(!compression.compressible(""));
+  }
+}
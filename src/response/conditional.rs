@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+use super::{find_header, ContentEncoding, ResponseHeaders};
+
+/// Headers a 304 keeps from the response it replaces, the ones a cache updates its stored copy with
+const NOT_MODIFIED_HEADERS: [&str; 6] = [
+  "Cache-Control",
+  "Content-Location",
+  "Date",
+  "ETag",
+  "Expires",
+  "Vary",
+];
+
+/// Kind of `ETag` generated from response bodies
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ETagMode {
+  /// Changes with every byte of the body, compressed bodies get their own
+  #[default]
+  Strong,
+  /// `W/` tag shared by every encoding of the body
+  Weak,
+}
+
+impl FromStr for ETagMode {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "strong" => Ok(ETagMode::Strong),
+      "weak" => Ok(ETagMode::Weak),
+      _ => Err(format!(
+        "Invalid etag \"{s}\", expected \"strong\" or \"weak\""
+      )),
+    }
+  }
+}
+
+impl ETagMode {
+  /// Tag made of the SHA-256 of the uncompressed `body`, a strong one also names the `encoding` it is sent with
+  pub fn etag(&self, body: &[u8], encoding: Option<ContentEncoding>) -> String {
+    let hash = URL_SAFE_NO_PAD.encode(&Sha256::digest(body)[..16]);
+
+    match (self, encoding) {
+      (ETagMode::Weak, _) => format!("W/\"{hash}\""),
+      (ETagMode::Strong, None) => format!("\"{hash}\""),
+      (ETagMode::Strong, Some(encoding)) => format!("\"{hash}-{}\"", encoding.as_str()),
+    }
+  }
+}
+
+/// The `If-None-Match` and `If-Modified-Since` headers of a GET or HEAD request
+#[derive(Debug, Clone, Default)]
+pub struct ConditionalRequest {
+  pub if_none_match: Option<String>,
+  pub if_modified_since: Option<String>,
+}
+
+impl ConditionalRequest {
+  /// Whether the client's copy is still current according to the response `headers`.
+  /// `If-Modified-Since` is ignored when the request also has an `If-None-Match`
+  pub fn not_modified(&self, headers: &ResponseHeaders) -> bool {
+    let header = |name| {
+      find_header(headers, name).and_then(|(_, value)| value.values().first().map(|v| v.trim()))
+    };
+
+    if let Some(if_none_match) = &self.if_none_match {
+      return match if_none_match.trim() {
+        "*" => true,
+        list => header("ETag").is_some_and(|etag| etag_list_contains(list, etag)),
+      };
+    }
+
+    match (
+      self
+        .if_modified_since
+        .as_deref()
+        .map(httpdate::parse_http_date),
+      header("Last-Modified").map(httpdate::parse_http_date),
+    ) {
+      (Some(Ok(since)), Some(Ok(last_modified))) => last_modified <= since,
+      _ => false,
+    }
+  }
+}
+
+/// Weak comparison of `etag` with the entity tags of an `If-None-Match` list, which can contain commas
+fn etag_list_contains(list: &str, etag: &str) -> bool {
+  let etag = etag.strip_prefix("W/").unwrap_or(etag);
+  let mut rest = list;
+
+  loop {
+    rest = rest.trim_start_matches([' ', '\t', ',']);
+    let tag = rest.strip_prefix("W/").unwrap_or(rest);
+    let Some(end) = tag
+      .strip_prefix('"')
+      .and_then(|quoted| quoted.find('"'))
+      .map(|end| end + 2)
+    else {
+      return false;
+    };
+
+    if &tag[..end] == etag {
+      return true;
+    }
+    rest = &tag[end..];
+  }
+}
+
+/// Headers of the 304 sent instead of a response with `headers`
+pub fn not_modified_headers(headers: &ResponseHeaders) -> ResponseHeaders {
+  headers
+    .iter()
+    .filter(|(key, _)| {
+      NOT_MODIFIED_HEADERS
+        .iter()
+        .any(|name| key.eq_ignore_ascii_case(name))
+    })
+    .map(|(key, value)| (key.clone(), value.clone()))
+    .collect()
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use super::{not_modified_headers, ConditionalRequest, ETagMode};
+  use crate::response::{ContentEncoding, ResponseHeaders};
+
+  #[test]
+  fn generate_etags() {
+    let strong = ETagMode::Strong.etag(b"hello", None);
+    assert!(strong.starts_with('"') && strong.ends_with('"'));
+    assert_eq!(strong, ETagMode::Strong.etag(b"hello", None));
+    assert_ne!(strong, ETagMode::Strong.etag(b"hello!", None));
+
+    assert_eq!(
+      ETagMode::Strong.etag(b"hello", Some(ContentEncoding::Gzip)),
+      format!("{}-gzip\"", &strong[..strong.len() - 1])
+    );
+    assert_eq!(
+      ETagMode::Weak.etag(b"hello", Some(ContentEncoding::Brotli)),
+      format!("W/{strong}")
+    );
+    assert!("medium".parse::<ETagMode>().is_err());
+  }
+
+  #[test]
+  fn evaluate_conditions() {
+    let headers = ResponseHeaders::from([
+      ("etag".to_owned(), "\"a,b\"".into()),
+      (
+        "Last-Modified".to_owned(),
+        "Sun, 06 Nov 1994 08:49:37 GMT".into(),
+      ),
+    ]);
+    let if_none_match = |value: &str| ConditionalRequest {
+      if_none_match: Some(value.to_owned()),
+      if_modified_since: Some("Sun, 06 Nov 1994 08:49:37 GMT".to_owned()),
+    };
+    let if_modified_since = |value: &str| ConditionalRequest {
+      if_none_match: None,
+      if_modified_since: Some(value.to_owned()),
+    };
+
+    assert!(if_none_match("\"a,b\"").not_modified(&headers));
+    assert!(if_none_match("\"x\", W/\"a,b\"").not_modified(&headers));
+    assert!(if_none_match("*").not_modified(&headers));
+    assert!(!if_none_match("\"a\"").not_modified(&headers));
+    assert!(!if_none_match("a,b").not_modified(&headers));
+
+    assert!(if_modified_since("Sun, 06 Nov 1994 08:49:37 GMT").not_modified(&headers));
+    assert!(if_modified_since("Mon, 07 Nov 1994 08:49:37 GMT").not_modified(&headers));
+    assert!(!if_modified_since("Sat, 05 Nov 1994 08:49:37 GMT").not_modified(&headers));
+    assert!(!if_modified_since("yesterday").not_modified(&headers));
+    assert!(!ConditionalRequest::default().not_modified(&headers));
+  }
+
+  #[test]
+  fn keep_cache_headers() {
+    let headers = ResponseHeaders::from([
+      ("ETag".to_owned(), "\"a\"".into()),
+      ("cache-control".to_owned(), "no-cache".into()),
+      ("Content-Type".to_owned(), "text/plain".into()),
+    ]);
+
+    assert_eq!(
+      not_modified_headers(&headers),
+      ResponseHeaders::from([
+        ("ETag".to_owned(), "\"a\"".into()),
+        ("cache-control".to_owned(), "no-cache".into()),
+      ])
+    );
+  }
+}
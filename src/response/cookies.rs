@@ -0,0 +1,221 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use napi::bindgen_prelude::{FromNapiValue, ToNapiValue, TypeName, ValueType};
+use napi::{check_status, sys};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use thiserror::Error;
+
+/// Bytes outside of the RFC 6265 `cookie-octet`s, plus `%` so values decode back to themselves
+const COOKIE_VALUE: &AsciiSet = &CONTROLS
+  .add(b' ')
+  .add(b'"')
+  .add(b',')
+  .add(b';')
+  .add(b'\\')
+  .add(b'%');
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CookieError {
+  #[error("Invalid cookie name {0:?}")]
+  InvalidName(String),
+  #[error("Invalid {attribute} in cookie {name:?}")]
+  InvalidAttribute {
+    name: String,
+    attribute: &'static str,
+  },
+}
+
+/// A cookie set on the client with a `Set-Cookie` header.
+#[napi(object, js_name = "AouCookie")]
+#[derive(Debug, Default, PartialEq)]
+pub struct Cookie {
+  pub name: String,
+  /// Percent-encoded when it contains characters not allowed in cookies
+  pub value: String,
+  pub domain: Option<String>,
+  pub path: Option<String>,
+  pub expires: Option<CookieExpires>,
+  /// Seconds until the cookie expires, `0` or less deletes it
+  pub max_age: Option<i64>,
+  pub secure: Option<bool>,
+  pub http_only: Option<bool>,
+  #[napi(ts_type = "'Strict' | 'Lax' | 'None'")]
+  pub same_site: Option<String>,
+}
+
+impl Cookie {
+  /// Value of the `Set-Cookie` header for this cookie
+  pub fn to_header(&self) -> Result<String, CookieError> {
+    if self.name.is_empty() || !self.name.bytes().all(is_token) {
+      return Err(CookieError::InvalidName(self.name.clone()));
+    }
+
+    let mut header = format!(
+      "{}={}",
+      self.name,
+      utf8_percent_encode(&self.value, COOKIE_VALUE)
+    );
+
+    if let Some(domain) = &self.domain {
+      header.push_str("; Domain=");
+      header.push_str(self.attribute("domain", domain)?);
+    }
+    if let Some(path) = &self.path {
+      header.push_str("; Path=");
+      header.push_str(self.attribute("path", path)?);
+    }
+    if let Some(expires) = &self.expires {
+      header.push_str("; Expires=");
+      header.push_str(&httpdate::fmt_http_date(expires.time()));
+    }
+    if let Some(max_age) = self.max_age {
+      header.push_str(&format!("; Max-Age={max_age}"));
+    }
+    if self.secure == Some(true) {
+      header.push_str("; Secure");
+    }
+    if self.http_only == Some(true) {
+      header.push_str("; HttpOnly");
+    }
+    if let Some(same_site) = &self.same_site {
+      let same_site = ["Strict", "Lax", "None"]
+        .into_iter()
+        .find(|policy| policy.eq_ignore_ascii_case(same_site))
+        .ok_or_else(|| self.invalid("sameSite"))?;
+
+      header.push_str("; SameSite=");
+      header.push_str(same_site);
+    }
+
+    Ok(header)
+  }
+
+  fn attribute<'a>(&self, attribute: &'static str, value: &'a str) -> Result<&'a str, CookieError> {
+    match value.bytes().any(|b| b == b';' || b.is_ascii_control()) {
+      true => Err(self.invalid(attribute)),
+      false => Ok(value),
+    }
+  }
+
+  fn invalid(&self, attribute: &'static str) -> CookieError {
+    CookieError::InvalidAttribute {
+      name: self.name.clone(),
+      attribute,
+    }
+  }
+}
+
+/// RFC 9110 `tchar`
+fn is_token(b: u8) -> bool {
+  b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// `AouCookie.expires`, either a `Date` or milliseconds since the epoch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CookieExpires(pub f64);
+
+impl CookieExpires {
+  pub fn time(&self) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(self.0.max(0.0) as u64)
+  }
+}
+
+impl TypeName for CookieExpires {
+  fn type_name() -> &'static str {
+    "Date | number"
+  }
+
+  fn value_type() -> ValueType {
+    ValueType::Unknown
+  }
+}
+
+impl FromNapiValue for CookieExpires {
+  unsafe fn from_napi_value(env: sys::napi_env, napi_val: sys::napi_value) -> napi::Result<Self> {
+    let mut is_date = false;
+    check_status!(sys::napi_is_date(env, napi_val, &mut is_date))?;
+
+    if !is_date {
+      return Ok(CookieExpires(f64::from_napi_value(env, napi_val)?));
+    }
+
+    let mut time = 0.0;
+    check_status!(sys::napi_get_date_value(env, napi_val, &mut time))?;
+
+    Ok(CookieExpires(time))
+  }
+}
+
+impl ToNapiValue for CookieExpires {
+  unsafe fn to_napi_value(env: sys::napi_env, val: Self) -> napi::Result<sys::napi_value> {
+    let mut date = std::ptr::null_mut();
+    check_status!(sys::napi_create_date(env, val.0, &mut date))?;
+
+    Ok(date)
+  }
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use super::{Cookie, CookieError, CookieExpires};
+
+  #[test]
+  fn serialize_cookie() {
+    let cookie = Cookie {
+      name: "session".to_owned(),
+      value: "two words; café".to_owned(),
+      domain: Some("example.com".to_owned()),
+      path: Some("/".to_owned()),
+      expires: Some(CookieExpires(784111777000.0)),
+      max_age: Some(3600),
+      secure: Some(true),
+      http_only: Some(true),
+      same_site: Some("lax".to_owned()),
+    };
+
+    assert_eq!(
+      cookie.to_header().unwrap(),
+      "session=two%20words%3B%20caf%C3%A9; Domain=example.com; Path=/; \
+       Expires=Sun, 06 Nov 1994 08:49:37 GMT; Max-Age=3600; Secure; HttpOnly; SameSite=Lax"
+    );
+
+    let minimal = Cookie {
+      name: "csrf".to_owned(),
+      value: "x=y".to_owned(),
+      ..Default::default()
+    };
+    assert_eq!(minimal.to_header().unwrap(), "csrf=x=y");
+  }
+
+  #[test]
+  fn invalid_cookies() {
+    let invalid_name = Cookie {
+      name: "a b".to_owned(),
+      ..Default::default()
+    };
+    assert_eq!(
+      invalid_name.to_header(),
+      Err(CookieError::InvalidName("a b".to_owned()))
+    );
+
+    let invalid_path = Cookie {
+      name: "a".to_owned(),
+      path: Some("/; Domain=evil.com".to_owned()),
+      ..Default::default()
+    };
+    assert_eq!(
+      invalid_path.to_header(),
+      Err(CookieError::InvalidAttribute {
+        name: "a".to_owned(),
+        attribute: "path"
+      })
+    );
+
+    let invalid_same_site = Cookie {
+      name: "a".to_owned(),
+      same_site: Some("sometimes".to_owned()),
+      ..Default::default()
+    };
+    assert!(invalid_same_site.to_header().is_err());
+  }
+}
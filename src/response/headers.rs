@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use napi::bindgen_prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A response header value, every value of an array is written as its own header line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HeaderValue {
+  One(String),
+  Many(Vec<String>),
+}
+
+impl HeaderValue {
+  pub fn values(&self) -> &[String] {
+    match self {
+      HeaderValue::One(value) => std::slice::from_ref(value),
+      HeaderValue::Many(values) => values,
+    }
+  }
+}
+
+impl From<String> for HeaderValue {
+  fn from(value: String) -> Self {
+    HeaderValue::One(value)
+  }
+}
+
+impl From<&str> for HeaderValue {
+  fn from(value: &str) -> Self {
+    HeaderValue::One(value.to_owned())
+  }
+}
+
+impl From<Vec<String>> for HeaderValue {
+  fn from(values: Vec<String>) -> Self {
+    HeaderValue::Many(values)
+  }
+}
+
+impl TypeName for HeaderValue {
+  fn type_name() -> &'static str {
+    "string | string[]"
+  }
+
+  fn value_type() -> ValueType {
+    ValueType::Unknown
+  }
+}
+
+impl FromNapiValue for HeaderValue {
+  unsafe fn from_napi_value(env: sys::napi_env, napi_val: sys::napi_value) -> Result<Self> {
+    match Either::<String, Vec<String>>::from_napi_value(env, napi_val)? {
+      Either::A(value) => Ok(HeaderValue::One(value)),
+      Either::B(values) => Ok(HeaderValue::Many(values)),
+    }
+  }
+}
+
+impl ToNapiValue for HeaderValue {
+  unsafe fn to_napi_value(env: sys::napi_env, val: Self) -> Result<sys::napi_value> {
+    match val {
+      HeaderValue::One(value) => String::to_napi_value(env, value),
+      HeaderValue::Many(values) => Vec::<String>::to_napi_value(env, values),
+    }
+  }
+}
+
+pub type ResponseHeaders = HashMap<String, HeaderValue>;
+
+/// The header named `name`, compared case-insensitively
+pub fn find_header<'h>(
+  headers: &'h ResponseHeaders,
+  name: &str,
+) -> Option<(&'h String, &'h HeaderValue)> {
+  headers
+    .iter()
+    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,609 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use napi::bindgen_prelude::{Buffer, Either};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use tracing::error;
+
+use crate::constants::CRLF;
+
+mod compression;
+mod conditional;
+mod cookies;
+mod headers;
+mod options;
+mod range;
+mod sendfile;
+mod stream;
+pub use compression::*;
+pub use conditional::*;
+pub use cookies::*;
+pub use headers::*;
+pub use options::*;
+pub use range::*;
+pub use sendfile::*;
+pub use stream::*;
+
+#[napi(object, js_name = "AouResponse")]
+pub struct Response {
+  pub status: Option<u32>,
+  #[napi(ts_type = "Record<string,string>")]
+  pub status_message: Option<String>,
+  /// Arrays are written as one header line per value, e.g. for multiple `Set-Cookie`s
+  #[napi(ts_type = "Record<string, string | string[]>")]
+  pub headers: Option<ResponseHeaders>,
+  /// Sent as one `Set-Cookie` header each, after the ones in `headers`
+  pub cookies: Option<Vec<Cookie>>,
+  #[napi(ts_type = "any | null")]
+  pub body: serde_json::Value,
+  /// Only the requested part is sent when a GET request has a `Range` header and `status` is 200
+  pub buffer: Option<Buffer>,
+  #[napi(ts_type = "AsyncIterable<Buffer | string> | ReadableStream<Uint8Array>")]
+  pub stream: Option<ResponseStream>,
+}
+
+unsafe impl Sync for Response {}
+unsafe impl Send for Response {}
+
+impl Default for Response {
+  fn default() -> Self {
+    Self {
+      status: None,
+      status_message: None,
+      headers: Default::default(),
+      cookies: None,
+      body: serde_json::Value::Null,
+      buffer: None,
+      stream: None,
+    }
+  }
+}
+
+/// How the end of the body is communicated to the client
+enum Framing {
+  ContentLength(usize),
+  Chunked,
+  /// 1xx, 204 and 304 responses can't have a body
+  NoBody,
+}
+
+impl Response {
+  pub async fn write_to_stream<TStream>(
+    &self,
+    stream: &mut TStream,
+    static_headers: &HashMap<String, String>,
+    options: &ResponseOptions<'_>,
+  ) -> anyhow::Result<()>
+  where
+    TStream: AsyncRead + AsyncWrite + Unpin,
+  {
+    let (status, status_message) = self.status_line();
+
+    let empty_headers = ResponseHeaders::with_capacity(0); // TODO: move to static
+    let mut headers = Cow::Borrowed(self.headers.as_ref().unwrap_or(&empty_headers));
+    if options.close {
+      headers
+        .to_mut()
+        .insert("Connection".to_owned(), "close".into());
+    }
+
+    let cookies = match self.set_cookies() {
+      Ok(cookies) => cookies,
+      Err(err) => {
+        error!("{err}");
+        let res = Response {
+          status: Some(500),
+          body: serde_json::Value::String(err.to_string()),
+          ..Default::default()
+        };
+
+        return Box::pin(res.write_to_stream(stream, static_headers, options)).await;
+      }
+    };
+
+    if status == 204 || status == 304 || (100..200).contains(&status) {
+      let headers_buf = Self::headers_buf(Framing::NoBody, static_headers, &headers, &cookies);
+      stream
+        .write_all(format!("HTTP/1.1 {status} {status_message}\r\n{headers_buf}\r\n",).as_bytes())
+        .await?;
+    } else if let Some(body_stream) = &self.stream {
+      let headers_buf = Self::headers_buf(Framing::Chunked, static_headers, &headers, &cookies);
+      stream
+        .write_all(format!("HTTP/1.1 {status} {status_message}\r\n{headers_buf}\r\n",).as_bytes())
+        .await?;
+
+      if !options.head {
+        Self::write_chunks(stream, body_stream).await?;
+      }
+    } else {
+      let serialized;
+      let body: &[u8] = match &self.buffer {
+        Some(buf) => buf,
+        None => {
+          serialized = match &self.body {
+            serde_json::Value::String(str) => str.to_owned(),
+            _ => (&self).body.to_string(),
+          };
+          serialized.as_bytes()
+        }
+      };
+      let len = body.len() as u64;
+
+      let encoding = Self::encoding(body.len(), &mut headers, options);
+      if let Some(etag) = options.etag.filter(|_| status == 200) {
+        if find_header(&headers, "ETag").is_none() {
+          headers
+            .to_mut()
+            .insert("ETag".to_owned(), etag.etag(body, encoding).into());
+        }
+      }
+
+      if Self::not_modified(status, &headers, options) {
+        return Self::write_not_modified(stream, static_headers, &headers, &cookies).await;
+      }
+
+      // Byte positions of a compressed body would depend on the encoding, it is sent whole instead
+      let ranges = match (&self.buffer, encoding) {
+        (Some(_), None) => Self::ranges(status, len, &mut headers, options),
+        _ => Ranges::Full,
+      };
+      match ranges {
+        Ranges::Full => (),
+        Ranges::Unsatisfiable => {
+          return Box::pin(Self::unsatisfiable(len).write_to_stream(
+            stream,
+            static_headers,
+            options,
+          ))
+          .await;
+        }
+        Ranges::Partial(ranges) => {
+          let (multipart, content_length) = Self::partial(&mut headers, &ranges, len);
+          let slice = |range: &ByteRange| &body[range.start as usize..=range.end as usize];
+
+          let headers_buf = Self::headers_buf(
+            Framing::ContentLength(content_length as usize),
+            static_headers,
+            &headers,
+            &cookies,
+          );
+          let mut partial =
+            format!("HTTP/1.1 206 Partial Content\r\n{headers_buf}\r\n").into_bytes();
+
+          match &multipart {
+            None => partial.extend_from_slice(slice(&ranges[0])),
+            Some(multipart) => {
+              for (index, range) in ranges.iter().enumerate() {
+                partial.extend_from_slice(multipart.part_head(index, range).as_bytes());
+                partial.extend_from_slice(slice(range));
+              }
+              partial.extend_from_slice(multipart.closing().as_bytes());
+            }
+          }
+          stream.write_all(&partial).await?;
+
+          return Ok(());
+        }
+      }
+
+      let body = match encoding {
+        Some(encoding) => {
+          headers
+            .to_mut()
+            .insert("Content-Encoding".to_owned(), encoding.as_str().into());
+          Cow::Owned(encoding.compress_blocking(body).await?)
+        }
+        None => Cow::Borrowed(body),
+      };
+      let headers_buf = Self::headers_buf(
+        Framing::ContentLength(body.len()),
+        static_headers,
+        &headers,
+        &cookies,
+      );
+
+      let mut buf = format!("HTTP/1.1 {status} {status_message}\r\n{headers_buf}\r\n").into_bytes();
+      if !options.head {
+        buf.extend_from_slice(&body);
+      }
+      stream.write_all(&buf).await?;
+    }
+
+    Ok(())
+  }
+
+  /// Writes the head and `len` bytes of `file`, or the ranges of it the request asked for.
+  /// `body`, `buffer` and `stream` are ignored, the file is never copied into JS.
+  pub async fn write_file_to_stream<TStream>(
+    &self,
+    stream: &mut TStream,
+    file: &mut File,
+    len: u64,
+    options: &ResponseOptions<'_>,
+  ) -> anyhow::Result<()>
+  where
+    TStream: AsyncRead + SendFile,
+  {
+    let (status, status_message) = self.status_line();
+
+    let empty_headers = ResponseHeaders::with_capacity(0);
+    let mut headers = Cow::Borrowed(self.headers.as_ref().unwrap_or(&empty_headers));
+    if options.close {
+      headers
+        .to_mut()
+        .insert("Connection".to_owned(), "close".into());
+    }
+    let cookies = self.set_cookies()?;
+
+    if Self::not_modified(status, &headers, options) {
+      return Self::write_not_modified(stream, &HashMap::new(), &headers, &cookies).await;
+    }
+
+    let ranges = match Self::ranges(status, len, &mut headers, options) {
+      Ranges::Full => None,
+      Ranges::Partial(ranges) => Some(ranges),
+      Ranges::Unsatisfiable => {
+        return Self::unsatisfiable(len)
+          .write_to_stream(stream, &HashMap::new(), options)
+          .await;
+      }
+    };
+
+    let (status, status_message, multipart, content_length) = match &ranges {
+      None => (status, status_message, None, len),
+      Some(ranges) => {
+        let (multipart, content_length) = Self::partial(&mut headers, ranges, len);
+        (206, "Partial Content", multipart, content_length)
+      }
+    };
+
+    let headers_buf = Self::headers_buf(
+      Framing::ContentLength(content_length as usize),
+      &HashMap::new(),
+      &headers,
+      &cookies,
+    );
+    stream
+      .write_all(format!("HTTP/1.1 {status} {status_message}\r\n{headers_buf}\r\n",).as_bytes())
+      .await?;
+
+    if options.head {
+      return Ok(());
+    }
+
+    match (&ranges, &multipart) {
+      (None, _) => stream.send_file(file, 0, len).await?,
+      (Some(ranges), None) => {
+        stream
+          .send_file(file, ranges[0].start, ranges[0].size())
+          .await?
+      }
+      (Some(ranges), Some(multipart)) => {
+        for (index, range) in ranges.iter().enumerate() {
+          stream
+            .write_all(multipart.part_head(index, range).as_bytes())
+            .await?;
+          stream.send_file(file, range.start, range.size()).await?;
+        }
+        stream.write_all(multipart.closing().as_bytes()).await?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Ranges of a 200 response with a `len` bytes body the request asked for, the response then gets `Accept-Ranges`
+  fn ranges(
+    status: u32,
+    len: u64,
+    headers: &mut Cow<ResponseHeaders>,
+    options: &ResponseOptions,
+  ) -> Ranges {
+    let Some(range) = options.range.as_ref().filter(|_| status == 200) else {
+      return Ranges::Full;
+    };
+
+    headers
+      .to_mut()
+      .insert("Accept-Ranges".to_owned(), "bytes".into());
+
+    range.ranges(len, headers)
+  }
+
+  /// Adds the headers of a 206 with `ranges` of a `total` bytes body,
+  /// several ranges are sent as `multipart/byteranges`. Returns the multipart layout and the `Content-Length`
+  fn partial(
+    headers: &mut Cow<ResponseHeaders>,
+    ranges: &[ByteRange],
+    total: u64,
+  ) -> (Option<MultipartRanges>, u64) {
+    if let [range] = ranges {
+      headers.to_mut().insert(
+        "Content-Range".to_owned(),
+        range.content_range(total).into(),
+      );
+      return (None, range.size());
+    }
+
+    let content_type =
+      find_header(headers, "Content-Type").and_then(|(_, value)| value.values().first().cloned());
+    let multipart = MultipartRanges::new(content_type, total);
+
+    let headers = headers.to_mut();
+    headers.retain(|key, _| !key.eq_ignore_ascii_case("Content-Type"));
+    headers.insert("Content-Type".to_owned(), multipart.content_type().into());
+
+    let content_length = multipart.content_length(ranges);
+    (Some(multipart), content_length)
+  }
+
+  fn unsatisfiable(len: u64) -> Response {
+    Response {
+      status: Some(416),
+      headers: Some(HashMap::from([(
+        "Content-Range".to_owned(),
+        format!("bytes */{len}").into(),
+      )])),
+      ..Default::default()
+    }
+  }
+
+  fn status_line(&self) -> (u32, &str) {
+    let status = self.status.unwrap_or(200);
+    let status_message = self
+      .status_message
+      .as_deref()
+      .or(Response::status_message(status))
+      .unwrap_or("");
+
+    (status, status_message)
+  }
+
+  /// Writes the JS stream as `Transfer-Encoding: chunked`.
+  /// The next chunk is only pulled from JS once the previous one was written to the socket.
+  async fn write_chunks<TStream>(
+    stream: &mut TStream,
+    body_stream: &ResponseStream,
+  ) -> anyhow::Result<()>
+  where
+    TStream: AsyncRead + AsyncWrite + Unpin,
+  {
+    loop {
+      let chunk = match body_stream.next().await {
+        Ok(Some(chunk)) => chunk,
+        Ok(None) => break,
+        // The head is already sent, a failing iterator can only end the body early
+        Err(err) => {
+          error!("Response stream failed {err}");
+          break;
+        }
+      };
+      let chunk: &[u8] = match &chunk {
+        Either::A(str) => str.as_bytes(),
+        Either::B(buf) => buf,
+      };
+
+      // An empty chunk would be read as the last chunk
+      if chunk.is_empty() {
+        continue;
+      }
+
+      stream
+        .write_all(format!("{:X}\r\n", chunk.len()).as_bytes())
+        .await?;
+      stream.write_all(chunk).await?;
+      stream.write_all(CRLF).await?;
+      stream.flush().await?;
+    }
+
+    stream.write_all(b"0\r\n\r\n").await?;
+
+    Ok(())
+  }
+
+  /// Encoding a `len` bytes body is compressed with, when its `Content-Type` is in the compressed types
+  /// and it is at least `min_size` bytes. Adds `Vary` to the headers, bodies the handler already encoded are left alone.
+  fn encoding(
+    len: usize,
+    headers: &mut Cow<ResponseHeaders>,
+    options: &ResponseOptions,
+  ) -> Option<ContentEncoding> {
+    let compression = options.compression.as_ref()?;
+
+    let compressible = find_header(headers, "Content-Type").is_some_and(|(_, content_type)| {
+      content_type
+        .values()
+        .first()
+        .is_some_and(|content_type| compression.compressible(content_type))
+    });
+    if !compressible || find_header(headers, "Content-Encoding").is_some() {
+      return None;
+    }
+
+    // The body depends on `Accept-Encoding` even when this client gets it uncompressed
+    match find_header(headers, "Vary").map(|(key, value)| (key.clone(), value.values().join(", ")))
+    {
+      Some((_, vary))
+        if vary.split(',').any(|field| {
+          let field = field.trim();
+          field == "*" || field.eq_ignore_ascii_case("Accept-Encoding")
+        }) => {}
+      Some((key, vary)) => {
+        headers
+          .to_mut()
+          .insert(key, format!("{vary}, Accept-Encoding").into());
+      }
+      None => {
+        headers
+          .to_mut()
+          .insert("Vary".to_owned(), "Accept-Encoding".into());
+      }
+    }
+
+    compression.encoding.filter(|_| len >= compression.min_size)
+  }
+
+  /// Whether a response is sent as a 304 because the client's copy matches it, only 200s are
+  fn not_modified(status: u32, headers: &ResponseHeaders, options: &ResponseOptions) -> bool {
+    options
+      .conditional
+      .as_ref()
+      .is_some_and(|conditional| status == 200 && conditional.not_modified(headers))
+  }
+
+  /// Writes a 304 with the cache headers of the response it replaces
+  async fn write_not_modified<TStream>(
+    stream: &mut TStream,
+    static_headers: &HashMap<String, String>,
+    headers: &ResponseHeaders,
+    cookies: &[String],
+  ) -> anyhow::Result<()>
+  where
+    TStream: AsyncWrite + Unpin,
+  {
+    let headers_buf = Self::headers_buf(
+      Framing::NoBody,
+      static_headers,
+      &not_modified_headers(headers),
+      cookies,
+    );
+    stream
+      .write_all(format!("HTTP/1.1 304 Not Modified\r\n{headers_buf}\r\n").as_bytes())
+      .await?;
+
+    Ok(())
+  }
+
+  /// `Set-Cookie` values of `cookies`, fails before anything is written if a cookie is invalid
+  fn set_cookies(&self) -> Result<Vec<String>, CookieError> {
+    self
+      .cookies
+      .iter()
+      .flatten()
+      .map(Cookie::to_header)
+      .collect()
+  }
+
+  fn headers_buf(
+    framing: Framing,
+    static_headers: &HashMap<String, String>,
+    headers: &ResponseHeaders,
+    cookies: &[String],
+  ) -> String {
+    let mut r = String::new();
+    let mut set = HashSet::<&String>::new();
+
+    static_headers
+      .iter()
+      .for_each(|(key, value)| match headers.get(key) {
+        Some(h) => {
+          set.insert(key);
+          h.values()
+            .iter()
+            .for_each(|value| r.push_str(format!("{key}: {value}\r\n").as_str()))
+        }
+        None => r.push_str(format!("{key}: {value}\r\n").as_str()),
+      });
+
+    headers.iter().for_each(|(key, value)| {
+      if set.contains(key)
+        || key.eq_ignore_ascii_case("Content-Length")
+        || key.eq_ignore_ascii_case("Transfer-Encoding")
+      {
+        return;
+      }
+      value
+        .values()
+        .iter()
+        .for_each(|value| r.push_str(format!("{key}: {value}\r\n").as_str()));
+    });
+
+    cookies
+      .iter()
+      .for_each(|cookie| r.push_str(format!("Set-Cookie: {cookie}\r\n").as_str()));
+
+    match framing {
+      Framing::ContentLength(content_length) => {
+        r.push_str(format!("Content-Length: {}\r\n", content_length).as_str())
+      }
+      Framing::Chunked => r.push_str("Transfer-Encoding: chunked\r\n"),
+      Framing::NoBody => (),
+    }
+
+    r
+  }
+
+  fn status_message<'r>(status_code: u32) -> Option<&'r str> {
+    match status_code {
+      100 => Some("Continue"),
+      101 => Some("Switching Protocols"),
+      102 => Some("Processing"),
+      103 => Some("Early Hints"),
+
+      200 => Some("OK"),
+      201 => Some("Created"),
+      202 => Some("Accepted"),
+      203 => Some("Non-Authoritative Information"),
+      204 => Some("No Content"),
+      205 => Some("Reset Content"),
+      206 => Some("Partial Content"),
+      207 => Some("Multi-Status"),
+      208 => Some("Already Reported"),
+      226 => Some("IM USED"),
+
+      300 => Some("Multiple Choices"),
+      301 => Some("Moved Permanently"),
+      302 => Some("Found"),
+      303 => Some("See Other"),
+      304 => Some("Not Modified"),
+      307 => Some("Temporary Redirect"),
+      308 => Some("Permanent Redirect"),
+
+      400 => Some("Bad Request"),
+      401 => Some("Unauthorized"),
+      402 => Some("Payment Required"),
+      403 => Some("Forbidden"),
+      404 => Some("Not Found"),
+      405 => Some("Method Not Allowed"),
+      406 => Some("Not Acceptable"),
+      407 => Some("Proxy Authentication Required"),
+      408 => Some("Request Timeout"),
+      409 => Some("Conflict"),
+      410 => Some("Gone"),
+      411 => Some("Length Required"),
+      412 => Some("Precondition Failed"),
+      413 => Some("Payload Too Large"),
+      414 => Some("URI Too Long"),
+      415 => Some("Unsupported Media Type"),
+      416 => Some("Range Not Satisfiable"),
+      417 => Some("Expectation Failed"),
+      418 => Some("I'm a teapot"),
+      421 => Some("Misdirected Request"),
+      422 => Some("Unprocessable Content"),
+      423 => Some("Locked"),
+      424 => Some("Failed Dependency"),
+      425 => Some("Too Early"),
+      426 => Some("Upgrade Required"),
+      428 => Some("Precondition Required"),
+      429 => Some("Too Many Requests"),
+      431 => Some("Request Header Fields Too Large"),
+      451 => Some("Unavailable For Legal Reasons"),
+
+      500 => Some("Internal Server Error"),
+      501 => Some("Not Implemented"),
+      502 => Some("Bad Gateway"),
+      503 => Some("Service Unavailable"),
+      504 => Some("Gateway Timeout"),
+      505 => Some("HTTP Version Not Supported"),
+      506 => Some("Variant Also Negotiates"),
+      507 => Some("Insufficient Storage"),
+      508 => Some("Loop Detected"),
+      510 => Some("Not Extended"),
+      511 => Some("Network Authentication Required"),
+
+      _ => None,
+    }
+  }
+}
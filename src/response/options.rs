@@ -0,0 +1,17 @@
+use super::{Compression, ConditionalRequest, ETagMode, RangeRequest};
+
+#[derive(Debug, Default, Clone)]
+pub struct ResponseOptions<'a> {
+  /// Response to a HEAD request, headers are written as usual but the body is skipped
+  pub head: bool,
+  /// Compression of buffered bodies, `None` sends every body as is
+  pub compression: Option<Compression<'a>>,
+  /// Set on GET requests, `buffer` and static file responses are then sent partially when a `Range` is asked for
+  pub range: Option<RangeRequest>,
+  /// Set on GET and HEAD requests, 200 responses whose `ETag` or `Last-Modified` match it are sent as a 304
+  pub conditional: Option<ConditionalRequest>,
+  /// `ETag` added to `body` and `buffer` responses that don't have one
+  pub etag: Option<ETagMode>,
+  /// The connection is closed after this response, it is sent with `Connection: close`
+  pub close: bool,
+}
@@ -0,0 +1,335 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::constants::MAX_BYTE_RANGES;
+
+use super::{find_header, ResponseHeaders};
+
+/// The `Range` and `If-Range` headers of a GET request, ranges can only be resolved once the body is known.
+#[derive(Debug, Clone, Default)]
+pub struct RangeRequest {
+  pub range: Option<String>,
+  pub if_range: Option<String>,
+}
+
+/// An inclusive range of byte positions, like in `Content-Range`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+  pub start: u64,
+  pub end: u64,
+}
+
+impl ByteRange {
+  pub fn size(&self) -> u64 {
+    self.end - self.start + 1
+  }
+
+  pub fn content_range(&self, total: u64) -> String {
+    format!("bytes {}-{}/{total}", self.start, self.end)
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+  /// No range was asked for, or it has to be ignored
+  Full,
+  Partial(Vec<ByteRange>),
+  /// Answered with a 416
+  Unsatisfiable,
+}
+
+impl RangeRequest {
+  /// Ranges of a `len` bytes body, the whole body is sent when `If-Range` doesn't match the response `headers`
+  pub fn ranges(&self, len: u64, headers: &ResponseHeaders) -> Ranges {
+    let Some(range) = &self.range else {
+      return Ranges::Full;
+    };
+
+    match &self.if_range {
+      Some(if_range) if !if_range_matches(if_range, headers) => Ranges::Full,
+      _ => parse_ranges(range, len),
+    }
+  }
+}
+
+/// `If-Range` holds either an entity tag, compared strongly, or the `Last-Modified` date
+fn if_range_matches(if_range: &str, headers: &ResponseHeaders) -> bool {
+  let if_range = if_range.trim();
+  let header = |name| {
+    find_header(headers, name).and_then(|(_, value)| value.values().first().map(|v| v.trim()))
+  };
+
+  if if_range.starts_with('"') {
+    return header("ETag").is_some_and(|etag| etag == if_range);
+  }
+  if if_range.starts_with("W/") {
+    return false;
+  }
+
+  match (
+    httpdate::parse_http_date(if_range),
+    header("Last-Modified").map(httpdate::parse_http_date),
+  ) {
+    (Ok(date), Some(Ok(last_modified))) => date == last_modified,
+    _ => false,
+  }
+}
+
+/// Parses a `Range: bytes=...` header, invalid headers and other units are ignored.
+pub fn parse_ranges(header: &str, len: u64) -> Ranges {
+  let header = header.trim();
+  let Some(specs) = header
+    .get(..6)
+    .filter(|unit| unit.eq_ignore_ascii_case("bytes="))
+    .map(|_| &header[6..])
+  else {
+    return Ranges::Full;
+  };
+
+  let mut ranges = Vec::new();
+  let mut specs_count = 0;
+
+  for spec in specs
+    .split(',')
+    .map(str::trim)
+    .filter(|spec| !spec.is_empty())
+  {
+    specs_count += 1;
+    if specs_count > MAX_BYTE_RANGES {
+      return Ranges::Full;
+    }
+
+    let Some((start, end)) = spec.split_once('-') else {
+      return Ranges::Full;
+    };
+
+    let range = match (start.trim(), end.trim()) {
+      ("", suffix) => {
+        let Some(suffix) = position(suffix) else {
+          return Ranges::Full;
+        };
+        if suffix == 0 || len == 0 {
+          continue;
+        }
+
+        ByteRange {
+          start: len.saturating_sub(suffix),
+          end: len - 1,
+        }
+      }
+      (start, end) => {
+        let Some(start) = position(start) else {
+          return Ranges::Full;
+        };
+        let end = match end {
+          "" => len.saturating_sub(1),
+          end => match position(end) {
+            Some(end) if end >= start => end.min(len.saturating_sub(1)),
+            _ => return Ranges::Full,
+          },
+        };
+        if start >= len {
+          continue;
+        }
+
+        ByteRange { start, end }
+      }
+    };
+
+    ranges.push(range);
+  }
+
+  match (specs_count, ranges.is_empty()) {
+    (0, _) => Ranges::Full,
+    (_, true) => Ranges::Unsatisfiable,
+    (_, false) => Ranges::Partial(coalesce(ranges)),
+  }
+}
+
+/// Sorts the ranges and merges the overlapping or adjacent ones, so repeated specs like
+/// `bytes=0-,0-` can't make the response larger than the body
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+  ranges.sort_unstable_by_key(|range| range.start);
+
+  let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+  for range in ranges {
+    match merged.last_mut() {
+      Some(last) if range.start <= last.end + 1 => last.end = last.end.max(range.end),
+      _ => merged.push(range),
+    }
+  }
+
+  merged
+}
+
+fn position(digits: &str) -> Option<u64> {
+  match !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+    true => digits.parse().ok(),
+    false => None,
+  }
+}
+
+/// Layout of a `multipart/byteranges` body, the data of each range goes after its part head
+pub struct MultipartRanges {
+  pub boundary: String,
+  /// `Content-Type` of the whole body, repeated in every part
+  pub content_type: Option<String>,
+  pub total: u64,
+}
+
+impl MultipartRanges {
+  pub fn new(content_type: Option<String>, total: u64) -> Self {
+    let random = || RandomState::new().build_hasher().finish();
+
+    MultipartRanges {
+      boundary: format!("{:016x}{:016x}", random(), random()),
+      content_type,
+      total,
+    }
+  }
+
+  pub fn content_type(&self) -> String {
+    format!("multipart/byteranges; boundary={}", self.boundary)
+  }
+
+  /// Delimiter and headers written before the data of the `index`th range
+  pub fn part_head(&self, index: usize, range: &ByteRange) -> String {
+    let delimiter = if index == 0 { "" } else { "\r\n" };
+    let content_type = self
+      .content_type
+      .as_ref()
+      .map(|content_type| format!("Content-Type: {content_type}\r\n"))
+      .unwrap_or_default();
+
+    format!(
+      "{delimiter}--{}\r\n{content_type}Content-Range: {}\r\n\r\n",
+      self.boundary,
+      range.content_range(self.total)
+    )
+  }
+
+  pub fn closing(&self) -> String {
+    format!("\r\n--{}--\r\n", self.boundary)
+  }
+
+  pub fn content_length(&self, ranges: &[ByteRange]) -> u64 {
+    let parts: u64 = ranges
+      .iter()
+      .enumerate()
+      .map(|(index, range)| self.part_head(index, range).len() as u64 + range.size())
+      .sum();
+
+    parts + self.closing().len() as u64
+  }
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use super::{parse_ranges, ByteRange, MultipartRanges, RangeRequest, Ranges};
+  use crate::response::ResponseHeaders;
+
+  fn range(start: u64, end: u64) -> ByteRange {
+    ByteRange { start, end }
+  }
+
+  #[test]
+  fn parse_range_headers() {
+    assert_eq!(
+      parse_ranges("bytes=0-99", 1000),
+      Ranges::Partial(vec![range(0, 99)])
+    );
+    assert_eq!(
+      parse_ranges("bytes=500-599, -100, 0-9", 1000),
+      Ranges::Partial(vec![range(0, 9), range(500, 599), range(900, 999)])
+    );
+    assert_eq!(
+      parse_ranges("Bytes=-2000", 1000),
+      Ranges::Partial(vec![range(0, 999)])
+    );
+    assert_eq!(parse_ranges("bytes=1000-", 1000), Ranges::Unsatisfiable);
+    assert_eq!(parse_ranges("bytes=-0", 1000), Ranges::Unsatisfiable);
+    assert_eq!(parse_ranges("bytes=0-", 0), Ranges::Unsatisfiable);
+
+    assert_eq!(parse_ranges("bytes=", 1000), Ranges::Full);
+    assert_eq!(parse_ranges("bytes=5-1", 1000), Ranges::Full);
+    assert_eq!(parse_ranges("bytes=a-b", 1000), Ranges::Full);
+    assert_eq!(parse_ranges("bytes=+1-2", 1000), Ranges::Full);
+    assert_eq!(parse_ranges("items=0-1", 1000), Ranges::Full);
+    assert_eq!(
+      parse_ranges(&format!("bytes={}", "0-1,".repeat(100)), 1000),
+      Ranges::Full
+    );
+  }
+
+  #[test]
+  fn overlapping_ranges_are_merged() {
+    assert_eq!(
+      parse_ranges(&format!("bytes={}", "0-,".repeat(32)), 1000),
+      Ranges::Partial(vec![range(0, 999)])
+    );
+    assert_eq!(
+      parse_ranges("bytes=500-, -100, 990-2000", 1000),
+      Ranges::Partial(vec![range(500, 999)])
+    );
+    assert_eq!(
+      parse_ranges("bytes=10-19, 0-9, 25-29, 20-22", 1000),
+      Ranges::Partial(vec![range(0, 22), range(25, 29)])
+    );
+  }
+
+  #[test]
+  fn if_range() {
+    let headers = ResponseHeaders::from([
+      ("ETag".to_owned(), "\"abc\"".into()),
+      (
+        "Last-Modified".to_owned(),
+        "Sun, 06 Nov 1994 08:49:37 GMT".into(),
+      ),
+    ]);
+    let request = |if_range: &str| RangeRequest {
+      range: Some("bytes=0-0".to_owned()),
+      if_range: Some(if_range.to_owned()),
+    };
+    let partial = Ranges::Partial(vec![range(0, 0)]);
+
+    assert_eq!(request("\"abc\"").ranges(10, &headers), partial);
+    assert_eq!(
+      request("Sun, 06 Nov 1994 08:49:37 GMT").ranges(10, &headers),
+      partial
+    );
+    assert_eq!(request("\"other\"").ranges(10, &headers), Ranges::Full);
+    assert_eq!(request("W/\"abc\"").ranges(10, &headers), Ranges::Full);
+    assert_eq!(
+      request("Mon, 07 Nov 1994 08:49:37 GMT").ranges(10, &headers),
+      Ranges::Full
+    );
+    assert_eq!(
+      request("\"abc\"").ranges(10, &ResponseHeaders::new()),
+      Ranges::Full
+    );
+  }
+
+  #[test]
+  fn multipart_layout() {
+    let multipart = MultipartRanges::new(Some("text/plain".to_owned()), 10);
+    let ranges = [range(0, 1), range(5, 9)];
+
+    let mut body = String::new();
+    for (index, range) in ranges.iter().enumerate() {
+      body.push_str(&multipart.part_head(index, range));
+      body.push_str(&"x".repeat(range.size() as usize));
+    }
+    body.push_str(&multipart.closing());
+
+    let boundary = &multipart.boundary;
+    assert_eq!(
+      body,
+      format!(
+        "--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\nxx\
+         \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 5-9/10\r\n\r\nxxxxx\
+         \r\n--{boundary}--\r\n"
+      )
+    );
+    assert_eq!(multipart.content_length(&ranges), body.len() as u64);
+  }
+}
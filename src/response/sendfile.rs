@@ -0,0 +1,72 @@
+use std::future::Future;
+use std::io::{self, SeekFrom};
+
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// Streams that can write part of a file without copying it through user space first.
+///
+/// The default implementation reads the file in chunks and writes them to the stream.
+pub trait SendFile: AsyncWrite + Unpin + Send {
+  /// Writes `len` bytes of `file` starting at `offset`
+  fn send_file(
+    &mut self,
+    file: &mut File,
+    offset: u64,
+    len: u64,
+  ) -> impl Future<Output = io::Result<()>> + Send {
+    async move {
+      file.seek(SeekFrom::Start(offset)).await?;
+
+      let copied = tokio::io::copy(&mut file.take(len), self).await?;
+      if copied < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+      }
+
+      Ok(())
+    }
+  }
+}
+
+/// Uses `sendfile(2)` so the file is copied to the socket by the kernel
+#[cfg(target_os = "linux")]
+impl SendFile for TcpStream {
+  async fn send_file(&mut self, file: &mut File, mut offset: u64, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    // Linux sends at most 0x7ffff000 bytes per call
+    const MAX_CHUNK: u64 = 0x7fff_f000;
+
+    let file_fd = file.as_raw_fd();
+    let socket_fd = self.as_raw_fd();
+    let end = offset + len;
+
+    while offset < end {
+      self.writable().await?;
+
+      let count = (end - offset).min(MAX_CHUNK) as usize;
+      let sent = self.try_io(Interest::WRITABLE, || {
+        let mut file_offset = offset as libc::off_t;
+        match unsafe { libc::sendfile(socket_fd, file_fd, &mut file_offset, count) } {
+          -1 => Err(io::Error::last_os_error()),
+          sent => Ok(sent as u64),
+        }
+      });
+
+      match sent {
+        // The file got shorter since its size was read
+        Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(sent) => offset += sent,
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+        Err(err) => return Err(err),
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl SendFile for TcpStream {}
@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunction;
+use napi::{Env, JsFunction, JsObject, JsSymbol, JsUnknown};
+use tracing::debug;
+
+#[napi(object, js_name = "AouStreamChunk")]
+pub struct StreamChunk {
+  pub done: Option<bool>,
+  pub value: Option<Either<String, Buffer>>,
+}
+
+/// A JS `AsyncIterable` (async generators, Node `Readable`s or `ReadableStream`s)
+/// returned in `AouResponse.stream`.
+///
+/// The iterator's `next` is kept as a threadsafe function so chunks can be pulled
+/// from the tokio side, one at a time, only after the previous one was written.
+/// A stream dropped before it is done, e.g. the client went away or the request was a HEAD,
+/// calls the iterator's `return` so the JS side can release its resources.
+pub struct ResponseStream {
+  next: ThreadsafeFunction<()>,
+  return_: Option<ThreadsafeFunction<()>>,
+  done: AtomicBool,
+}
+
+impl ResponseStream {
+  /// Returns the next chunk or `None` once the iterator is done.
+  /// Errors are the ones the iterator threw or rejected with.
+  pub async fn next(&self) -> Result<Option<Either<String, Buffer>>> {
+    let chunk = self
+      .next
+      .call_async::<Promise<StreamChunk>>(Ok(()))
+      .await?
+      .await?;
+
+    if chunk.done.unwrap_or(false) {
+      self.done.store(true, Ordering::Relaxed);
+      return Ok(None);
+    }
+
+    Ok(Some(chunk.value.unwrap_or(Either::A(String::new()))))
+  }
+}
+
+impl Drop for ResponseStream {
+  fn drop(&mut self) {
+    if self.done.load(Ordering::Relaxed) {
+      return;
+    }
+
+    if let Some(return_) = self.return_.take() {
+      // Awaited on its own so a rejected `return` is handled and the drop doesn't block
+      spawn(async move {
+        if let Err(err) = return_.call_async::<Promise<StreamChunk>>(Ok(())).await {
+          debug!("Response stream return failed {err}");
+        }
+      });
+    }
+  }
+}
+
+impl TypeName for ResponseStream {
+  fn type_name() -> &'static str {
+    "AsyncIterable"
+  }
+
+  fn value_type() -> ValueType {
+    ValueType::Object
+  }
+}
+
+impl FromNapiValue for ResponseStream {
+  unsafe fn from_napi_value(env: sys::napi_env, napi_val: sys::napi_value) -> Result<Self> {
+    let env = Env::from_raw(env);
+    let iterable = JsObject::from_napi_value(env.raw(), napi_val)?;
+
+    let async_iterator = env
+      .get_global()?
+      .get_named_property::<JsFunction>("Symbol")?
+      .coerce_to_object()?
+      .get_named_property::<JsSymbol>("asyncIterator")?;
+
+    let factory = iterable.get_property::<_, JsUnknown>(async_iterator)?;
+
+    if factory.get_type()? != ValueType::Function {
+      return Err(Error::new(
+        Status::InvalidArg,
+        "AouResponse.stream must be an AsyncIterable or a ReadableStream",
+      ));
+    }
+
+    let iterator = factory
+      .cast::<JsFunction>()
+      .call_without_args(Some(&iterable))?
+      .coerce_to_object()?;
+
+    let next = bind_method(&iterator, "next")?
+      .ok_or_else(|| Error::new(Status::InvalidArg, "AouResponse.stream has no next method"))?;
+    let return_ = bind_method(&iterator, "return")?;
+
+    Ok(ResponseStream {
+      next: next.create_threadsafe_function(0, |_| Ok(Vec::<JsUnknown>::new()))?,
+      return_: return_
+        .map(|return_| return_.create_threadsafe_function(0, |_| Ok(Vec::<JsUnknown>::new())))
+        .transpose()?,
+      done: AtomicBool::new(false),
+    })
+  }
+}
+
+/// The threadsafe functions are called without a `this`, so the iterator's methods are bound to it.
+/// `return` is optional in the iterator protocol, a missing method is `None`.
+fn bind_method(iterator: &JsObject, name: &str) -> Result<Option<JsFunction>> {
+  let method = iterator.get_named_property::<JsUnknown>(name)?;
+  if method.get_type()? != ValueType::Function {
+    return Ok(None);
+  }
+
+  let method = method.coerce_to_object()?;
+  let bound = method
+    .get_named_property::<JsFunction>("bind")?
+    .call(Some(&method), &[iterator])?;
+
+  Ok(Some(JsFunction::try_from(bound)?))
+}
+
+impl ToNapiValue for ResponseStream {
+  unsafe fn to_napi_value(_: sys::napi_env, _: Self) -> Result<sys::napi_value> {
+    Err(Error::new(
+      Status::InvalidArg,
+      "AouResponse.stream can't be converted back into a JS value",
+    ))
+  }
+}
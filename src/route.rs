@@ -1,5 +1,44 @@
+use std::str::FromStr;
+
 use crate::request::HttpMethod;
 
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum RouteMatchError {
+  #[error("Route not found")]
+  NotFound,
+  #[error("Method not allowed")]
+  MethodNotAllowed(Vec<HttpMethod>),
+  #[error("Route found at {0}")]
+  Redirect(String),
+}
+
+/// How a path only matching a route once its trailing slash is added or removed is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TrailingSlash {
+  /// `/a` and `/a/` are different routes
+  #[default]
+  Strict,
+  /// Answered with a 308 to the path matching the route
+  Redirect,
+  /// Handled by the route as if the path matched it
+  Lenient,
+}
+
+impl FromStr for TrailingSlash {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "strict" => Ok(TrailingSlash::Strict),
+      "redirect" => Ok(TrailingSlash::Redirect),
+      "lenient" => Ok(TrailingSlash::Lenient),
+      _ => Err(format!(
+        "Invalid trailingSlash \"{s}\", expected \"strict\", \"redirect\" or \"lenient\""
+      )),
+    }
+  }
+}
+
 #[allow(non_snake_case)]
 #[derive(Debug, Clone, Copy)]
 pub struct Route<T> {
@@ -85,4 +124,59 @@ impl<T> Route<T> {
   pub fn set_all(&mut self, value: T) {
     self.ALL = Some(value)
   }
+
+  /// Methods with a registered handler plus the `automatic` ones answered without one,
+  /// every method is allowed if the route has a handler for ALL
+  pub fn methods(&self, automatic: &[HttpMethod]) -> Vec<HttpMethod> {
+    if self.has_all() {
+      return HttpMethod::METHODS.to_vec();
+    }
+
+    HttpMethod::METHODS
+      .into_iter()
+      .filter(|method| self.has_method(*method) || automatic.contains(method))
+      .collect()
+  }
+}
+
+pub fn allow_header(methods: &[HttpMethod]) -> String {
+  methods
+    .iter()
+    .map(|method| method.to_str())
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use crate::request::HttpMethod;
+
+  use super::{allow_header, Route};
+
+  #[test]
+  fn registered_methods() {
+    let mut route = Route::<()>::default();
+    route.set_method(HttpMethod::GET, ());
+    route.set_method(HttpMethod::PATCH, ());
+
+    assert_eq!(
+      route.methods(&[]),
+      vec![HttpMethod::GET, HttpMethod::PATCH],
+      "Only registered methods should be listed"
+    );
+    assert_eq!(allow_header(&route.methods(&[])), "GET, PATCH");
+    assert_eq!(
+      allow_header(&route.methods(&[HttpMethod::HEAD, HttpMethod::OPTIONS])),
+      "GET, HEAD, OPTIONS, PATCH",
+      "Automatic methods should be listed in order"
+    );
+
+    route.set_all(());
+
+    assert_eq!(
+      route.methods(&[]).len(),
+      HttpMethod::METHODS.len(),
+      "ALL should allow every method"
+    );
+  }
 }
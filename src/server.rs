@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::net::SocketAddrV4;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use matchit::Match;
@@ -10,53 +12,396 @@ use napi::bindgen_prelude::*;
 use napi::threadsafe_function::ErrorStrategy;
 use napi::threadsafe_function::ThreadsafeFunction;
 use napi::JsFunction;
+use napi::{Env, JsObject};
 use napi_derive::napi;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 use tracing::debug;
 use tracing::error;
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
+use crate::constants::{COMPRESSION_MIN_SIZE, COMPRESSION_TYPES};
 use crate::error::AouError;
 use crate::request::Connection;
 use crate::request::HttpMethod;
 use crate::request::{self, Request};
-use crate::response::Response;
-use crate::route::Route;
+use crate::request::{MultipartLimits, RequestLimits, RequestTimeouts, ResolveLimits, TempFiles};
+use crate::response::{
+  Compression, ConditionalRequest, ContentEncoding, ETagMode, RangeRequest, Response,
+  ResponseOptions, SendFile,
+};
+use crate::route::{self, Route, RouteMatchError, TrailingSlash};
+use crate::session::{AouSession, SessionOptions, Sessions};
+use crate::static_files::{StaticFiles, StaticOptions};
+use crate::utils::{self, urlencoded};
+use crate::websocket::{self, HandshakeError, Topics, WebSocketOptions, WebSocketRoute};
 
 #[napi]
 pub struct AouInstance {
   pub ip: String,
   pub port: u32,
   _options: AouOptions,
-  _router: Arc<matchit::Router<Route<ThreadsafeFunction<Request, ErrorStrategy::Fatal>>>>,
+  _router: Arc<AouRouter>,
+  shutdown: watch::Sender<bool>,
+  accept_loop: Mutex<Option<JoinHandle<JoinSet<anyhow::Result<()>>>>>,
 }
 
 #[napi(object)]
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone)]
 pub struct AouOptions {
   pub tracing: Option<bool>,
+  /// Answer HEAD requests with the GET handler of the route. Defaults to `true`
+  pub auto_head: Option<bool>,
+  /// Answer OPTIONS requests with the methods registered on the route. Defaults to `true`
+  pub auto_options: Option<bool>,
+  /// Parse `a[b]=c` query keys into nested objects and `a[]=c` into arrays. Defaults to `false`
+  pub nested_query: Option<bool>,
+  /// What to do with paths that only match a route once their trailing slash is added or removed. Defaults to `"strict"`
+  #[napi(ts_type = "'strict' | 'redirect' | 'lenient'")]
+  pub trailing_slash: Option<String>,
+  /// Cookie sessions available as `req.session`
+  pub session: Option<SessionOptions>,
+  /// Compress `body` and `buffer` responses with the best encoding the client accepts in `Accept-Encoding`,
+  /// streamed responses are sent as is. Defaults to `false`
+  pub compression: Option<bool>,
+  /// Bytes a body needs before it is compressed. Defaults to `1024`
+  pub compression_min_size: Option<u32>,
+  /// `Content-Type`s that are compressed, `type/*` matches every subtype.
+  /// Defaults to text, JSON, JavaScript, XML, WebAssembly and SVG
+  pub compression_types: Option<Vec<String>>,
+  /// Add an `ETag` hashed from the body to `body` and `buffer` responses that don't set one, and answer
+  /// `GET` and `HEAD` requests whose `If-None-Match` or `If-Modified-Since` match the response with a 304.
+  /// Disabled by default
+  #[napi(ts_type = "'strong' | 'weak'")]
+  pub etag: Option<String>,
+  /// Milliseconds an idle keep-alive connection is kept open waiting for the next request. Defaults to `5000`
+  pub keep_alive_timeout: Option<u32>,
+  /// Milliseconds allowed to receive the request head and headers, answered with a 408 when exceeded. Defaults to `10000`
+  pub header_timeout: Option<u32>,
+  /// Milliseconds allowed to receive the whole request body, counted from the end of the headers and answered with a 408 when exceeded. Defaults to `30000`
+  pub body_timeout: Option<u32>,
+  /// Milliseconds a handler has to respond, answered with a 503 if it couldn't be called in time
+  /// or a 504 if it didn't resolve in time. Disabled by default
+  pub handler_timeout: Option<u32>,
+  /// Longest request target accepted, answered with a 414 when exceeded. Defaults to `8192`
+  pub max_uri_length: Option<u32>,
+  /// Bytes allowed for the request headers, answered with a 431 when exceeded. Defaults to `65536`
+  pub max_header_bytes: Option<u32>,
+  /// Header fields allowed in a request, answered with a 431 when exceeded. Defaults to `100`
+  pub max_header_count: Option<u32>,
+  /// Bytes allowed for the request body, answered with a 413 when exceeded. Defaults to `16777216`
+  pub max_body_size: Option<u32>,
+  /// Bytes allowed for a gzip or deflate `Content-Encoding` body once decompressed, answered with a 413 when exceeded.
+  /// Other encodings are answered with a 415. Defaults to `16777216`
+  pub max_decompressed_size: Option<u32>,
+  /// Bytes allowed for a single multipart part, `req.multipart()` rejects with a 413 when exceeded. Defaults to `16777216`
+  pub multipart_max_part_size: Option<u32>,
+  /// Bytes allowed for all the multipart parts together, `req.multipart()` rejects with a 413 when exceeded. Defaults to `16777216`
+  pub multipart_max_total_size: Option<u32>,
+  /// Uploaded files larger than this are written to a temp file instead of kept in memory. Defaults to `1048576`
+  pub multipart_memory_limit: Option<u32>,
+}
+
+/// Options of a single route, the limits override the ones set in `AouOptions`.
+#[napi(object)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AouRouteOptions {
+  pub max_header_bytes: Option<u32>,
+  pub max_header_count: Option<u32>,
+  pub max_body_size: Option<u32>,
+  pub max_decompressed_size: Option<u32>,
+  pub compression: Option<bool>,
+  pub compression_min_size: Option<u32>,
+  /// Turns `etag` on or off for the route, strong ETags are used when the server has no `etag` mode
+  pub etag: Option<bool>,
+}
+
+impl AouRouteOptions {
+  pub fn apply(&self, limits: RequestLimits) -> RequestLimits {
+    RequestLimits {
+      max_uri_length: limits.max_uri_length,
+      max_header_bytes: self
+        .max_header_bytes
+        .map_or(limits.max_header_bytes, |max| max as usize),
+      max_header_count: self
+        .max_header_count
+        .map_or(limits.max_header_count, |max| max as usize),
+      max_body_size: self
+        .max_body_size
+        .map_or(limits.max_body_size, |max| max as usize),
+      max_decompressed_size: self
+        .max_decompressed_size
+        .map_or(limits.max_decompressed_size, |max| max as usize),
+      multipart: limits.multipart,
+    }
+  }
+}
+
+/// What answers the requests of a route
+#[derive(Clone)]
+pub enum Handler {
+  Js(ThreadsafeFunction<Request, ErrorStrategy::Fatal>),
+  Static(Arc<StaticFiles>),
+  WebSocket(Arc<WebSocketRoute>),
+}
+
+/// A handler and the options it was registered with
+#[derive(Clone)]
+pub struct RouteHandler {
+  pub function: Handler,
+  pub options: AouRouteOptions,
+}
+
+impl RouteHandler {
+  fn new(function: JsFunction, options: Option<AouRouteOptions>) -> Self {
+    let function: ThreadsafeFunction<Request, ErrorStrategy::Fatal> = function
+      .create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))
+      .unwrap();
+
+    RouteHandler {
+      function: Handler::Js(function),
+      options: options.unwrap_or_default(),
+    }
+  }
+
+  fn files(files: StaticFiles) -> Self {
+    RouteHandler {
+      function: Handler::Static(Arc::new(files)),
+      options: Default::default(),
+    }
+  }
+
+  fn websocket(websocket: WebSocketRoute) -> Self {
+    RouteHandler {
+      function: Handler::WebSocket(Arc::new(websocket)),
+      options: Default::default(),
+    }
+  }
+}
+
+pub type AouRouter = matchit::Router<Route<RouteHandler>>;
+
+/// Limits from `AouOptions`, overridden by the options of the route a request matches
+struct RouterLimits<'r> {
+  router: &'r AouRouter,
+  options: &'r AouOptions,
+  limits: RequestLimits,
+}
+
+impl ResolveLimits for RouterLimits<'_> {
+  fn limits(&self) -> &RequestLimits {
+    &self.limits
+  }
+
+  fn route_limits(&self, method: &str, path: &str) -> RequestLimits {
+    let (path, _query) = path.split_once('?').unwrap_or((path, ""));
+
+    let (Ok(method), Ok(path)) = (HttpMethod::from_str(method), utils::path::normalize(path))
+    else {
+      return self.limits;
+    };
+    let alternate = utils::path::toggle_trailing_slash(&path);
+
+    match AouServer::find_route(self.router, &path, &alternate, method, self.options) {
+      Ok((_, handler)) => handler.options.apply(self.limits),
+      Err(_) => self.limits,
+    }
+  }
+}
+
+impl AouOptions {
+  pub fn auto_head(&self) -> bool {
+    self.auto_head.unwrap_or(true)
+  }
+
+  pub fn auto_options(&self) -> bool {
+    self.auto_options.unwrap_or(true)
+  }
+
+  pub fn nested_query(&self) -> bool {
+    self.nested_query.unwrap_or(false)
+  }
+
+  pub fn trailing_slash(&self) -> TrailingSlash {
+    self
+      .trailing_slash
+      .as_deref()
+      .and_then(|policy| policy.parse().ok())
+      .unwrap_or_default()
+  }
+
+  pub fn etag(&self) -> Option<ETagMode> {
+    self.etag.as_deref().and_then(|mode| mode.parse().ok())
+  }
+
+  pub fn request_timeouts(&self) -> RequestTimeouts {
+    let defaults = RequestTimeouts::default();
+    let millis = |timeout: Option<u32>, default: Duration| {
+      timeout.map_or(default, |timeout| Duration::from_millis(timeout as u64))
+    };
+
+    RequestTimeouts {
+      keep_alive: millis(self.keep_alive_timeout, defaults.keep_alive),
+      header: millis(self.header_timeout, defaults.header),
+      body: millis(self.body_timeout, defaults.body),
+    }
+  }
+
+  pub fn request_limits(&self) -> RequestLimits {
+    let defaults = RequestLimits::default();
+    let size = |max: Option<u32>, default: usize| max.map_or(default, |max| max as usize);
+
+    RequestLimits {
+      max_uri_length: size(self.max_uri_length, defaults.max_uri_length),
+      max_header_bytes: size(self.max_header_bytes, defaults.max_header_bytes),
+      max_header_count: size(self.max_header_count, defaults.max_header_count),
+      max_body_size: size(self.max_body_size, defaults.max_body_size),
+      max_decompressed_size: size(self.max_decompressed_size, defaults.max_decompressed_size),
+      multipart: self.multipart_limits(),
+    }
+  }
+
+  pub fn multipart_limits(&self) -> MultipartLimits {
+    let defaults = MultipartLimits::default();
+    let size = |max: Option<u32>, default: usize| max.map_or(default, |max| max as usize);
+
+    MultipartLimits {
+      max_part_size: size(self.multipart_max_part_size, defaults.max_part_size),
+      max_total_size: size(self.multipart_max_total_size, defaults.max_total_size),
+      memory_limit: size(self.multipart_memory_limit, defaults.memory_limit),
+    }
+  }
+
+  pub fn compression_types(&self) -> Vec<String> {
+    self
+      .compression_types
+      .clone()
+      .unwrap_or_else(|| COMPRESSION_TYPES.into_iter().map(str::to_owned).collect())
+  }
+
+  pub fn handler_timeout(&self) -> Option<Duration> {
+    self
+      .handler_timeout
+      .map(|timeout| Duration::from_millis(timeout as u64))
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HandlerTimeoutError {
+  #[error("Handler couldn't be called in time")]
+  Unavailable,
+  #[error("Handler didn't respond in time")]
+  Response,
+}
+
+impl HandlerTimeoutError {
+  pub fn status(&self) -> u32 {
+    match self {
+      HandlerTimeoutError::Unavailable => 503,
+      HandlerTimeoutError::Response => 504,
+    }
+  }
+}
+
+#[napi(object)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AouCloseOptions {
+  /// Milliseconds to wait for in-flight requests before force closing the remaining connections
+  pub timeout: Option<u32>,
+}
+
+#[napi]
+impl AouInstance {
+  /// Stops accepting connections, closes idle connections and resolves once every in-flight request is done.
+  #[napi]
+  pub async fn close(&self, options: Option<AouCloseOptions>) -> Result<()> {
+    let options = options.unwrap_or_default();
+
+    self.shutdown.send_replace(true);
+
+    let accept_loop = self
+      .accept_loop
+      .lock()
+      .expect("Accept loop lock poisoned")
+      .take();
+
+    let Some(accept_loop) = accept_loop else {
+      return Ok(());
+    };
+
+    let mut connections = accept_loop
+      .await
+      .map_err(|err| Error::from_reason(format!("Accept loop panicked {err}")))?;
+
+    info!("Draining {} connections", connections.len());
+
+    let drain = async { while connections.join_next().await.is_some() {} };
+
+    match options.timeout {
+      Some(timeout) => {
+        let timeout = tokio::time::Duration::from_millis(timeout as u64);
+
+        if tokio::time::timeout(timeout, drain).await.is_err() {
+          info!("Force closing {} connections", connections.len());
+          connections.shutdown().await;
+        }
+      }
+      None => drain.await,
+    }
+
+    Ok(())
+  }
 }
 
 #[napi]
 pub struct AouServer {
-  router: matchit::Router<Route<ThreadsafeFunction<Request, ErrorStrategy::Fatal>>>,
+  router: AouRouter,
   options: AouOptions,
+  sessions: Option<Arc<Sessions>>,
+  topics: Arc<Topics>,
 }
 
 #[napi]
 impl AouServer {
   #[napi(constructor)]
-  pub fn new(options: Option<AouOptions>) -> Self {
+  pub fn new(options: Option<AouOptions>) -> Result<Self> {
     let options = options.unwrap_or_default();
 
-    AouServer {
+    if let Some(policy) = &options.trailing_slash {
+      policy
+        .parse::<TrailingSlash>()
+        .map_err(|err| Error::new(Status::InvalidArg, err))?;
+    }
+
+    if let Some(mode) = &options.etag {
+      mode
+        .parse::<ETagMode>()
+        .map_err(|err| Error::new(Status::InvalidArg, err))?;
+    }
+
+    // The keys are derived once and shared by every connection
+    let sessions = match &options.session {
+      Some(session) => {
+        Some(Arc::new(Sessions::new(session.clone()).map_err(|err| {
+          Error::new(Status::InvalidArg, err.to_string())
+        })?))
+      }
+      None => None,
+    };
+
+    Ok(AouServer {
       router: matchit::Router::new(),
       options,
-    }
+      sessions,
+      topics: Default::default(),
+    })
   }
 
   #[napi]
@@ -74,6 +419,8 @@ impl AouServer {
 
     let router = Arc::new(self.router.clone());
     let router2 = router.clone();
+    let options = self.options.clone();
+    let sessions = self.sessions.clone();
 
     let addr = format!("{host}:{port}")
       .parse::<SocketAddrV4>()
@@ -83,56 +430,134 @@ impl AouServer {
       .await
       .expect("Couldn't establish tcp connection");
 
-    tokio::spawn(async move {
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let mut stop_accepting = shutdown_rx.clone();
+
+    let accept_loop = tokio::spawn(async move {
       let router = router;
+      let mut connections = JoinSet::new();
 
       loop {
-        let (stream, mut _addr) = listener.accept().await.expect("Failed to accept socket");
-        let router = router.clone();
+        tokio::select! {
+          accepted = listener.accept() => {
+            let (stream, mut _addr) = match accepted {
+              Ok(accepted) => accepted,
+              Err(err) => {
+                error!("Failed to accept socket {err}");
+                continue;
+              }
+            };
+            let router = router.clone();
+            let options = options.clone();
+            let shutdown = shutdown_rx.clone();
+
+            let sessions = sessions.clone();
 
-        tokio::spawn(async move { handle_connection(stream, router).await });
+            connections.spawn(async move {
+              handle_connection(stream, router, options, sessions, shutdown).await
+            });
+          },
+          Some(_) = connections.join_next(), if !connections.is_empty() => (),
+          _ = stop_accepting.wait_for(|closing| *closing) => break,
+        }
       }
+
+      debug!("Stopped accepting connections");
+      connections
     });
 
     AouInstance {
       ip: addr.ip().to_string(),
       port: addr.port() as u32,
       _router: router2,
-      _options: self.options,
+      _options: self.options.clone(),
+      shutdown,
+      accept_loop: Mutex::new(Some(accept_loop)),
     }
   }
 
   fn match_route<'r, 'f>(
-    router: &'r matchit::Router<Route<ThreadsafeFunction<Request, ErrorStrategy::Fatal>>>,
+    router: &'r AouRouter,
     route: &'r str,
     method: HttpMethod,
-  ) -> Option<(
-    Match<'r, 'r, &'r Route<ThreadsafeFunction<Request, ErrorStrategy::Fatal>>>,
-    &'f ThreadsafeFunction<Request, ErrorStrategy::Fatal>,
-  )>
+    options: &AouOptions,
+  ) -> std::result::Result<
+    (Match<'r, 'r, &'r Route<RouteHandler>>, &'f RouteHandler),
+    RouteMatchError,
+  >
   where
     'r: 'f,
   {
     let route = match router.at(route) {
       Ok(h) => h,
       Err(_) => {
-        return None;
+        return Err(RouteMatchError::NotFound);
       }
     };
 
-    match (route.value.get_method(method), route.value.get_all()) {
-      (Some(r), _) => Some((route, r)),
-      (None, Some(r)) => Some((route, r)),
-      (None, None) => None,
+    let fallback = match method {
+      HttpMethod::HEAD if options.auto_head() => route.value.get_method(HttpMethod::GET),
+      _ => &None,
+    };
+
+    match (
+      route.value.get_method(method),
+      fallback,
+      route.value.get_all(),
+    ) {
+      (Some(r), _, _) => Ok((route, r)),
+      (None, Some(r), _) => Ok((route, r)),
+      (None, None, Some(r)) => Ok((route, r)),
+      (None, None, None) => Err(RouteMatchError::MethodNotAllowed(Self::allowed_methods(
+        route.value,
+        options,
+      ))),
     }
   }
 
-  fn insert_all(&mut self, route: String, function: JsFunction) {
-    let handler: ThreadsafeFunction<Request, ErrorStrategy::Fatal> = function
-      .create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))
-      .unwrap();
+  /// Matches the normalized `path`, falling back to `alternate`, the path with its trailing slash
+  /// toggled, as the `trailingSlash` option allows.
+  fn find_route<'r, 'f>(
+    router: &'r AouRouter,
+    path: &'r str,
+    alternate: &'r str,
+    method: HttpMethod,
+    options: &AouOptions,
+  ) -> std::result::Result<
+    (Match<'r, 'r, &'r Route<RouteHandler>>, &'f RouteHandler),
+    RouteMatchError,
+  >
+  where
+    'r: 'f,
+  {
+    match Self::match_route(router, path, method, options) {
+      Err(RouteMatchError::NotFound) if path != alternate => match options.trailing_slash() {
+        TrailingSlash::Strict => Err(RouteMatchError::NotFound),
+        TrailingSlash::Lenient => Self::match_route(router, alternate, method, options),
+        TrailingSlash::Redirect => match Self::match_route(router, alternate, method, options) {
+          Err(RouteMatchError::NotFound) => Err(RouteMatchError::NotFound),
+          _ => Err(RouteMatchError::Redirect(alternate.to_owned())),
+        },
+      },
+      result => result,
+    }
+  }
 
-    let mut new_route = Route::<ThreadsafeFunction<Request, ErrorStrategy::Fatal>>::default();
+  /// Methods registered on the route plus the ones answered automatically
+  fn allowed_methods<T>(route: &Route<T>, options: &AouOptions) -> Vec<HttpMethod> {
+    let mut automatic = Vec::with_capacity(2);
+    if options.auto_head() && route.has_method(HttpMethod::GET) {
+      automatic.push(HttpMethod::HEAD);
+    }
+    if options.auto_options() {
+      automatic.push(HttpMethod::OPTIONS);
+    }
+
+    route.methods(&automatic)
+  }
+
+  fn insert_all(&mut self, route: String, handler: RouteHandler) {
+    let mut new_route = Route::<RouteHandler>::default();
     new_route.set_all(handler.clone());
 
     match self.router.insert(route.as_str(), new_route) {
@@ -147,12 +572,8 @@ impl AouServer {
     }
   }
 
-  fn insert_route(&mut self, route: String, method: HttpMethod, function: JsFunction) {
-    let handler: ThreadsafeFunction<Request, ErrorStrategy::Fatal> = function
-      .create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))
-      .unwrap();
-
-    let mut new_route = Route::<ThreadsafeFunction<Request, ErrorStrategy::Fatal>>::default();
+  fn insert_route(&mut self, route: String, method: HttpMethod, handler: RouteHandler) {
+    let mut new_route = Route::<RouteHandler>::default();
     new_route.set_method(method, handler.clone());
 
     match self.router.insert(route.as_str(), new_route) {
@@ -171,76 +592,218 @@ impl AouServer {
     };
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn get(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::GET, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn get(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(route, HttpMethod::GET, RouteHandler::new(handler, options));
+    Ok(())
+  }
+
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn head(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(route, HttpMethod::HEAD, RouteHandler::new(handler, options));
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn head(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::HEAD, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn post(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(route, HttpMethod::POST, RouteHandler::new(handler, options));
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn post(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::POST, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn put(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(route, HttpMethod::PUT, RouteHandler::new(handler, options));
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn put(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::PUT, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn delete(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(
+      route,
+      HttpMethod::DELETE,
+      RouteHandler::new(handler, options),
+    );
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn delete(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::DELETE, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn connect(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(
+      route,
+      HttpMethod::CONNECT,
+      RouteHandler::new(handler, options),
+    );
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn connect(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::CONNECT, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn options(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(
+      route,
+      HttpMethod::OPTIONS,
+      RouteHandler::new(handler, options),
+    );
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn options(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::OPTIONS, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn trace(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(
+      route,
+      HttpMethod::TRACE,
+      RouteHandler::new(handler, options),
+    );
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn trace(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::TRACE, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn patch(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_route(
+      route,
+      HttpMethod::PATCH,
+      RouteHandler::new(handler, options),
+    );
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn patch(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_route(route, HttpMethod::PATCH, handler);
+  #[napi(ts_args_type = "route:void,handler:void,options?:AouRouteOptions")]
+  pub fn all(
+    &mut self,
+    route: String,
+    handler: JsFunction,
+    options: Option<AouRouteOptions>,
+  ) -> Result<()> {
+    self.insert_all(route, RouteHandler::new(handler, options));
+    Ok(())
+  }
+
+  /// Serves the files in `dir` on GET requests to `route`, its last parameter is the path of the file.
+  /// Files are streamed to the socket without going through JS
+  #[napi(js_name = "static")]
+  pub fn serve_static(
+    &mut self,
+    route: String,
+    dir: String,
+    options: Option<StaticOptions>,
+  ) -> Result<()> {
+    let files = StaticFiles::new(&dir, options.unwrap_or_default())
+      .map_err(|err| Error::new(Status::InvalidArg, err.to_string()))?;
+
+    self.insert_route(route, HttpMethod::GET, RouteHandler::files(files));
     Ok(())
   }
 
-  #[napi(ts_args_type = "route:void,handler:void")]
-  pub fn all(&mut self, route: String, handler: JsFunction) -> Result<()> {
-    self.insert_all(route, handler);
+  /// Upgrades GET requests to `route` to WebSockets, frames are read and written by the server
+  /// and `handlers` are called with the messages
+  #[napi(
+    ts_args_type = "route: string, handlers: AouWebSocketHandlers, options?: AouWebSocketOptions"
+  )]
+  pub fn ws(
+    &mut self,
+    env: Env,
+    route: String,
+    handlers: JsObject,
+    options: Option<WebSocketOptions>,
+  ) -> Result<()> {
+    let websocket = WebSocketRoute::new(env, handlers, options, self.topics.clone())?;
+
+    self.insert_route(route, HttpMethod::GET, RouteHandler::websocket(websocket));
     Ok(())
   }
+
+  /// Sends a message to every socket subscribed to `topic`, from any `server.ws()` route.
+  /// Returns the number of sockets it was queued on, slow consumers are left out by their backpressure policy
+  #[napi(ts_args_type = "topic: string, data: string | Buffer")]
+  pub fn publish(&self, topic: String, data: Either<String, Buffer>) -> u32 {
+    let (opcode, payload) = websocket::message(data);
+    self.topics.publish(&topic, opcode, payload, None)
+  }
+
+  /// Number of sockets subscribed to `topic`
+  #[napi]
+  pub fn subscribers(&self, topic: String) -> u32 {
+    self.topics.subscribers(&topic)
+  }
 }
 
 pub async fn handle_connection<TStream>(
   mut stream: TStream,
-  router: Arc<matchit::Router<Route<ThreadsafeFunction<Request, ErrorStrategy::Fatal>>>>,
+  router: Arc<AouRouter>,
+  options: AouOptions,
+  sessions: Option<Arc<Sessions>>,
+  mut shutdown: watch::Receiver<bool>,
 ) -> anyhow::Result<()>
 where
-  TStream: AsyncRead + AsyncWrite + Unpin,
+  TStream: AsyncRead + SendFile,
 {
+  let timeouts = options.request_timeouts();
+  let compression_types = options.compression_types();
+  let limits = RouterLimits {
+    router: router.as_ref(),
+    options: &options,
+    limits: options.request_limits(),
+  };
+
   loop {
-    let mut req = match request::handle_request(&mut stream).await {
+    // Only idle connections are closed on shutdown, a request whose first byte arrived is finished first.
+    let start = tokio::select! {
+      start = request::read_request_start(&mut stream, &timeouts) => start,
+      Ok(_) = shutdown.wait_for(|closing| *closing) => {
+        debug!("Closing idle connection on shutdown");
+        break;
+      }
+    };
+    let req = match start {
+      Ok(buf) => request::read_request(&mut stream, buf, &timeouts, &limits).await,
+      Err(err) => Err(err),
+    };
+
+    let mut req = match req {
       Ok(req) => req,
       Err(request::HandleRequestError::EOF) => {
         info!("EOF");
@@ -250,45 +813,285 @@ where
         info!("Connection Timeout");
         return Err(anyhow!("Timeout"));
       }
-      Err(request::HandleRequestError::Invalid(err)) => {
-        error!("Invalid Request {err}");
-        return Err(err);
-      }
-    };
+      Err(err) => match err.status() {
+        Some(status) => {
+          error!("Rejected Request {err}");
 
-    let should_close = req.get_connection() == &Connection::Close;
-    let method = HttpMethod::from_str(req.method()).expect("Method not supported"); // TODO : Return actual method not allowed response
-                                                                                    //
-    let path = req.path().to_owned();
-    let (path, _query) = path.split_once('?').unwrap_or((&path, ""));
+          write_closing_response(
+            &mut stream,
+            status,
+            err.to_string(),
+            &ResponseOptions::default(),
+          )
+          .await?;
 
-    info!("{method} {path}");
+          return Err(err.into());
+        }
+        None => {
+          error!("Failed to read request {err}");
+          return Err(err.into());
+        }
+      },
+    };
 
-    let (route, handler) = match AouServer::match_route(router.as_ref(), path, method) {
-      Some(_match) => _match,
-      None => {
-        debug!("Route not found {path}");
+    let should_close = req.get_connection() == &Connection::Close || *shutdown.borrow();
+    let method = match HttpMethod::from_str(req.method()) {
+      Ok(method) => method,
+      Err(_) => {
+        debug!("Method not implemented {}", req.method());
         let res = Response {
-          status: Some(404),
+          status: Some(501),
           ..Default::default()
         };
 
-        res.write_to_stream(&mut stream, &HashMap::new()).await?; //TODO: static headers.
+        res
+          .write_to_stream(&mut stream, &HashMap::new(), &ResponseOptions::default())
+          .await?;
         stream.flush().await?;
 
-        return Err(anyhow!("Route Not Found"));
+        return Err(anyhow!("Method Not Implemented"));
       }
     };
 
+    let response_options = ResponseOptions {
+      head: method == HttpMethod::HEAD,
+      // Ranges are only defined for GET, HEAD still advertises them
+      range: match method {
+        HttpMethod::GET => Some(RangeRequest {
+          range: req.header("range".to_owned()),
+          if_range: req.header("if-range".to_owned()),
+        }),
+        HttpMethod::HEAD => Some(RangeRequest::default()),
+        _ => None,
+      },
+      conditional: matches!(method, HttpMethod::GET | HttpMethod::HEAD).then(|| {
+        ConditionalRequest {
+          if_none_match: req.header("if-none-match".to_owned()),
+          if_modified_since: req.header("if-modified-since".to_owned()),
+        }
+      }),
+      close: should_close,
+      ..Default::default()
+    };
+
+    let path = match utils::path::normalize(req.raw_path()) {
+      Ok(path) => path,
+      Err(err) => {
+        error!("Rejected Request {err}");
+        write_closing_response(&mut stream, 400, err.to_string(), &response_options).await?;
+
+        return Err(err.into());
+      }
+    };
+    let alternate = utils::path::toggle_trailing_slash(&path);
+    req.set_path(path.clone());
+
+    info!("{method} {path}");
+
+    let (route, handler) =
+      match AouServer::find_route(router.as_ref(), &path, &alternate, method, &options) {
+        Ok(_match) => _match,
+        Err(RouteMatchError::Redirect(location)) => {
+          debug!("Redirecting {path} to {location}");
+          let location = match req.raw_query() {
+            "" => utils::path::encode(&location),
+            query => format!("{}?{query}", utils::path::encode(&location)),
+          };
+          let res = Response {
+            status: Some(308),
+            headers: Some(HashMap::from([("Location".to_owned(), location.into())])),
+            ..Default::default()
+          };
+
+          res
+            .write_to_stream(&mut stream, &HashMap::new(), &response_options)
+            .await?;
+          stream.flush().await?;
+
+          if should_close {
+            break;
+          }
+          continue;
+        }
+        Err(RouteMatchError::MethodNotAllowed(methods))
+          if method == HttpMethod::OPTIONS && options.auto_options() =>
+        {
+          debug!("Automatic OPTIONS at {path}");
+          let res = Response {
+            status: Some(204),
+            headers: Some(HashMap::from([(
+              "Allow".to_owned(),
+              route::allow_header(&methods).into(),
+            )])),
+            ..Default::default()
+          };
+
+          res
+            .write_to_stream(&mut stream, &HashMap::new(), &response_options)
+            .await?;
+          stream.flush().await?;
+
+          if should_close {
+            break;
+          }
+          continue;
+        }
+        Err(RouteMatchError::NotFound) => {
+          debug!("Route not found {path}");
+          let res = Response {
+            status: Some(404),
+            ..Default::default()
+          };
+
+          res
+            .write_to_stream(&mut stream, &HashMap::new(), &response_options)
+            .await?; //TODO: static headers.
+          stream.flush().await?;
+
+          return Err(anyhow!("Route Not Found"));
+        }
+        Err(RouteMatchError::MethodNotAllowed(methods)) => {
+          debug!("Method {method} not allowed at {path}");
+          let res = Response {
+            status: Some(405),
+            headers: Some(HashMap::from([(
+              "Allow".to_owned(),
+              route::allow_header(&methods).into(),
+            )])),
+            ..Default::default()
+          };
+
+          res
+            .write_to_stream(&mut stream, &HashMap::new(), &response_options)
+            .await?;
+          stream.flush().await?;
+
+          return Err(anyhow!("Method Not Allowed"));
+        }
+      };
+
     req.params = route
       .params
       .iter()
-      .map(|(k, v)| (k.to_owned(), v.to_owned()))
+      .map(|(k, v)| (k.to_owned(), utils::path::decode_param(v)))
       .collect();
 
-    let r = handler.call_async::<Promise<Response>>(req).await?;
+    let function = match &handler.function {
+      Handler::Js(function) => function,
+      Handler::Static(files) => {
+        let file = route
+          .params
+          .iter()
+          .last()
+          .map(|(_, path)| utils::path::decode_param(path))
+          .unwrap_or_default();
+
+        files.serve(&mut stream, &file, &response_options).await?;
+        stream.flush().await?;
+
+        if should_close {
+          break;
+        }
+        continue;
+      }
+      Handler::WebSocket(websocket) => {
+        let handshake = match method {
+          HttpMethod::GET => websocket.handshake(&req),
+          _ => Err(HandshakeError::NotAnUpgrade),
+        };
 
-    let res: Response = match r.await {
+        let res = match &handshake {
+          Ok(handshake) => Response {
+            status: Some(101),
+            headers: Some(handshake.headers()),
+            ..Default::default()
+          },
+          Err(err) => {
+            debug!("Rejected WebSocket upgrade {err}");
+            Response {
+              status: Some(err.status()),
+              headers: Some(err.headers()),
+              body: serde_json::Value::String(err.to_string()),
+              ..Default::default()
+            }
+          }
+        };
+        res
+          .write_to_stream(&mut stream, &HashMap::new(), &response_options)
+          .await?;
+        stream.flush().await?;
+
+        match handshake {
+          // The connection belongs to the WebSocket from now on
+          Ok(handshake) => return websocket.serve(stream, req, handshake, shutdown).await,
+          Err(_) if should_close => break,
+          Err(_) => continue,
+        }
+      }
+    };
+
+    if options.nested_query() {
+      req.query = urlencoded::decode_nested(req.raw_query().as_bytes());
+    }
+    req.set_multipart_limits(options.multipart_limits());
+
+    // Static files always have validators, handler responses are only checked once ETags are enabled
+    let etag = etag(&options, &handler.options);
+    let response_options = ResponseOptions {
+      compression: compression(&options, &handler.options, &compression_types, &req),
+      conditional: response_options.conditional.filter(|_| etag.is_some()),
+      etag,
+      ..response_options
+    };
+
+    let session = sessions
+      .as_ref()
+      .map(|sessions| sessions.load(&req.cookies()));
+    if let Some(session) = &session {
+      req.set_session(session.clone());
+    }
+
+    // Uploads the handler didn't move away are removed once the request is done, even on errors.
+    let _temp_files = RemoveTempFiles(req.temp_files().clone());
+
+    let deadline = options
+      .handler_timeout()
+      .map(|timeout| Instant::now() + timeout);
+
+    // Waiting for the call itself means the JS thread is too busy to even start the handler.
+    let r = match until_deadline(deadline, function.call_async::<Promise<Response>>(req)).await {
+      Some(r) => r?,
+      None => {
+        let err = HandlerTimeoutError::Unavailable;
+        error!("{err} {method} {path}");
+        write_closing_response(
+          &mut stream,
+          err.status(),
+          err.to_string(),
+          &response_options,
+        )
+        .await?;
+        return Err(err.into());
+      }
+    };
+
+    let r = match until_deadline(deadline, r).await {
+      Some(r) => r,
+      None => {
+        let err = HandlerTimeoutError::Response;
+        error!("{err} {method} {path}");
+        write_closing_response(
+          &mut stream,
+          err.status(),
+          err.to_string(),
+          &response_options,
+        )
+        .await?;
+        return Err(err.into());
+      }
+    };
+
+    let res: Response = match r {
       Ok(r) => r,
       Err(err) => {
         let err: napi::Error = err;
@@ -305,7 +1108,7 @@ where
             error!("AouError: {err:?}");
 
             <AouError as Into<Response>>::into(err)
-              .write_to_stream(&mut stream, &HashMap::new())
+              .write_to_stream(&mut stream, &HashMap::new(), &response_options)
               .await?;
           }
           None => {
@@ -316,7 +1119,7 @@ where
               body: serde_json::Value::String(err.reason),
               ..Default::default()
             }
-            .write_to_stream(&mut stream, &HashMap::new())
+            .write_to_stream(&mut stream, &HashMap::new(), &response_options)
             .await?;
           }
         };
@@ -325,7 +1128,21 @@ where
       }
     };
 
-    res.write_to_stream(&mut stream, &HashMap::new()).await?;
+    let res = match (&sessions, &session) {
+      (Some(sessions), Some(session)) => with_session_cookie(res, sessions, session),
+      _ => res,
+    };
+
+    // The server may have started shutting down while the handler ran
+    let should_close = should_close || *shutdown.borrow();
+    let response_options = ResponseOptions {
+      close: should_close,
+      ..response_options
+    };
+
+    res
+      .write_to_stream(&mut stream, &HashMap::new(), &response_options)
+      .await?;
 
     stream.flush().await?;
 
@@ -337,3 +1154,98 @@ where
   debug!("Closing connection");
   Ok::<(), anyhow::Error>(())
 }
+
+/// Adds the session cookie if the handler changed the session, a session too large for a cookie is a 500.
+fn with_session_cookie(mut res: Response, sessions: &Sessions, session: &AouSession) -> Response {
+  match sessions.cookie(session) {
+    Ok(Some(cookie)) => {
+      res.cookies.get_or_insert_with(Vec::new).push(cookie);
+      res
+    }
+    Ok(None) => res,
+    Err(err) => {
+      error!("{err}");
+      Response {
+        status: Some(500),
+        body: serde_json::Value::String(err.to_string()),
+        ..Default::default()
+      }
+    }
+  }
+}
+
+/// Compression of the route's responses, the route options override the server ones.
+fn compression<'a>(
+  options: &AouOptions,
+  route_options: &AouRouteOptions,
+  content_types: &'a [String],
+  req: &Request,
+) -> Option<Compression<'a>> {
+  if !route_options
+    .compression
+    .or(options.compression)
+    .unwrap_or(false)
+  {
+    return None;
+  }
+
+  Some(Compression {
+    encoding: ContentEncoding::negotiate(req.header_values(b"accept-encoding")),
+    min_size: route_options
+      .compression_min_size
+      .or(options.compression_min_size)
+      .map_or(COMPRESSION_MIN_SIZE, |min| min as usize),
+    content_types,
+  })
+}
+
+/// ETag mode of the route's responses, the route options can turn it on or off.
+fn etag(options: &AouOptions, route_options: &AouRouteOptions) -> Option<ETagMode> {
+  match route_options.etag {
+    Some(true) => Some(options.etag().unwrap_or_default()),
+    Some(false) => None,
+    None => options.etag(),
+  }
+}
+
+/// Resolves to `None` if the deadline passes first, waits forever without one.
+async fn until_deadline<F>(deadline: Option<Instant>, future: F) -> Option<F::Output>
+where
+  F: std::future::Future,
+{
+  match deadline {
+    Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+    None => Some(future.await),
+  }
+}
+
+/// Writes an error response that tells the client the connection is about to be closed.
+async fn write_closing_response<TStream>(
+  stream: &mut TStream,
+  status: u32,
+  message: String,
+  options: &ResponseOptions<'_>,
+) -> anyhow::Result<()>
+where
+  TStream: AsyncRead + AsyncWrite + Unpin,
+{
+  Response {
+    status: Some(status),
+    headers: Some(HashMap::from([("Connection".to_owned(), "close".into())])),
+    body: serde_json::Value::String(message),
+    ..Default::default()
+  }
+  .write_to_stream(stream, &HashMap::new(), options)
+  .await?;
+  stream.flush().await?;
+
+  Ok(())
+}
+
+struct RemoveTempFiles(TempFiles);
+
+impl Drop for RemoveTempFiles {
+  fn drop(&mut self) {
+    self.0.remove_all();
+  }
+}
@@ -0,0 +1,483 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use aes_gcm::{
+  aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+  Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{
+  constants::{MAX_COOKIE_SIZE, MIN_SESSION_SECRET_LENGTH, SESSION_COOKIE_NAME},
+  response::{Cookie, CookieExpires},
+};
+
+const NONCE_SIZE: usize = 12;
+/// HKDF `info` labels, so signing and encryption never share a key
+const SESSION_SIGN_INFO: &[u8] = b"aou.session sign";
+const SESSION_ENCRYPT_INFO: &[u8] = b"aou.session encrypt";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SessionError {
+  #[error("session.secrets needs at least one secret")]
+  NoSecrets,
+  #[error("Session secrets need at least {MIN_SESSION_SECRET_LENGTH} bytes")]
+  ShortSecret,
+  #[error("Session cookie is {0} bytes, over the {MAX_COOKIE_SIZE} bytes browsers accept")]
+  TooLarge(usize),
+  #[error("Failed to encrypt the session")]
+  Encrypt,
+}
+
+/// Cookie sessions, configured with `AouOptions.session`.
+#[napi(object, js_name = "AouSessionOptions")]
+#[derive(Debug, Default, Clone)]
+pub struct SessionOptions {
+  /// Keys the session cookie is signed or encrypted with, at least 32 bytes of UTF-8 each.
+  /// The first one is used for new cookies and the others are still accepted, so keys can be rotated
+  pub secrets: Vec<String>,
+  /// Encrypt the session with AES-256-GCM so the client can't read it, instead of only signing it with HMAC-SHA256. Defaults to `false`
+  pub encrypt: Option<bool>,
+  /// Defaults to `"aou.session"`
+  pub cookie_name: Option<String>,
+  /// Seconds a session lasts after its last change, also enforced inside the cookie. Without it the cookie is dropped when the browser closes
+  pub max_age: Option<u32>,
+  pub domain: Option<String>,
+  /// Defaults to `"/"`
+  pub path: Option<String>,
+  /// Defaults to `false`
+  pub secure: Option<bool>,
+  /// Defaults to `true`
+  pub http_only: Option<bool>,
+  /// Defaults to `"Lax"`
+  #[napi(ts_type = "'Strict' | 'Lax' | 'None'")]
+  pub same_site: Option<String>,
+}
+
+/// What is stored in the cookie
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+  data: Map<String, Value>,
+  /// Seconds since the epoch
+  #[serde(skip_serializing_if = "Option::is_none")]
+  expires: Option<u64>,
+}
+
+#[derive(Clone)]
+struct SessionKey {
+  sign: Vec<u8>,
+  cipher: Aes256Gcm,
+}
+
+/// Loads sessions from the request cookies and turns changed sessions back into cookies.
+#[derive(Clone)]
+pub struct Sessions {
+  options: SessionOptions,
+  keys: Vec<SessionKey>,
+}
+
+impl Sessions {
+  pub fn new(options: SessionOptions) -> Result<Self, SessionError> {
+    if options.secrets.is_empty() {
+      return Err(SessionError::NoSecrets);
+    }
+
+    let keys = options
+      .secrets
+      .iter()
+      .map(|secret| {
+        if secret.len() < MIN_SESSION_SECRET_LENGTH {
+          return Err(SessionError::ShortSecret);
+        }
+
+        let secret = secret.as_bytes();
+        let cipher_key = hkdf_sha256(&[], secret, SESSION_ENCRYPT_INFO);
+
+        Ok(SessionKey {
+          sign: hkdf_sha256(&[], secret, SESSION_SIGN_INFO).to_vec(),
+          cipher: Aes256Gcm::new(&cipher_key.into()),
+        })
+      })
+      .collect::<Result<_, _>>()?;
+
+    Ok(Sessions { options, keys })
+  }
+
+  /// The session stored in the request cookies, or an empty one if it's missing, tampered with or expired.
+  pub fn load(&self, cookies: &HashMap<String, String>) -> AouSession {
+    let Some((envelope, key)) = cookies
+      .get(self.cookie_name())
+      .and_then(|cookie| self.decode(cookie))
+    else {
+      return AouSession::default();
+    };
+
+    if envelope.expires.is_some_and(|expires| expires <= now()) {
+      return AouSession::default();
+    }
+
+    // Cookies from a rotated out key are sent again with the current one.
+    AouSession::new(envelope.data, key != 0)
+  }
+
+  /// The `Set-Cookie` for `session`, `None` if it didn't change.
+  pub fn cookie(&self, session: &AouSession) -> Result<Option<Cookie>, SessionError> {
+    let state = session.state.lock().unwrap();
+
+    if !state.changed {
+      return Ok(None);
+    }
+
+    if state.destroyed {
+      return Ok(Some(Cookie {
+        max_age: Some(0),
+        expires: Some(CookieExpires(0.0)),
+        ..self.cookie_attributes(String::new())
+      }));
+    }
+
+    let envelope = Envelope {
+      data: state.data.clone(),
+      expires: self.options.max_age.map(|max_age| now() + max_age as u64),
+    };
+    let value = self.encode(&envelope)?;
+
+    let size = self.cookie_name().len() + 1 + value.len();
+    if size > MAX_COOKIE_SIZE {
+      return Err(SessionError::TooLarge(size));
+    }
+
+    Ok(Some(Cookie {
+      max_age: self.options.max_age.map(i64::from),
+      ..self.cookie_attributes(value)
+    }))
+  }
+
+  fn cookie_attributes(&self, value: String) -> Cookie {
+    Cookie {
+      name: self.cookie_name().to_owned(),
+      value,
+      domain: self.options.domain.clone(),
+      path: Some(self.options.path.clone().unwrap_or_else(|| "/".to_owned())),
+      secure: self.options.secure,
+      http_only: Some(self.options.http_only.unwrap_or(true)),
+      same_site: Some(
+        self
+          .options
+          .same_site
+          .clone()
+          .unwrap_or_else(|| "Lax".to_owned()),
+      ),
+      ..Default::default()
+    }
+  }
+
+  fn cookie_name(&self) -> &str {
+    self
+      .options
+      .cookie_name
+      .as_deref()
+      .unwrap_or(SESSION_COOKIE_NAME)
+  }
+
+  fn encrypted(&self) -> bool {
+    self.options.encrypt.unwrap_or(false)
+  }
+
+  /// Signed cookies are `payload.signature`, encrypted ones are `nonce + ciphertext`, all base64url.
+  /// The cookie name is part of the signature or the associated data, so values can't be moved between cookies.
+  fn encode(&self, envelope: &Envelope) -> Result<String, SessionError> {
+    let json = serde_json::to_vec(envelope).map_err(|_| SessionError::Encrypt)?;
+    let key = &self.keys[0];
+
+    if self.encrypted() {
+      let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+      let payload = Payload {
+        msg: &json,
+        aad: self.cookie_name().as_bytes(),
+      };
+      let ciphertext = key
+        .cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| SessionError::Encrypt)?;
+
+      return Ok(URL_SAFE_NO_PAD.encode([nonce.as_slice(), &ciphertext].concat()));
+    }
+
+    let payload = URL_SAFE_NO_PAD.encode(json);
+    let signature = URL_SAFE_NO_PAD.encode(self.signature(key, &payload).finalize().into_bytes());
+
+    Ok(format!("{payload}.{signature}"))
+  }
+
+  /// The envelope and the index of the key that opened it
+  fn decode(&self, cookie: &str) -> Option<(Envelope, usize)> {
+    let (json, key) = if self.encrypted() {
+      let bytes = URL_SAFE_NO_PAD.decode(cookie).ok()?;
+      if bytes.len() < NONCE_SIZE {
+        return None;
+      }
+      let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+
+      self.keys.iter().enumerate().find_map(|(index, key)| {
+        let payload = Payload {
+          msg: ciphertext,
+          aad: self.cookie_name().as_bytes(),
+        };
+        let json = key.cipher.decrypt(Nonce::from_slice(nonce), payload).ok()?;
+
+        Some((json, index))
+      })?
+    } else {
+      let (payload, signature) = cookie.rsplit_once('.')?;
+      let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+      let index = self.keys.iter().position(|key| {
+        self
+          .signature(key, payload)
+          .verify_slice(&signature)
+          .is_ok()
+      })?;
+
+      (URL_SAFE_NO_PAD.decode(payload).ok()?, index)
+    };
+
+    Some((serde_json::from_slice(&json).ok()?, key))
+  }
+
+  fn signature(&self, key: &SessionKey, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+      <Hmac<Sha256> as Mac>::new_from_slice(&key.sign).expect("HMAC accepts any key size");
+    mac.update(self.cookie_name().as_bytes());
+    mac.update(b"=");
+    mac.update(payload.as_bytes());
+
+    mac
+  }
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+  data: Map<String, Value>,
+  changed: bool,
+  destroyed: bool,
+}
+
+/// `req.session`, shared with the server so changes made by the handler are written back to the cookie.
+#[napi]
+#[derive(Debug, Clone, Default)]
+pub struct AouSession {
+  state: Arc<Mutex<SessionState>>,
+}
+
+#[napi]
+impl AouSession {
+  fn new(data: Map<String, Value>, changed: bool) -> Self {
+    AouSession {
+      state: Arc::new(Mutex::new(SessionState {
+        data,
+        changed,
+        destroyed: false,
+      })),
+    }
+  }
+
+  #[napi(ts_return_type = "any")]
+  pub fn get(&self, key: String) -> Option<Value> {
+    self.state.lock().unwrap().data.get(&key).cloned()
+  }
+
+  #[napi(ts_args_type = "key: string, value: any")]
+  pub fn set(&self, key: String, value: Value) {
+    let mut state = self.state.lock().unwrap();
+    state.data.insert(key, value);
+    state.changed = true;
+    state.destroyed = false;
+  }
+
+  /// Returns `false` if there was no value at `key`
+  #[napi]
+  pub fn delete(&self, key: String) -> bool {
+    let mut state = self.state.lock().unwrap();
+    let deleted = state.data.remove(&key).is_some();
+    state.changed |= deleted;
+
+    deleted
+  }
+
+  /// Removes every value and expires the session cookie
+  #[napi]
+  pub fn destroy(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.data.clear();
+    state.changed = true;
+    state.destroyed = true;
+  }
+
+  /// A copy of every value in the session
+  #[napi(getter, ts_return_type = "Record<string, any>")]
+  pub fn data(&self) -> Map<String, Value> {
+    self.state.lock().unwrap().data.clone()
+  }
+}
+
+/// HKDF-SHA256 (RFC 5869) with a single block of output, the 32 bytes a key needs
+fn hkdf_sha256(salt: &[u8], secret: &[u8], info: &[u8]) -> [u8; 32] {
+  let mut extract = <Hmac<Sha256> as Mac>::new_from_slice(salt).expect("HMAC accepts any key size");
+  extract.update(secret);
+  let prk = extract.finalize().into_bytes();
+
+  let mut expand = <Hmac<Sha256> as Mac>::new_from_slice(&prk).expect("HMAC accepts any key size");
+  expand.update(info);
+  expand.update(&[1]);
+
+  expand.finalize().into_bytes().into()
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs()
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use std::collections::HashMap;
+
+  use serde_json::json;
+
+  use super::{hkdf_sha256, SessionError, SessionOptions, Sessions};
+
+  const SECRET: &str = "0123456789abcdef0123456789abcdef";
+  const OLD_SECRET: &str = "fedcba9876543210fedcba9876543210";
+
+  fn sessions(secrets: &[&str], encrypt: bool) -> Sessions {
+    Sessions::new(SessionOptions {
+      secrets: secrets.iter().map(|secret| secret.to_string()).collect(),
+      encrypt: Some(encrypt),
+      max_age: Some(60),
+      ..Default::default()
+    })
+    .unwrap()
+  }
+
+  fn cookies(value: &str) -> HashMap<String, String> {
+    HashMap::from([("aou.session".to_owned(), value.to_owned())])
+  }
+
+  #[test]
+  fn round_trip() {
+    for encrypt in [false, true] {
+      let sessions = sessions(&[SECRET], encrypt);
+
+      let session = sessions.load(&HashMap::new());
+      assert_eq!(
+        sessions.cookie(&session),
+        Ok(None),
+        "Unchanged sessions aren't sent"
+      );
+
+      session.set("user".to_owned(), json!({ "id": 1 }));
+      let cookie = sessions.cookie(&session).unwrap().unwrap();
+      assert_eq!(cookie.name, "aou.session");
+      assert_eq!(cookie.max_age, Some(60));
+      assert_eq!(cookie.http_only, Some(true));
+      assert_eq!(
+        cookie.value.contains('.'),
+        !encrypt,
+        "Only signed sessions have a signature"
+      );
+
+      let loaded = sessions.load(&cookies(&cookie.value));
+      assert_eq!(loaded.get("user".to_owned()), Some(json!({ "id": 1 })));
+      assert_eq!(sessions.cookie(&loaded), Ok(None));
+
+      loaded.destroy();
+      let expired = sessions.cookie(&loaded).unwrap().unwrap();
+      assert_eq!((expired.value.as_str(), expired.max_age), ("", Some(0)));
+    }
+  }
+
+  #[test]
+  fn tampered_cookies_are_ignored() {
+    for encrypt in [false, true] {
+      let sessions = sessions(&[SECRET], encrypt);
+
+      let session = sessions.load(&HashMap::new());
+      session.set("admin".to_owned(), json!(false));
+      let value = sessions.cookie(&session).unwrap().unwrap().value;
+
+      let mut tampered = value.clone().into_bytes();
+      tampered[4] = if tampered[4] == b'A' { b'B' } else { b'A' };
+      let tampered = String::from_utf8(tampered).unwrap();
+
+      assert!(sessions.load(&cookies(&tampered)).data().is_empty());
+      assert!(sessions.load(&cookies("garbage")).data().is_empty());
+
+      let other_key = self::sessions(&[OLD_SECRET], encrypt);
+      assert!(other_key.load(&cookies(&value)).data().is_empty());
+    }
+  }
+
+  #[test]
+  fn rotated_keys() {
+    for encrypt in [false, true] {
+      let old = sessions(&[OLD_SECRET], encrypt);
+      let session = old.load(&HashMap::new());
+      session.set("user".to_owned(), json!("aou"));
+      let old_cookie = old.cookie(&session).unwrap().unwrap().value;
+
+      let rotated = sessions(&[SECRET, OLD_SECRET], encrypt);
+      let loaded = rotated.load(&cookies(&old_cookie));
+      assert_eq!(loaded.get("user".to_owned()), Some(json!("aou")));
+
+      let reissued = rotated.cookie(&loaded).unwrap().unwrap().value;
+      let current = sessions(&[SECRET], encrypt);
+      assert_eq!(
+        current.load(&cookies(&reissued)).get("user".to_owned()),
+        Some(json!("aou")),
+        "Sessions from an old key are reissued with the current one"
+      );
+    }
+  }
+
+  #[test]
+  fn invalid_options() {
+    let options = |secrets: Vec<String>| SessionOptions {
+      secrets,
+      ..Default::default()
+    };
+
+    assert_eq!(
+      Sessions::new(options(vec![])).err(),
+      Some(SessionError::NoSecrets)
+    );
+    assert_eq!(
+      Sessions::new(options(vec!["short".to_owned()])).err(),
+      Some(SessionError::ShortSecret)
+    );
+  }
+
+  #[test]
+  fn hkdf_matches_rfc_5869() {
+    // Test case 1, the first 32 bytes of its output
+    let salt: Vec<u8> = (0x00..=0x0c).collect();
+    let info: Vec<u8> = (0xf0..=0xf9).collect();
+    let key = hkdf_sha256(&salt, &[0x0b; 22], &info);
+
+    let hex: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+    assert_eq!(
+      hex,
+      "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+    );
+  }
+}
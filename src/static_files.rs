@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::AsyncRead;
+use tracing::{debug, error};
+
+use crate::response::{Response, ResponseHeaders, ResponseOptions, SendFile};
+
+#[derive(Error, Debug)]
+pub enum StaticFilesError {
+  #[error("Static directory {0:?} couldn't be opened: {1}")]
+  Root(String, io::Error),
+  #[error("Static directory {0:?} is not a directory")]
+  NotADirectory(String),
+}
+
+/// Options of `server.static()`.
+#[napi(object, js_name = "AouStaticOptions")]
+#[derive(Debug, Default, Clone)]
+pub struct StaticOptions {
+  /// File served for requests to a directory. Defaults to `"index.html"`
+  pub index: Option<String>,
+  /// File served instead of a 404 for paths that don't match a file, e.g. `"index.html"` for single page apps
+  pub fallback: Option<String>,
+  /// Serve files and directories whose name starts with a `.`. Defaults to `false`
+  pub dotfiles: Option<bool>,
+  /// Seconds sent in `Cache-Control: public, max-age=<seconds>`, no `Cache-Control` is sent by default
+  pub max_age: Option<u32>,
+}
+
+/// Files of a directory served by a `server.static()` route, without going through JS.
+#[derive(Debug)]
+pub struct StaticFiles {
+  root: PathBuf,
+  options: StaticOptions,
+}
+
+/// An opened file and what it was opened as
+#[derive(Debug)]
+pub struct StaticFile {
+  pub file: File,
+  pub path: PathBuf,
+  pub metadata: Metadata,
+}
+
+impl StaticFiles {
+  pub fn new(dir: &str, options: StaticOptions) -> Result<Self, StaticFilesError> {
+    let root =
+      std::fs::canonicalize(dir).map_err(|err| StaticFilesError::Root(dir.to_owned(), err))?;
+    if !root.is_dir() {
+      return Err(StaticFilesError::NotADirectory(dir.to_owned()));
+    }
+
+    Ok(StaticFiles { root, options })
+  }
+
+  pub fn index(&self) -> &str {
+    self.options.index.as_deref().unwrap_or("index.html")
+  }
+
+  pub fn dotfiles(&self) -> bool {
+    self.options.dotfiles.unwrap_or(false)
+  }
+
+  /// Answers with the file at `path`, relative to the directory, or a 404 when there's none
+  pub async fn serve<TStream>(
+    &self,
+    stream: &mut TStream,
+    path: &str,
+    options: &ResponseOptions<'_>,
+  ) -> anyhow::Result<()>
+  where
+    TStream: AsyncRead + SendFile,
+  {
+    let mut file = match self.open(path).await {
+      Ok(file) => file,
+      Err(err) => {
+        let status = match err.kind() {
+          io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => 404,
+          io::ErrorKind::PermissionDenied => 403,
+          _ => {
+            error!("Failed to open static file {path:?} {err}");
+            500
+          }
+        };
+        debug!("Static file {path:?} not served {err}");
+
+        return Response {
+          status: Some(status),
+          ..Default::default()
+        }
+        .write_to_stream(stream, &HashMap::new(), options)
+        .await;
+      }
+    };
+
+    let len = file.metadata.len();
+    Response {
+      status: Some(200),
+      headers: Some(self.headers(&file)),
+      ..Default::default()
+    }
+    .write_file_to_stream(stream, &mut file.file, len, options)
+    .await
+  }
+
+  /// Opens the file at `path`, or the fallback file when there's none
+  pub async fn open(&self, path: &str) -> io::Result<StaticFile> {
+    match self.open_path(path).await {
+      Err(err) if err.kind() == io::ErrorKind::NotFound => match &self.options.fallback {
+        Some(fallback) => self.open_path(fallback).await,
+        None => Err(err),
+      },
+      result => result,
+    }
+  }
+
+  async fn open_path(&self, path: &str) -> io::Result<StaticFile> {
+    let relative = self.relative_path(path).ok_or(io::ErrorKind::NotFound)?;
+    let mut path = self.contained(&self.root.join(relative)).await?;
+
+    if tokio::fs::metadata(&path).await?.is_dir() {
+      path = self.contained(&path.join(self.index())).await?;
+    }
+
+    let file = File::open(&path).await?;
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+      return Err(io::ErrorKind::NotFound.into());
+    }
+
+    Ok(StaticFile {
+      file,
+      path,
+      metadata,
+    })
+  }
+
+  /// Resolves symlinks in `path`, which could otherwise point outside of the directory
+  async fn contained(&self, path: &Path) -> io::Result<PathBuf> {
+    let path = tokio::fs::canonicalize(path).await?;
+
+    match path.starts_with(&self.root) {
+      true => Ok(path),
+      false => Err(io::ErrorKind::NotFound.into()),
+    }
+  }
+
+  /// `path` as a relative path, `None` when it would leave the directory or contains a hidden dotfile
+  fn relative_path(&self, path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+
+    for segment in path.split('/') {
+      match segment {
+        "" | "." => continue,
+        ".." => return None,
+        // Separators and drive prefixes of other platforms
+        _ if segment.contains(['\\', '\0']) || (cfg!(windows) && segment.contains(':')) => {
+          return None
+        }
+        _ if segment.starts_with('.') && !self.dotfiles() => return None,
+        _ => relative.push(segment),
+      }
+    }
+
+    Some(relative)
+  }
+
+  fn headers(&self, file: &StaticFile) -> ResponseHeaders {
+    let mut headers = ResponseHeaders::from([
+      ("Content-Type".to_owned(), content_type(&file.path).into()),
+      ("ETag".to_owned(), etag(&file.metadata).into()),
+    ]);
+
+    if let Ok(modified) = file.metadata.modified() {
+      headers.insert(
+        "Last-Modified".to_owned(),
+        httpdate::fmt_http_date(modified).into(),
+      );
+    }
+
+    if let Some(max_age) = self.options.max_age {
+      headers.insert(
+        "Cache-Control".to_owned(),
+        format!("public, max-age={max_age}").into(),
+      );
+    }
+
+    headers
+  }
+}
+
+/// Guessed from the extension, text types are assumed to be UTF-8
+pub fn content_type(path: &Path) -> String {
+  let mime = mime_guess::from_path(path).first_or_octet_stream();
+
+  match (mime.type_(), mime.subtype()) {
+    (mime_guess::mime::TEXT, _)
+    | (_, mime_guess::mime::JAVASCRIPT)
+    | (_, mime_guess::mime::JSON) => format!("{mime}; charset=utf-8"),
+    _ => mime.to_string(),
+  }
+}
+
+/// Strong validator made of the modification time and size, like nginx's
+pub fn etag(metadata: &Metadata) -> String {
+  let modified = metadata
+    .modified()
+    .ok()
+    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+    .unwrap_or_default();
+
+  format!("\"{:x}-{:x}\"", modified.as_secs(), metadata.len())
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use std::path::{Path, PathBuf};
+
+  use super::{content_type, StaticFiles, StaticOptions};
+
+  fn fixtures(options: StaticOptions) -> StaticFiles {
+    StaticFiles::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures"), options).unwrap()
+  }
+
+  #[test]
+  fn relative_paths() {
+    let files = fixtures(StaticOptions::default());
+
+    assert_eq!(
+      files.relative_path("/css//./site.css"),
+      Some(PathBuf::from("css/site.css"))
+    );
+    assert_eq!(files.relative_path(""), Some(PathBuf::new()));
+    assert_eq!(files.relative_path("../secret"), None);
+    assert_eq!(files.relative_path("a/../../secret"), None);
+    assert_eq!(files.relative_path("a\\..\\secret"), None);
+    assert_eq!(files.relative_path(".env"), None);
+    assert_eq!(files.relative_path(".well-known/x"), None);
+
+    let dotfiles = fixtures(StaticOptions {
+      dotfiles: Some(true),
+      ..Default::default()
+    });
+    assert_eq!(dotfiles.relative_path(".env"), Some(PathBuf::from(".env")));
+  }
+
+  #[tokio::test]
+  async fn open_files() {
+    let files = fixtures(StaticOptions::default());
+
+    let file = files.open("hello_world.txt").await.unwrap();
+    assert_eq!(file.metadata.len(), 84);
+
+    assert!(files.open("missing.txt").await.is_err());
+    assert!(files.open("hello_world.txt/x").await.is_err());
+    assert!(files.open("").await.is_err(), "fixtures has no index.html");
+
+    let spa = fixtures(StaticOptions {
+      fallback: Some("hello_world.txt".to_owned()),
+      ..Default::default()
+    });
+    let file = spa.open("app/route").await.unwrap();
+    assert!(file.path.ends_with("hello_world.txt"));
+
+    assert!(StaticFiles::new("fixtures/missing", StaticOptions::default()).is_err());
+  }
+
+  #[test]
+  fn content_types() {
+    assert_eq!(
+      content_type(Path::new("a.html")),
+      "text/html; charset=utf-8"
+    );
+    assert_eq!(
+      content_type(Path::new("app.js")),
+      "text/javascript; charset=utf-8"
+    );
+    assert_eq!(
+      content_type(Path::new("data.json")),
+      "application/json; charset=utf-8"
+    );
+    assert_eq!(content_type(Path::new("image.png")), "image/png");
+    assert_eq!(
+      content_type(Path::new("unknown.xyz123")),
+      "application/octet-stream"
+    );
+  }
+}
@@ -1,5 +1,7 @@
+pub mod path;
 #[cfg(test)]
 pub mod test;
+pub mod urlencoded;
 
 pub fn range_from_subslice<T>(source: &[T], slice: &[T]) -> (usize, usize) {
   let ptr = source.as_ptr() as usize;
@@ -0,0 +1,176 @@
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PathError {
+  #[error("Invalid percent-encoding in path")]
+  InvalidEncoding,
+  #[error("Path is not valid UTF-8")]
+  InvalidUtf8,
+}
+
+/// Normalizes a request path before routing.
+///
+/// `%XX` escapes are decoded, except `%2F` and `%25`, which stay encoded so the normalized
+/// path splits on `/` exactly like the raw one. Empty (`//`) and `.` segments are removed,
+/// and `..` removes the segment before it without ever going above the root.
+/// Targets that aren't paths, like `OPTIONS *`, are returned as they are.
+pub fn normalize(raw: &str) -> Result<String, PathError> {
+  if !raw.starts_with('/') {
+    return Ok(raw.to_owned());
+  }
+
+  let mut segments: Vec<String> = Vec::new();
+  let mut trailing_slash = false;
+
+  for segment in raw[1..].split('/') {
+    let segment = decode_segment(segment)?;
+    trailing_slash = matches!(segment.as_str(), "" | "." | "..");
+
+    match segment.as_str() {
+      "" | "." => (),
+      ".." => {
+        segments.pop();
+      }
+      _ => segments.push(segment),
+    }
+  }
+
+  let mut path = String::with_capacity(raw.len());
+  for segment in &segments {
+    path.push('/');
+    path.push_str(segment);
+  }
+
+  if path.is_empty() || trailing_slash {
+    path.push('/');
+  }
+
+  Ok(path)
+}
+
+/// Decodes the `%2F` and `%25` escapes left in a route param by [`normalize`].
+pub fn decode_param(value: &str) -> String {
+  let mut decoded = String::with_capacity(value.len());
+  let mut rest = value;
+
+  while let Some(percent) = rest.find('%') {
+    decoded.push_str(&rest[..percent]);
+    rest = &rest[percent..];
+
+    let escape = rest.get(..3).unwrap_or_default();
+    if escape.eq_ignore_ascii_case("%2F") {
+      decoded.push('/');
+    } else if escape == "%25" {
+      decoded.push('%');
+    } else {
+      decoded.push('%');
+      rest = &rest[1..];
+      continue;
+    }
+    rest = &rest[3..];
+  }
+
+  decoded.push_str(rest);
+  decoded
+}
+
+/// Percent-encodes a normalized path so it can be sent back in a header like `Location`.
+pub fn encode(path: &str) -> String {
+  let mut encoded = String::with_capacity(path.len());
+
+  for byte in path.bytes() {
+    match byte {
+      b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => encoded.push(byte as char),
+      b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b','
+      | b';' | b'=' | b':' | b'@' | b'/' | b'%' => encoded.push(byte as char),
+      _ => encoded.push_str(&format!("%{byte:02X}")),
+    }
+  }
+
+  encoded
+}
+
+/// `/a` for `/a/` and the other way around, the root is left as it is.
+pub fn toggle_trailing_slash(path: &str) -> String {
+  match path.strip_suffix('/') {
+    Some("") => path.to_owned(),
+    Some(trimmed) => trimmed.to_owned(),
+    None => format!("{path}/"),
+  }
+}
+
+fn decode_segment(segment: &str) -> Result<String, PathError> {
+  if !segment.contains('%') {
+    return Ok(segment.to_owned());
+  }
+
+  let bytes = segment.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+
+  while i < bytes.len() {
+    if bytes[i] != b'%' {
+      decoded.push(bytes[i]);
+      i += 1;
+      continue;
+    }
+
+    let byte = bytes
+      .get(i + 1..i + 3)
+      .and_then(|hex| std::str::from_utf8(hex).ok())
+      .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+      .ok_or(PathError::InvalidEncoding)?;
+
+    match byte {
+      b'/' => decoded.extend_from_slice(b"%2F"),
+      b'%' => decoded.extend_from_slice(b"%25"),
+      _ => decoded.push(byte),
+    }
+    i += 3;
+  }
+
+  String::from_utf8(decoded).map_err(|_| PathError::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use super::{decode_param, encode, normalize, toggle_trailing_slash, PathError};
+
+  #[test]
+  fn normalizes_paths() {
+    let cases = [
+      ("/", "/"),
+      ("/files/my%20doc", "/files/my doc"),
+      ("/a/../b", "/b"),
+      ("//a", "/a"),
+      ("/a/./b/", "/a/b/"),
+      ("/a/b/..", "/a/"),
+      ("/../../etc", "/etc"),
+      ("/a/%2e%2E/b", "/b"),
+      ("/caf%C3%A9", "/café"),
+      ("/a%2fb/100%25", "/a%2Fb/100%25"),
+      ("*", "*"),
+    ];
+
+    for (raw, normalized) in cases {
+      assert_eq!(normalize(raw).as_deref(), Ok(normalized), "{raw}");
+    }
+  }
+
+  #[test]
+  fn invalid_paths() {
+    assert_eq!(normalize("/a%zz"), Err(PathError::InvalidEncoding));
+    assert_eq!(normalize("/a%2"), Err(PathError::InvalidEncoding));
+    assert_eq!(normalize("/a%FF"), Err(PathError::InvalidUtf8));
+  }
+
+  #[test]
+  fn params_and_locations() {
+    assert_eq!(decode_param("a%2Fb/100%25%252F"), "a/b/100%%2F");
+    assert_eq!(encode("/my doc/café?"), "/my%20doc/caf%C3%A9%3F");
+    assert_eq!(toggle_trailing_slash("/a"), "/a/");
+    assert_eq!(toggle_trailing_slash("/a/"), "/a");
+    assert_eq!(toggle_trailing_slash("/"), "/");
+  }
+}
@@ -0,0 +1,167 @@
+use serde_json::{Map, Value};
+
+/// Brackets allowed in a single key by [`decode_nested`]
+pub const MAX_NESTED_DEPTH: usize = 5;
+
+/// Decodes `application/x-www-form-urlencoded` pairs, used for both query strings and form bodies.
+///
+/// `+` is decoded as a space and `%XX` escapes as bytes, invalid UTF-8 is replaced.
+pub fn decode_pairs(input: &[u8]) -> impl Iterator<Item = (String, String)> + '_ {
+  form_urlencoded::parse(input).map(|(key, value)| (key.into_owned(), value.into_owned()))
+}
+
+/// Decodes the pairs into an object, keys repeated in the input become arrays of their values.
+pub fn decode_object(input: &[u8]) -> Map<String, Value> {
+  let mut object = Map::new();
+
+  for (key, value) in decode_pairs(input) {
+    insert_repeated(&mut object, key, value);
+  }
+
+  object
+}
+
+/// Inserts `value`, turning the entry into an array if `key` is already set.
+pub fn insert_repeated(object: &mut Map<String, Value>, key: String, value: String) {
+  match object.get_mut(&key) {
+    Some(Value::Array(values)) => values.push(Value::String(value)),
+    Some(first) => *first = Value::Array(vec![first.take(), Value::String(value)]),
+    None => {
+      object.insert(key, Value::String(value));
+    }
+  }
+}
+
+/// Decodes the pairs into nested values: `a[b]=c` sets `b` on the `a` object and `a[]=c`
+/// pushes `c` onto the `a` array. Keys nested deeper than [`MAX_NESTED_DEPTH`] or with
+/// unbalanced brackets are kept as they are, and pairs conflicting with the shape set by an
+/// earlier pair are dropped.
+pub fn decode_nested(input: &[u8]) -> Map<String, Value> {
+  let mut object = Map::new();
+
+  for (key, value) in decode_pairs(input) {
+    match split_nested_key(&key) {
+      Some((base, segments)) => insert_nested(&mut object, base.to_owned(), &segments, value),
+      None => insert_repeated(&mut object, key, value),
+    }
+  }
+
+  object
+}
+
+/// Splits `a[b][]` into `("a", ["b", ""])`, `None` if the key isn't nested or is malformed.
+fn split_nested_key(key: &str) -> Option<(&str, Vec<&str>)> {
+  let open = key.find('[').filter(|&open| open > 0)?;
+  let (base, mut rest) = key.split_at(open);
+  let mut segments = Vec::new();
+
+  while !rest.is_empty() {
+    let close = rest.strip_prefix('[')?.find(']')? + 1;
+    segments.push(&rest[1..close]);
+    rest = &rest[close + 1..];
+  }
+
+  (segments.len() <= MAX_NESTED_DEPTH).then_some((base, segments))
+}
+
+fn insert_nested(object: &mut Map<String, Value>, key: String, segments: &[&str], value: String) {
+  let Some((segment, rest)) = segments.split_first() else {
+    return insert_repeated(object, key, value);
+  };
+
+  if segment.is_empty() {
+    let entry = object
+      .entry(key)
+      .or_insert_with(|| Value::Array(Vec::new()));
+    if let Value::String(first) = entry {
+      *entry = Value::Array(vec![Value::String(std::mem::take(first))]);
+    }
+    let Value::Array(values) = entry else {
+      return;
+    };
+
+    // `a[][b]=c` starts a new object for every pair.
+    match rest.split_first() {
+      None => values.push(Value::String(value)),
+      Some((next, rest)) => {
+        let mut child = Map::new();
+        insert_nested(&mut child, next.to_string(), rest, value);
+        values.push(Value::Object(child));
+      }
+    }
+    return;
+  }
+
+  let entry = object
+    .entry(key)
+    .or_insert_with(|| Value::Object(Map::new()));
+  if let Value::Object(child) = entry {
+    insert_nested(child, segment.to_string(), rest, value);
+  }
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use serde_json::json;
+
+  use super::{decode_nested, decode_object, decode_pairs};
+
+  #[test]
+  fn decodes_pairs() {
+    let pairs = decode_pairs(b"a=1&b=two+words&c=%F0%9F%A6%80&empty=&flag").collect::<Vec<_>>();
+
+    assert_eq!(
+      pairs,
+      [
+        ("a", "1"),
+        ("b", "two words"),
+        ("c", "🦀"),
+        ("empty", ""),
+        ("flag", "")
+      ]
+      .map(|(k, v)| (k.to_owned(), v.to_owned()))
+    );
+  }
+
+  #[test]
+  fn repeated_keys_become_arrays() {
+    let object = decode_object(b"tag=a&name=aou&tag=b&tag=c%20d");
+
+    assert_eq!(
+      serde_json::Value::Object(object),
+      json!({ "name": "aou", "tag": ["a", "b", "c d"] })
+    );
+  }
+
+  #[test]
+  fn nested_keys() {
+    let object = decode_nested(
+      b"user[name]=aou&user[tags][]=a&user[tags][]=b&items[][id]=1&items[][id]=2&tag=x&tag=y",
+    );
+
+    assert_eq!(
+      serde_json::Value::Object(object),
+      json!({
+        "user": { "name": "aou", "tags": ["a", "b"] },
+        "items": [{ "id": "1" }, { "id": "2" }],
+        "tag": ["x", "y"],
+      })
+    );
+  }
+
+  #[test]
+  fn malformed_nested_keys_stay_flat() {
+    let object = decode_nested(b"a[b=1&[c]=2&d[e]f=3&g[1][2][3][4][5][6]=4&h=5&h[i]=6");
+
+    assert_eq!(
+      serde_json::Value::Object(object),
+      json!({
+        "a[b": "1",
+        "[c]": "2",
+        "d[e]f": "3",
+        "g[1][2][3][4][5][6]": "4",
+        "h": "5",
+      })
+    );
+  }
+}
@@ -0,0 +1,82 @@
+use std::io;
+
+use crate::response::ResponseHeaders;
+
+/// Why a WebSocket connection failed, the connection is closed with `close_code()`
+#[derive(thiserror::Error, Debug)]
+pub enum WebSocketError {
+  #[error("Connection lost: {0}")]
+  Io(#[from] io::Error),
+  #[error("Client frames must be masked")]
+  Unmasked,
+  #[error("Reserved bits set without an extension")]
+  ReservedBits,
+  #[error("Unknown opcode {0:#x}")]
+  UnknownOpcode(u8),
+  #[error("Invalid control frame")]
+  InvalidControlFrame,
+  #[error("Continuation frame without a message")]
+  UnexpectedContinuation,
+  #[error("Expected a continuation frame")]
+  ExpectedContinuation,
+  #[error("Invalid close frame")]
+  InvalidCloseFrame,
+  #[error("Invalid UTF-8 in a text message")]
+  InvalidUtf8,
+  #[error("Message too large")]
+  MessageTooLarge,
+}
+
+impl WebSocketError {
+  /// Status code of the close frame sent before closing the connection
+  pub fn close_code(&self) -> u16 {
+    match self {
+      WebSocketError::Io(_) => 1006,
+      WebSocketError::Unmasked
+      | WebSocketError::ReservedBits
+      | WebSocketError::UnknownOpcode(_)
+      | WebSocketError::InvalidControlFrame
+      | WebSocketError::UnexpectedContinuation
+      | WebSocketError::ExpectedContinuation
+      | WebSocketError::InvalidCloseFrame => 1002,
+      WebSocketError::InvalidUtf8 => 1007,
+      WebSocketError::MessageTooLarge => 1009,
+    }
+  }
+}
+
+/// Why an upgrade request was refused, answered with `status()` instead of a `101`
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum HandshakeError {
+  #[error("Expected a WebSocket upgrade request")]
+  NotAnUpgrade,
+  #[error("Missing Connection: Upgrade header")]
+  MissingConnectionUpgrade,
+  #[error("Unsupported WebSocket version")]
+  UnsupportedVersion,
+  #[error("Invalid Sec-WebSocket-Key header")]
+  InvalidKey,
+}
+
+impl HandshakeError {
+  pub fn status(&self) -> u32 {
+    match self {
+      HandshakeError::NotAnUpgrade | HandshakeError::UnsupportedVersion => 426,
+      HandshakeError::MissingConnectionUpgrade | HandshakeError::InvalidKey => 400,
+    }
+  }
+
+  /// A 426 tells the client what it has to upgrade to
+  pub fn headers(&self) -> ResponseHeaders {
+    match self {
+      HandshakeError::NotAnUpgrade => ResponseHeaders::from([
+        ("Upgrade".to_owned(), "websocket".into()),
+        ("Connection".to_owned(), "Upgrade".into()),
+      ]),
+      HandshakeError::UnsupportedVersion => {
+        ResponseHeaders::from([("Sec-WebSocket-Version".to_owned(), "13".into())])
+      }
+      _ => ResponseHeaders::new(),
+    }
+  }
+}
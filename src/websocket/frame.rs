@@ -0,0 +1,354 @@
+use std::io;
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::WebSocketError;
+
+/// Control frames can't carry more than 125 bytes
+const MAX_CONTROL_PAYLOAD: usize = 125;
+/// Bytes asked from the connection at once when reading frames
+const FRAME_READ_SIZE: usize = 8 * 1024;
+/// Room left in a close frame once its status code is written
+pub const MAX_CLOSE_REASON: usize = MAX_CONTROL_PAYLOAD - 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+  Continuation,
+  Text,
+  Binary,
+  Close,
+  Ping,
+  Pong,
+}
+
+impl Opcode {
+  fn parse(opcode: u8) -> Result<Self, WebSocketError> {
+    match opcode {
+      0x0 => Ok(Opcode::Continuation),
+      0x1 => Ok(Opcode::Text),
+      0x2 => Ok(Opcode::Binary),
+      0x8 => Ok(Opcode::Close),
+      0x9 => Ok(Opcode::Ping),
+      0xA => Ok(Opcode::Pong),
+      _ => Err(WebSocketError::UnknownOpcode(opcode)),
+    }
+  }
+
+  fn as_u8(&self) -> u8 {
+    match self {
+      Opcode::Continuation => 0x0,
+      Opcode::Text => 0x1,
+      Opcode::Binary => 0x2,
+      Opcode::Close => 0x8,
+      Opcode::Ping => 0x9,
+      Opcode::Pong => 0xA,
+    }
+  }
+
+  pub fn is_control(&self) -> bool {
+    matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+  pub fin: bool,
+  pub opcode: Opcode,
+  pub payload: Vec<u8>,
+}
+
+/// Reads client frames through a buffer kept between calls, so a read cancelled by a timer
+/// or the server shutting down loses nothing and the next one resumes where it stopped.
+pub struct FrameReader<R> {
+  reader: R,
+  buf: BytesMut,
+}
+
+impl<R> FrameReader<R>
+where
+  R: AsyncRead + Unpin,
+{
+  pub fn new(reader: R) -> Self {
+    FrameReader {
+      reader,
+      buf: BytesMut::with_capacity(FRAME_READ_SIZE),
+    }
+  }
+
+  /// Reads a client frame and unmasks its payload, cancel safe.
+  /// Data frames longer than `max_payload` are rejected before their payload is read.
+  pub async fn read_frame(&mut self, max_payload: usize) -> Result<Frame, WebSocketError> {
+    loop {
+      if let Some(frame) = decode_frame(&mut self.buf, max_payload)? {
+        return Ok(frame);
+      }
+
+      self.buf.reserve(FRAME_READ_SIZE);
+      if self.reader.read_buf(&mut self.buf).await? == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+      }
+    }
+  }
+}
+
+/// Takes the first frame out of `buf`, `None` until it's complete
+fn decode_frame(buf: &mut BytesMut, max_payload: usize) -> Result<Option<Frame>, WebSocketError> {
+  let [first, second, ..] = buf[..] else {
+    return Ok(None);
+  };
+
+  let fin = first & 0x80 != 0;
+  if first & 0x70 != 0 {
+    return Err(WebSocketError::ReservedBits);
+  }
+  let opcode = Opcode::parse(first & 0x0F)?;
+  if second & 0x80 == 0 {
+    return Err(WebSocketError::Unmasked);
+  }
+
+  let (len, offset) = match second & 0x7F {
+    126 => match buf.get(2..4) {
+      Some(len) => (u16::from_be_bytes([len[0], len[1]]) as u64, 4),
+      None => return Ok(None),
+    },
+    127 => match buf.get(2..10) {
+      Some(len) => (u64::from_be_bytes(len.try_into().unwrap()), 10),
+      None => return Ok(None),
+    },
+    len => (len as u64, 2),
+  };
+
+  if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+    return Err(WebSocketError::InvalidControlFrame);
+  }
+  if len > max_payload as u64 {
+    return Err(WebSocketError::MessageTooLarge);
+  }
+
+  let frame_len = offset + 4 + len as usize;
+  if buf.len() < frame_len {
+    buf.reserve(frame_len - buf.len());
+    return Ok(None);
+  }
+
+  let frame = buf.split_to(frame_len);
+  let (mask, payload) = frame[offset..].split_at(4);
+  let payload = payload
+    .iter()
+    .enumerate()
+    .map(|(index, byte)| byte ^ mask[index % 4])
+    .collect();
+
+  Ok(Some(Frame {
+    fin,
+    opcode,
+    payload,
+  }))
+}
+
+/// Writes a whole message as a single unmasked frame, servers never mask their frames
+pub async fn write_frame<W>(writer: &mut W, opcode: Opcode, payload: &[u8]) -> std::io::Result<()>
+where
+  W: AsyncWrite + Unpin,
+{
+  let mut head = Vec::with_capacity(10);
+  head.push(0x80 | opcode.as_u8());
+
+  match payload.len() {
+    len @ 0..=125 => head.push(len as u8),
+    len @ 126..=0xFFFF => {
+      head.push(126);
+      head.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+    len => {
+      head.push(127);
+      head.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+  }
+
+  writer.write_all(&head).await?;
+  writer.write_all(payload).await
+}
+
+/// Status codes an endpoint may send in a close frame
+pub fn valid_close_code(code: u16) -> bool {
+  matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// Status code and reason of a close frame, a frame without a code has neither
+pub fn parse_close(payload: &[u8]) -> Result<Option<(u16, String)>, WebSocketError> {
+  match payload {
+    [] => Ok(None),
+    [code_high, code_low, reason @ ..] => {
+      let code = u16::from_be_bytes([*code_high, *code_low]);
+      if !valid_close_code(code) {
+        return Err(WebSocketError::InvalidCloseFrame);
+      }
+      let reason = std::str::from_utf8(reason).map_err(|_| WebSocketError::InvalidUtf8)?;
+
+      Ok(Some((code, reason.to_owned())))
+    }
+    [_] => Err(WebSocketError::InvalidCloseFrame),
+  }
+}
+
+/// Payload of a close frame, the reason is cut to fit in a control frame
+pub fn close_payload(code: u16, reason: &str) -> Bytes {
+  let mut end = reason.len().min(MAX_CLOSE_REASON);
+  while !reason.is_char_boundary(end) {
+    end -= 1;
+  }
+
+  let mut payload = Vec::with_capacity(2 + end);
+  payload.extend_from_slice(&code.to_be_bytes());
+  payload.extend_from_slice(&reason.as_bytes()[..end]);
+
+  Bytes::from(payload)
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use std::time::Duration;
+
+  use tokio::io::AsyncWriteExt;
+
+  use super::{close_payload, parse_close, write_frame, Frame, FrameReader, Opcode};
+  use crate::websocket::WebSocketError;
+
+  /// A client frame masked with `mask`
+  fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![first];
+    match payload.len() {
+      len @ 0..=125 => frame.push(0x80 | len as u8),
+      len => {
+        frame.push(0x80 | 126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+      }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+  }
+
+  async fn read_frame(data: &[u8], max_payload: usize) -> Result<Frame, WebSocketError> {
+    FrameReader::new(data).read_frame(max_payload).await
+  }
+
+  #[tokio::test]
+  async fn read_client_frames() {
+    let data = masked(0x81, b"Hello");
+    assert_eq!(
+      read_frame(&data, 1024).await.unwrap(),
+      Frame {
+        fin: true,
+        opcode: Opcode::Text,
+        payload: b"Hello".to_vec(),
+      }
+    );
+
+    let long = vec![7; 300];
+    let data = masked(0x02, &long);
+    let frame = read_frame(&data, 1024).await.unwrap();
+    assert_eq!((frame.fin, frame.opcode), (false, Opcode::Binary));
+    assert_eq!(frame.payload, long);
+
+    assert!(matches!(
+      read_frame(&data, 299).await,
+      Err(WebSocketError::MessageTooLarge)
+    ));
+  }
+
+  #[tokio::test]
+  async fn reject_invalid_frames() {
+    let read = |data: Vec<u8>| async move { read_frame(&data, 1024).await.unwrap_err() };
+
+    assert!(matches!(
+      read(vec![0x81, 0x00]).await,
+      WebSocketError::Unmasked
+    ));
+    assert!(matches!(
+      read(masked(0xC1, b"x")).await,
+      WebSocketError::ReservedBits
+    ));
+    assert!(matches!(
+      read(masked(0x83, b"x")).await,
+      WebSocketError::UnknownOpcode(3)
+    ));
+    assert!(matches!(
+      read(masked(0x09, b"")).await,
+      WebSocketError::InvalidControlFrame
+    ));
+    assert!(matches!(
+      read(masked(0x89, &[0; 126])).await,
+      WebSocketError::InvalidControlFrame
+    ));
+    assert!(matches!(
+      read(masked(0x81, b"Hello")[..5].to_vec()).await,
+      WebSocketError::Io(_)
+    ));
+  }
+
+  #[tokio::test]
+  async fn resume_cancelled_reads() {
+    let data = [masked(0x82, &[7; 300]), masked(0x89, b"ping")].concat();
+    let (mut client, server) = tokio::io::duplex(1024);
+    let mut frames = FrameReader::new(server);
+
+    client.write_all(&data[..100]).await.unwrap();
+    let read = tokio::time::timeout(Duration::from_millis(10), frames.read_frame(1024)).await;
+    assert!(read.is_err(), "The frame isn't complete yet");
+    assert_eq!(frames.buf.len(), 100);
+
+    client.write_all(&data[100..]).await.unwrap();
+    let frame = frames.read_frame(1024).await.unwrap();
+    assert_eq!(
+      (frame.opcode, frame.payload),
+      (Opcode::Binary, vec![7; 300])
+    );
+    let frame = frames.read_frame(1024).await.unwrap();
+    assert_eq!(
+      (frame.opcode, frame.payload),
+      (Opcode::Ping, b"ping".to_vec())
+    );
+    assert_eq!(frames.buf.len(), 0);
+  }
+
+  #[tokio::test]
+  async fn write_server_frames() {
+    let mut written = Vec::new();
+    write_frame(&mut written, Opcode::Text, b"Hi")
+      .await
+      .unwrap();
+    assert_eq!(written, b"\x81\x02Hi");
+
+    let mut written = Vec::new();
+    write_frame(&mut written, Opcode::Binary, &[1; 200])
+      .await
+      .unwrap();
+    assert_eq!(written[..4], [0x82, 126, 0, 200]);
+    assert_eq!(written.len(), 204);
+
+    let mut written = Vec::new();
+    write_frame(&mut written, Opcode::Binary, &vec![1; 70_000])
+      .await
+      .unwrap();
+    assert_eq!(written[..10], [0x82, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70]);
+  }
+
+  #[test]
+  fn close_frames() {
+    assert_eq!(parse_close(b"").unwrap(), None);
+    assert_eq!(
+      parse_close(b"\x03\xe8bye").unwrap(),
+      Some((1000, "bye".to_owned()))
+    );
+    assert!(parse_close(b"\x03").is_err());
+    assert!(parse_close(b"\x03\xed").is_err(), "1005 can't be sent");
+    assert!(parse_close(b"\x03\xe8\xff").is_err());
+
+    assert_eq!(&close_payload(4000, "done")[..], b"\x0f\xa0done");
+    assert_eq!(close_payload(1000, &"é".repeat(100)).len(), 2 + 122);
+  }
+}
@@ -0,0 +1,152 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+use super::HandshakeError;
+use crate::request::Request;
+use crate::response::ResponseHeaders;
+
+/// Appended to `Sec-WebSocket-Key` before hashing it, from RFC 6455
+const WEBSOCKET_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// An accepted upgrade request
+#[derive(Debug, PartialEq)]
+pub struct Handshake {
+  pub accept: String,
+  /// Subprotocol picked from `Sec-WebSocket-Protocol`
+  pub protocol: Option<String>,
+}
+
+impl Handshake {
+  /// Checks the upgrade headers of `req` and picks the first subprotocol it offers that is in `protocols`
+  pub fn new(req: &Request, protocols: &[String]) -> Result<Self, HandshakeError> {
+    let has_token = |name: &[u8], token: &[u8]| {
+      req.header_values(name).any(|value| {
+        value
+          .split(|b| b == &b',')
+          .any(|value| value.trim_ascii().eq_ignore_ascii_case(token))
+      })
+    };
+
+    if !has_token(b"upgrade", b"websocket") {
+      return Err(HandshakeError::NotAnUpgrade);
+    }
+    if !has_token(b"connection", b"upgrade") {
+      return Err(HandshakeError::MissingConnectionUpgrade);
+    }
+    if !req
+      .header_values(b"sec-websocket-version")
+      .any(|version| version.trim_ascii() == b"13")
+    {
+      return Err(HandshakeError::UnsupportedVersion);
+    }
+
+    let mut keys = req.header_values(b"sec-websocket-key");
+    let key = match (keys.next(), keys.next()) {
+      (Some(key), None) => key.trim_ascii(),
+      _ => return Err(HandshakeError::InvalidKey),
+    };
+    // The key is a random 16 bytes nonce
+    match STANDARD.decode(key) {
+      Ok(nonce) if nonce.len() == 16 => (),
+      _ => return Err(HandshakeError::InvalidKey),
+    }
+
+    let protocol = req
+      .header_values(b"sec-websocket-protocol")
+      .flat_map(|value| value.split(|b| b == &b','))
+      .map(|protocol| String::from_utf8_lossy(protocol.trim_ascii()))
+      .find_map(|offered| protocols.iter().find(|protocol| **protocol == offered))
+      .cloned();
+
+    Ok(Handshake {
+      accept: accept_key(key),
+      protocol,
+    })
+  }
+
+  /// Headers of the `101 Switching Protocols` response
+  pub fn headers(&self) -> ResponseHeaders {
+    let mut headers = ResponseHeaders::from([
+      ("Upgrade".to_owned(), "websocket".into()),
+      ("Connection".to_owned(), "Upgrade".into()),
+      (
+        "Sec-WebSocket-Accept".to_owned(),
+        self.accept.clone().into(),
+      ),
+    ]);
+
+    if let Some(protocol) = &self.protocol {
+      headers.insert("Sec-WebSocket-Protocol".to_owned(), protocol.clone().into());
+    }
+
+    headers
+  }
+}
+
+/// `Sec-WebSocket-Accept` proving the server read the client's `Sec-WebSocket-Key`
+pub fn accept_key(key: &[u8]) -> String {
+  let mut hasher = Sha1::new();
+  hasher.update(key);
+  hasher.update(WEBSOCKET_GUID);
+
+  STANDARD.encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use super::{accept_key, Handshake};
+  use crate::request::Request;
+  use crate::websocket::HandshakeError;
+
+  fn upgrade(headers: &str) -> Request {
+    Request::from_string(format!(
+      "GET /chat HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n"
+    ))
+  }
+
+  const HEADERS: &str = "Upgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+    Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+
+  #[test]
+  fn accept_keys() {
+    assert_eq!(
+      accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+      "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+  }
+
+  #[test]
+  fn accept_upgrades() {
+    let handshake = Handshake::new(&upgrade(HEADERS), &[]).unwrap();
+    assert_eq!(handshake.accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    assert_eq!(handshake.protocol, None);
+
+    let protocols = ["chat".to_owned(), "superchat".to_owned()];
+    let req = upgrade(&format!(
+      "{HEADERS}Sec-WebSocket-Protocol: graphql-ws, superchat, chat\r\n"
+    ));
+    assert_eq!(
+      Handshake::new(&req, &protocols).unwrap().protocol,
+      Some("superchat".to_owned())
+    );
+  }
+
+  #[test]
+  fn reject_upgrades() {
+    let handshake = |headers: &str| Handshake::new(&upgrade(headers), &[]).unwrap_err();
+
+    assert_eq!(handshake(""), HandshakeError::NotAnUpgrade);
+    assert_eq!(
+      handshake(&HEADERS.replace("keep-alive, Upgrade", "keep-alive")),
+      HandshakeError::MissingConnectionUpgrade
+    );
+    assert_eq!(
+      handshake(&HEADERS.replace("Version: 13", "Version: 8")),
+      HandshakeError::UnsupportedVersion
+    );
+    assert_eq!(
+      handshake(&HEADERS.replace("dGhlIHNhbXBsZSBub25jZQ==", "c2hvcnQ=")),
+      HandshakeError::InvalidKey
+    );
+  }
+}
@@ -0,0 +1,12 @@
+mod error;
+mod frame;
+mod handshake;
+mod route;
+mod socket;
+mod topics;
+pub use error::*;
+pub use frame::*;
+pub use handshake::*;
+pub use route::*;
+pub use socket::*;
+pub use topics::*;
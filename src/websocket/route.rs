@@ -0,0 +1,488 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction};
+use napi::{Env, JsFunction, JsObject, JsUnknown};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error};
+
+use super::{parse_close, write_frame, FrameReader, Handshake, HandshakeError, Opcode, Outgoing};
+use super::{Backpressure, BackpressurePolicy, Socket, Topics, WebSocketError};
+use crate::constants::{
+  WEBSOCKET_CLOSE_TIMEOUT_MS, WEBSOCKET_IDLE_TIMEOUT_MS, WEBSOCKET_MAX_BACKPRESSURE,
+  WEBSOCKET_MAX_PAYLOAD_LENGTH,
+};
+use crate::request::Request;
+
+/// Options of `server.ws()`.
+#[napi(object, js_name = "AouWebSocketOptions")]
+#[derive(Debug, Default, Clone)]
+pub struct WebSocketOptions {
+  /// Bytes allowed for a message once its fragments are joined, the socket is closed with a 1009 when exceeded.
+  /// Defaults to `16777216`
+  pub max_payload_length: Option<u32>,
+  /// Milliseconds without any frame from the client before the socket is closed with a 1001,
+  /// a ping is sent halfway through. Defaults to `120000`, `0` disables it
+  pub idle_timeout: Option<u32>,
+  /// Subprotocols the server speaks, the first one the client offers in `Sec-WebSocket-Protocol` is picked
+  pub protocols: Option<Vec<String>>,
+  /// Bytes waiting to be written to a socket before it is a slow consumer, the messages sent
+  /// or published to it are then handled by `backpressure`. Defaults to `1048576`, `0` disables it
+  pub max_backpressure: Option<u32>,
+  /// `"close"` closes slow consumers with a 1008 and skips their queued messages,
+  /// `"drop"` drops the messages sent to them until they catch up. Defaults to `"close"`
+  #[napi(ts_type = "'close' | 'drop'")]
+  pub backpressure: Option<String>,
+}
+
+impl WebSocketOptions {
+  pub fn max_payload_length(&self) -> usize {
+    self
+      .max_payload_length
+      .map_or(WEBSOCKET_MAX_PAYLOAD_LENGTH, |max| max as usize)
+  }
+
+  pub fn backpressure(&self) -> Backpressure {
+    let limit = match self.max_backpressure {
+      Some(0) => None,
+      limit => Some(limit.map_or(WEBSOCKET_MAX_BACKPRESSURE, |limit| limit as usize)),
+    };
+    let policy = self
+      .backpressure
+      .as_deref()
+      .and_then(|policy| policy.parse().ok())
+      .unwrap_or_default();
+
+    Backpressure { limit, policy }
+  }
+
+  pub fn idle_timeout(&self) -> Option<Duration> {
+    match self.idle_timeout {
+      Some(0) => None,
+      timeout => Some(Duration::from_millis(
+        timeout.map_or(WEBSOCKET_IDLE_TIMEOUT_MS, |timeout| timeout as u64),
+      )),
+    }
+  }
+}
+
+/// A message received from the client
+pub enum Message {
+  Text(String),
+  Binary(Vec<u8>),
+}
+
+pub struct OpenEvent {
+  socket: Arc<Socket>,
+  req: Request,
+}
+
+pub struct MessageEvent {
+  socket: Arc<Socket>,
+  message: Message,
+}
+
+pub struct CloseEvent {
+  socket: Arc<Socket>,
+  code: u16,
+  reason: String,
+}
+
+type Handler<T> = ThreadsafeFunction<T, ErrorStrategy::Fatal>;
+
+/// What a handler returned, a promise is awaited before the next event of the socket is handled
+enum Returned {
+  Value,
+  Promise(Promise<Ignored>),
+}
+
+/// Resolved value of a handler's promise
+struct Ignored;
+
+impl FromNapiValue for Ignored {
+  unsafe fn from_napi_value(_: sys::napi_env, _: sys::napi_value) -> Result<Self> {
+    Ok(Ignored)
+  }
+}
+
+impl FromNapiValue for Returned {
+  unsafe fn from_napi_value(env: sys::napi_env, napi_val: sys::napi_value) -> Result<Self> {
+    let mut is_promise = false;
+    napi::check_status!(sys::napi_is_promise(env, napi_val, &mut is_promise))?;
+
+    match is_promise {
+      true => Ok(Returned::Promise(Promise::from_napi_value(env, napi_val)?)),
+      false => Ok(Returned::Value),
+    }
+  }
+}
+
+/// A `server.ws()` route, the handlers get the same `AouWebSocket` for every event of a connection
+pub struct WebSocketRoute {
+  open: Option<Handler<OpenEvent>>,
+  message: Option<Handler<MessageEvent>>,
+  /// Always set, the socket's JS object is released after it ran
+  close: Handler<CloseEvent>,
+  options: WebSocketOptions,
+  topics: Arc<Topics>,
+}
+
+impl WebSocketRoute {
+  pub fn new(
+    env: Env,
+    handlers: JsObject,
+    options: Option<WebSocketOptions>,
+    topics: Arc<Topics>,
+  ) -> Result<Self> {
+    let options = options.unwrap_or_default();
+    if let Some(policy) = &options.backpressure {
+      policy
+        .parse::<BackpressurePolicy>()
+        .map_err(|err| Error::new(Status::InvalidArg, err))?;
+    }
+
+    let open = handler(
+      &handlers,
+      "open",
+      |ctx: ThreadSafeCallContext<OpenEvent>| {
+        let socket = ctx.value.socket.js_object(ctx.env)?;
+        let req = ctx.value.req.into_instance(ctx.env)?.as_object(ctx.env);
+
+        Ok(vec![socket.into_unknown(), req.into_unknown()])
+      },
+    )?;
+
+    let message = handler(
+      &handlers,
+      "message",
+      |ctx: ThreadSafeCallContext<MessageEvent>| {
+        let socket = ctx.value.socket.js_object(ctx.env)?;
+        let data = match ctx.value.message {
+          Message::Text(text) => ctx.env.create_string_from_std(text)?.into_unknown(),
+          Message::Binary(data) => ctx
+            .env
+            .create_buffer_with_data(data)?
+            .into_raw()
+            .into_unknown(),
+        };
+
+        Ok(vec![socket.into_unknown(), data])
+      },
+    )?;
+
+    let close = |ctx: ThreadSafeCallContext<CloseEvent>| {
+      let socket = ctx.value.socket.js_object(ctx.env)?;
+      ctx.value.socket.release_js_object(ctx.env)?;
+
+      Ok(vec![
+        socket.into_unknown(),
+        ctx.env.create_uint32(ctx.value.code as u32)?.into_unknown(),
+        ctx
+          .env
+          .create_string_from_std(ctx.value.reason)?
+          .into_unknown(),
+      ])
+    };
+    let close = match handler(&handlers, "close", close)? {
+      Some(close) => close,
+      None => env
+        .create_function_from_closure("close", |_: napi::CallContext| Ok(()))?
+        .create_threadsafe_function(0, close)?,
+    };
+
+    Ok(WebSocketRoute {
+      open,
+      message,
+      close,
+      options,
+      topics,
+    })
+  }
+
+  pub fn handshake(&self, req: &Request) -> std::result::Result<Handshake, HandshakeError> {
+    Handshake::new(req, self.options.protocols.as_deref().unwrap_or_default())
+  }
+
+  /// Runs the connection once the `101` was sent, until the closing handshake is done or the connection is lost.
+  /// The server closes the socket with a 1001 when it shuts down.
+  pub async fn serve<TStream>(
+    &self,
+    stream: TStream,
+    req: Request,
+    handshake: Handshake,
+    mut shutdown: watch::Receiver<bool>,
+  ) -> anyhow::Result<()>
+  where
+    TStream: AsyncRead + AsyncWrite + Unpin + Send,
+  {
+    let (reader, writer) = tokio::io::split(stream);
+    let (outgoing, queue) = mpsc::unbounded_channel();
+    let socket = Arc::new(Socket::new(
+      outgoing,
+      handshake.protocol,
+      self.topics.clone(),
+      self.options.backpressure(),
+    ));
+
+    if let Some(open) = &self.open {
+      call(
+        open,
+        OpenEvent {
+          socket: socket.clone(),
+          req,
+        },
+      )
+      .await;
+    }
+
+    let mut frames = FrameReader::new(reader);
+    let read = self.read_messages(&mut frames, &socket, &mut shutdown);
+    let write = write_frames(writer, queue, &socket);
+    tokio::pin!(read, write);
+
+    let (code, reason) = tokio::select! {
+      closed = &mut read => {
+        // Gives the writer time to send the close frame
+        let close_timeout = Duration::from_millis(WEBSOCKET_CLOSE_TIMEOUT_MS);
+        let _ = tokio::time::timeout(close_timeout, &mut write).await;
+        closed
+      }
+      _ = &mut write => read.await,
+    };
+    debug!("WebSocket closed {code} {reason}");
+    socket.unsubscribe_all();
+
+    call(
+      &self.close,
+      CloseEvent {
+        socket: socket.clone(),
+        code,
+        reason,
+      },
+    )
+    .await;
+
+    Ok(())
+  }
+
+  /// Reads frames until the socket is closed, returns the status code and reason it was closed with
+  async fn read_messages<R>(
+    &self,
+    frames: &mut FrameReader<R>,
+    socket: &Arc<Socket>,
+    shutdown: &mut watch::Receiver<bool>,
+  ) -> (u16, String)
+  where
+    R: AsyncRead + Unpin,
+  {
+    match self.read_frames(frames, socket, shutdown).await {
+      Ok(closed) => closed,
+      Err(WebSocketError::Io(err)) => {
+        debug!("WebSocket connection lost {err}");
+        socket.abort();
+        (1006, String::new())
+      }
+      Err(err) => {
+        debug!("WebSocket failed {err}");
+        let (code, reason) = (err.close_code(), err.to_string());
+        socket.close(Some((code, &reason)));
+        (code, reason)
+      }
+    }
+  }
+
+  async fn read_frames<R>(
+    &self,
+    frames: &mut FrameReader<R>,
+    socket: &Arc<Socket>,
+    shutdown: &mut watch::Receiver<bool>,
+  ) -> std::result::Result<(u16, String), WebSocketError>
+  where
+    R: AsyncRead + Unpin,
+  {
+    let max_payload_length = self.options.max_payload_length();
+    let idle_timeout = self.options.idle_timeout();
+    let close_timeout = Duration::from_millis(WEBSOCKET_CLOSE_TIMEOUT_MS);
+
+    // Fragments of the message being received
+    let mut fragments: Option<(Opcode, Vec<u8>)> = None;
+    let mut pinged = false;
+
+    loop {
+      let wait = match socket.is_closing() {
+        true => Some(close_timeout),
+        false => idle_timeout.map(|timeout| timeout / 2),
+      };
+      let received = fragments.as_ref().map_or(0, |(_, data)| data.len());
+      // Reads are cancel safe, a frame cut by the timer or the shutdown is resumed on the next one
+      let read = frames.read_frame(max_payload_length - received);
+
+      let frame = tokio::select! {
+        frame = async {
+          match wait {
+            Some(wait) => tokio::time::timeout(wait, read).await.ok(),
+            None => Some(read.await),
+          }
+        } => frame,
+        Ok(_) = shutdown.wait_for(|closing| *closing), if !socket.is_closing() => {
+          socket.close(Some((1001, "Server shutting down")));
+          continue;
+        }
+      };
+
+      let frame = match frame {
+        Some(frame) => frame?,
+        // The client didn't answer the close frame
+        None if socket.is_closing() => {
+          socket.abort();
+          return Ok((1006, String::new()));
+        }
+        // Only complete frames count as activity, a frame trickled in byte by byte still times out
+        None if pinged => {
+          socket.close(Some((1001, "Idle timeout")));
+          continue;
+        }
+        None => {
+          pinged = true;
+          socket.control(Opcode::Ping, Bytes::new());
+          continue;
+        }
+      };
+      pinged = false;
+
+      match frame.opcode {
+        Opcode::Ping => {
+          socket.control(Opcode::Pong, Bytes::from(frame.payload));
+          continue;
+        }
+        Opcode::Pong => continue,
+        Opcode::Close => {
+          let close = parse_close(&frame.payload)?;
+          // Answered with the same status code, unless the server started closing
+          socket.close(close.as_ref().map(|(code, _)| (*code, "")));
+
+          return Ok(close.unwrap_or((1005, String::new())));
+        }
+        _ => (),
+      }
+
+      let (opcode, data) = match (frame.opcode, fragments.take()) {
+        (Opcode::Continuation, None) => return Err(WebSocketError::UnexpectedContinuation),
+        (Opcode::Continuation, Some((opcode, mut data))) => {
+          data.extend_from_slice(&frame.payload);
+          (opcode, data)
+        }
+        (_, Some(_)) => return Err(WebSocketError::ExpectedContinuation),
+        (opcode, None) => (opcode, frame.payload),
+      };
+
+      if !frame.fin {
+        fragments = Some((opcode, data));
+        continue;
+      }
+      // Messages sent after a close frame are dropped
+      if socket.is_closing() {
+        continue;
+      }
+
+      let message = match opcode {
+        Opcode::Text => {
+          Message::Text(String::from_utf8(data).map_err(|_| WebSocketError::InvalidUtf8)?)
+        }
+        _ => Message::Binary(data),
+      };
+
+      if let Some(handler) = &self.message {
+        call(
+          handler,
+          MessageEvent {
+            socket: socket.clone(),
+            message,
+          },
+        )
+        .await;
+      }
+    }
+  }
+}
+
+/// The handler named `name` in the `handlers` object passed to `server.ws()`
+fn handler<T, F>(handlers: &JsObject, name: &str, args: F) -> Result<Option<Handler<T>>>
+where
+  T: 'static,
+  F: 'static + Send + FnMut(ThreadSafeCallContext<T>) -> Result<Vec<JsUnknown>>,
+{
+  let handler = handlers.get_named_property::<JsUnknown>(name)?;
+
+  match handler.get_type()? {
+    ValueType::Undefined | ValueType::Null => Ok(None),
+    ValueType::Function => {
+      // Safety: the type was just checked
+      let handler = unsafe { handler.cast::<JsFunction>() };
+      Ok(Some(handler.create_threadsafe_function(0, args)?))
+    }
+    _ => Err(Error::new(
+      Status::InvalidArg,
+      format!("AouWebSocketHandlers.{name} must be a function"),
+    )),
+  }
+}
+
+/// Calls a handler and waits for its promise, a rejected promise is only logged
+async fn call<T: 'static>(handler: &Handler<T>, event: T) {
+  let result = match handler.call_async::<Returned>(event).await {
+    Ok(Returned::Promise(promise)) => promise.await.map(|_| ()),
+    Ok(Returned::Value) => Ok(()),
+    Err(err) => Err(err),
+  };
+
+  if let Err(err) = result {
+    error!("WebSocket handler failed {err}");
+  }
+}
+
+/// Writes the queued frames, batching the ones queued while the previous write was pending
+async fn write_frames<W>(writer: W, mut queue: mpsc::UnboundedReceiver<Outgoing>, socket: &Socket)
+where
+  W: AsyncWrite + Unpin,
+{
+  let mut writer = BufWriter::new(writer);
+
+  while let Some(outgoing) = queue.recv().await {
+    let result = match &outgoing {
+      // A slow consumer only gets its close frame
+      Outgoing::Frame(opcode, payload) if socket.is_overflowed() => {
+        if !opcode.is_control() {
+          socket.written(payload.len());
+        }
+        continue;
+      }
+      Outgoing::Frame(opcode, payload) => {
+        let written = write_frame(&mut writer, *opcode, payload).await;
+        if !opcode.is_control() {
+          socket.written(payload.len());
+        }
+        match queue.is_empty() {
+          true => written.and(writer.flush().await),
+          false => written,
+        }
+      }
+      Outgoing::Close(payload) => {
+        let written = write_frame(&mut writer, Opcode::Close, payload).await;
+        let _ = written.and(writer.flush().await);
+        let _ = writer.shutdown().await;
+        break;
+      }
+      Outgoing::Abort => break,
+    };
+
+    if let Err(err) = result {
+      debug!("WebSocket write failed {err}");
+      socket.abort();
+      break;
+    }
+  }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use napi::bindgen_prelude::*;
+use napi::{Env, JsObject, Ref};
+use tokio::sync::mpsc;
+
+use super::{close_payload, valid_close_code, Opcode, Topics, MAX_CLOSE_REASON};
+
+static NEXT_SOCKET_ID: AtomicU64 = AtomicU64::new(0);
+
+/// What happens to messages for a socket that has more than `limit` bytes waiting to be written
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BackpressurePolicy {
+  /// The socket is closed with a 1008 and its queued messages are skipped
+  #[default]
+  Close,
+  /// Messages are dropped until the socket catches up
+  Drop,
+}
+
+impl FromStr for BackpressurePolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    match s {
+      "close" => Ok(BackpressurePolicy::Close),
+      "drop" => Ok(BackpressurePolicy::Drop),
+      _ => Err(format!(
+        "Invalid backpressure \"{s}\", expected \"close\" or \"drop\""
+      )),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Backpressure {
+  /// `None` never treats the socket as a slow consumer
+  pub limit: Option<usize>,
+  pub policy: BackpressurePolicy,
+}
+
+/// Frames waiting to be written to the connection
+#[derive(Debug)]
+pub enum Outgoing {
+  Frame(Opcode, Bytes),
+  /// Last frame written, the connection is shut down after it
+  Close(Bytes),
+  /// The connection is gone, nothing else is written
+  Abort,
+}
+
+/// A connection shared by the tokio side and its `AouWebSocket` JS object
+pub struct Socket {
+  id: u64,
+  outgoing: mpsc::UnboundedSender<Outgoing>,
+  /// Bytes of data frames queued but not written yet
+  buffered: AtomicUsize,
+  /// Set once a close frame was queued, nothing can be sent after it
+  closing: AtomicBool,
+  /// Set when the backpressure limit closed the socket, the frames queued before the close frame are skipped
+  overflowed: AtomicBool,
+  backpressure: Backpressure,
+  protocol: Option<String>,
+  topics: Arc<Topics>,
+  /// Topics the socket is subscribed to, locked before `topics`
+  subscriptions: Mutex<HashSet<String>>,
+  /// The JS object handed to every handler, kept alive until the `close` handler ran
+  js: Mutex<Option<Ref<()>>>,
+}
+
+impl Socket {
+  pub fn new(
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+    protocol: Option<String>,
+    topics: Arc<Topics>,
+    backpressure: Backpressure,
+  ) -> Self {
+    Socket {
+      id: NEXT_SOCKET_ID.fetch_add(1, Ordering::Relaxed),
+      outgoing,
+      buffered: AtomicUsize::new(0),
+      closing: AtomicBool::new(false),
+      overflowed: AtomicBool::new(false),
+      backpressure,
+      protocol,
+      topics,
+      subscriptions: Mutex::new(HashSet::new()),
+      js: Mutex::new(None),
+    }
+  }
+
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Queues a data frame, `false` once the socket is closing or when the backpressure policy dropped it
+  pub fn send(&self, opcode: Opcode, payload: Bytes) -> bool {
+    if self.is_closing() {
+      return false;
+    }
+    if let Some(limit) = self.backpressure.limit {
+      if self.buffered.load(Ordering::Relaxed) > limit {
+        if self.backpressure.policy == BackpressurePolicy::Close {
+          self.overflowed.store(true, Ordering::Release);
+          self.close(Some((1008, "Backpressure limit exceeded")));
+        }
+        return false;
+      }
+    }
+
+    self.buffered.fetch_add(payload.len(), Ordering::Relaxed);
+    self.outgoing.send(Outgoing::Frame(opcode, payload)).is_ok()
+  }
+
+  /// Queues a ping or a pong, dropped once the socket is closing
+  pub fn control(&self, opcode: Opcode, payload: Bytes) {
+    if !self.is_closing() {
+      let _ = self.outgoing.send(Outgoing::Frame(opcode, payload));
+    }
+  }
+
+  /// Queues the close frame, `false` if the socket was already closing.
+  /// `None` echoes a close frame that had no status code.
+  pub fn close(&self, close: Option<(u16, &str)>) -> bool {
+    if self.closing.swap(true, Ordering::AcqRel) {
+      return false;
+    }
+
+    let payload = close.map_or_else(Bytes::new, |(code, reason)| close_payload(code, reason));
+    let _ = self.outgoing.send(Outgoing::Close(payload));
+    true
+  }
+
+  /// Stops writing without a close frame, the connection was lost
+  pub fn abort(&self) {
+    self.closing.store(true, Ordering::Release);
+    let _ = self.outgoing.send(Outgoing::Abort);
+  }
+
+  pub fn written(&self, len: usize) {
+    self.buffered.fetch_sub(len, Ordering::Relaxed);
+  }
+
+  pub fn is_closing(&self) -> bool {
+    self.closing.load(Ordering::Acquire)
+  }
+
+  pub fn is_overflowed(&self) -> bool {
+    self.overflowed.load(Ordering::Acquire)
+  }
+
+  /// Adds the socket to the subscribers of `topic`, `false` once the socket is closing
+  pub fn subscribe(self: &Arc<Self>, topic: String) -> bool {
+    let mut subscriptions = self.subscriptions.lock().expect("WebSocket lock poisoned");
+    // Checked under the lock so nothing subscribes after `unsubscribe_all()`
+    if self.is_closing() {
+      return false;
+    }
+
+    self.topics.add(&topic, self);
+    subscriptions.insert(topic);
+    true
+  }
+
+  /// `false` if the socket wasn't subscribed to `topic`
+  pub fn unsubscribe(&self, topic: &str) -> bool {
+    let mut subscriptions = self.subscriptions.lock().expect("WebSocket lock poisoned");
+    if !subscriptions.remove(topic) {
+      return false;
+    }
+
+    self.topics.remove(topic, self.id);
+    true
+  }
+
+  /// Leaves every topic once the socket is closing, the topics stop holding on to it
+  pub fn unsubscribe_all(&self) {
+    let mut subscriptions = self.subscriptions.lock().expect("WebSocket lock poisoned");
+    for topic in subscriptions.drain() {
+      self.topics.remove(&topic, self.id);
+    }
+  }
+
+  /// The `AouWebSocket` of this connection, created by the first handler that needs it.
+  /// Must be called on the JS thread.
+  pub fn js_object(self: &Arc<Self>, env: Env) -> Result<JsObject> {
+    let mut js = self.js.lock().expect("WebSocket lock poisoned");
+    if let Some(reference) = js.as_ref() {
+      return env.get_reference_value(reference);
+    }
+
+    let object = AouWebSocket {
+      socket: self.clone(),
+    }
+    .into_instance(env)?
+    .as_object(env);
+    *js = Some(env.create_reference(&object)?);
+
+    Ok(object)
+  }
+
+  /// Lets the JS object be garbage collected once JS holds no other reference to it.
+  /// Must be called on the JS thread.
+  pub fn release_js_object(&self, env: Env) -> Result<()> {
+    let reference = self.js.lock().expect("WebSocket lock poisoned").take();
+    if let Some(mut reference) = reference {
+      reference.unref(env)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// A WebSocket connection, every handler of the connection gets the same object.
+#[napi]
+pub struct AouWebSocket {
+  socket: Arc<Socket>,
+}
+
+#[napi]
+impl AouWebSocket {
+  /// Sends a string as a text message and a Buffer as a binary one.
+  /// Returns `false` once the socket is closing or when the backpressure policy dropped the message
+  #[napi(ts_args_type = "data: string | Buffer")]
+  pub fn send(&self, data: Either<String, Buffer>) -> bool {
+    let (opcode, payload) = message(data);
+    self.socket.send(opcode, payload)
+  }
+
+  /// Subscribes the socket to `topic` until it is closed. Returns `false` once the socket is closing
+  #[napi]
+  pub fn subscribe(&self, topic: String) -> bool {
+    self.socket.subscribe(topic)
+  }
+
+  /// Returns `false` if the socket wasn't subscribed to `topic`
+  #[napi]
+  pub fn unsubscribe(&self, topic: String) -> bool {
+    self.socket.unsubscribe(&topic)
+  }
+
+  #[napi]
+  pub fn is_subscribed(&self, topic: String) -> bool {
+    let subscriptions = self
+      .socket
+      .subscriptions
+      .lock()
+      .expect("WebSocket lock poisoned");
+    subscriptions.contains(&topic)
+  }
+
+  /// Topics the socket is subscribed to
+  #[napi(getter)]
+  pub fn topics(&self) -> Vec<String> {
+    let subscriptions = self
+      .socket
+      .subscriptions
+      .lock()
+      .expect("WebSocket lock poisoned");
+    subscriptions.iter().cloned().collect()
+  }
+
+  /// Sends the message to every other subscriber of `topic`, like `server.publish()`.
+  /// Returns the number of sockets it was queued on
+  #[napi(ts_args_type = "topic: string, data: string | Buffer")]
+  pub fn publish(&self, topic: String, data: Either<String, Buffer>) -> u32 {
+    let (opcode, payload) = message(data);
+    self
+      .socket
+      .topics
+      .publish(&topic, opcode, payload, Some(self.socket.id))
+  }
+
+  /// Starts the closing handshake, `code` defaults to `1000`.
+  /// The `close` handler runs once the client answered or the connection is gone
+  #[napi]
+  pub fn close(&self, code: Option<u32>, reason: Option<String>) -> Result<()> {
+    let code = code.unwrap_or(1000);
+    let reason = reason.unwrap_or_default();
+
+    match u16::try_from(code) {
+      Ok(code) if valid_close_code(code) => (),
+      _ => {
+        return Err(Error::new(
+          Status::InvalidArg,
+          format!("Invalid close code {code}"),
+        ))
+      }
+    }
+    if reason.len() > MAX_CLOSE_REASON {
+      return Err(Error::new(
+        Status::InvalidArg,
+        format!("Close reason longer than {MAX_CLOSE_REASON} bytes"),
+      ));
+    }
+
+    self.socket.close(Some((code as u16, &reason)));
+    Ok(())
+  }
+
+  /// Bytes passed to `send()` that weren't written to the connection yet
+  #[napi(getter)]
+  pub fn buffered_amount(&self) -> u32 {
+    u32::try_from(self.socket.buffered.load(Ordering::Relaxed)).unwrap_or(u32::MAX)
+  }
+
+  /// `true` once `close()` was called, the client started closing or the connection was lost
+  #[napi(getter)]
+  pub fn closing(&self) -> bool {
+    self.socket.is_closing()
+  }
+
+  /// Subprotocol picked from the client's `Sec-WebSocket-Protocol`
+  #[napi(getter)]
+  pub fn protocol(&self) -> Option<String> {
+    self.socket.protocol.clone()
+  }
+}
+
+/// A string is sent as a text message and a Buffer as a binary one
+pub fn message(data: Either<String, Buffer>) -> (Opcode, Bytes) {
+  match data {
+    Either::A(text) => (Opcode::Text, Bytes::from(text)),
+    Either::B(buffer) => (Opcode::Binary, Bytes::from(Vec::from(buffer))),
+  }
+}
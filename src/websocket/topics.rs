@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use bytes::Bytes;
+
+use super::{Opcode, Socket};
+
+/// Subscribers of every topic, shared by a server and all of its sockets
+#[derive(Default)]
+pub struct Topics {
+  topics: RwLock<HashMap<String, HashMap<u64, Arc<Socket>>>>,
+}
+
+impl Topics {
+  /// Queues the message on every subscriber of `topic` but `except`,
+  /// returns the number of sockets it was queued on
+  pub fn publish(&self, topic: &str, opcode: Opcode, payload: Bytes, except: Option<u64>) -> u32 {
+    let topics = self.topics.read().expect("Topics lock poisoned");
+    let Some(subscribers) = topics.get(topic) else {
+      return 0;
+    };
+
+    subscribers
+      .values()
+      .filter(|socket| Some(socket.id()) != except)
+      .filter(|socket| socket.send(opcode, payload.clone()))
+      .count() as u32
+  }
+
+  pub fn subscribers(&self, topic: &str) -> u32 {
+    let topics = self.topics.read().expect("Topics lock poisoned");
+    topics
+      .get(topic)
+      .map_or(0, |subscribers| subscribers.len() as u32)
+  }
+
+  pub(super) fn add(&self, topic: &str, socket: &Arc<Socket>) {
+    let mut topics = self.topics.write().expect("Topics lock poisoned");
+    topics
+      .entry(topic.to_owned())
+      .or_default()
+      .insert(socket.id(), socket.clone());
+  }
+
+  pub(super) fn remove(&self, topic: &str, id: u64) {
+    let mut topics = self.topics.write().expect("Topics lock poisoned");
+    if let Some(subscribers) = topics.get_mut(topic) {
+      subscribers.remove(&id);
+      if subscribers.is_empty() {
+        topics.remove(topic);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod unit_tests {
+  use std::sync::Arc;
+
+  use bytes::Bytes;
+  use tokio::sync::mpsc;
+
+  use super::Topics;
+  use crate::websocket::{Backpressure, BackpressurePolicy, Opcode, Outgoing, Socket};
+
+  fn socket(
+    topics: &Arc<Topics>,
+    limit: Option<usize>,
+    policy: BackpressurePolicy,
+  ) -> (Arc<Socket>, mpsc::UnboundedReceiver<Outgoing>) {
+    let (outgoing, queue) = mpsc::unbounded_channel();
+    let backpressure = Backpressure { limit, policy };
+    let socket = Socket::new(outgoing, None, topics.clone(), backpressure);
+
+    (Arc::new(socket), queue)
+  }
+
+  #[test]
+  fn publish_to_subscribers() {
+    let topics = Arc::new(Topics::default());
+    let (first, mut first_queue) = socket(&topics, None, BackpressurePolicy::Close);
+    let (second, mut second_queue) = socket(&topics, None, BackpressurePolicy::Close);
+
+    assert!(first.subscribe("news".to_owned()));
+    assert!(second.subscribe("news".to_owned()));
+    assert!(second.subscribe("sports".to_owned()));
+    assert_eq!(topics.subscribers("news"), 2);
+
+    let hello = Bytes::from("hello");
+    assert_eq!(topics.publish("news", Opcode::Text, hello.clone(), None), 2);
+    assert_eq!(
+      topics.publish("news", Opcode::Text, hello.clone(), Some(first.id())),
+      1
+    );
+    assert_eq!(topics.publish("weather", Opcode::Text, hello, None), 0);
+
+    assert!(matches!(
+      first_queue.try_recv(),
+      Ok(Outgoing::Frame(Opcode::Text, _))
+    ));
+    assert!(first_queue.try_recv().is_err());
+    assert!(second_queue.try_recv().is_ok() && second_queue.try_recv().is_ok());
+
+    assert!(first.unsubscribe("news"));
+    assert!(!first.unsubscribe("news"));
+    second.close(None);
+    second.unsubscribe_all();
+    assert!(!second.subscribe("news".to_owned()));
+    assert_eq!(topics.subscribers("news"), 0);
+    assert_eq!(topics.subscribers("sports"), 0);
+  }
+
+  #[test]
+  fn slow_consumers() {
+    let topics = Arc::new(Topics::default());
+    let (dropping, _dropping_queue) = socket(&topics, Some(4), BackpressurePolicy::Drop);
+    let (closing, mut closing_queue) = socket(&topics, Some(4), BackpressurePolicy::Close);
+    dropping.subscribe("feed".to_owned());
+    closing.subscribe("feed".to_owned());
+
+    let data = Bytes::from("12345");
+    assert_eq!(
+      topics.publish("feed", Opcode::Binary, data.clone(), None),
+      2
+    );
+    assert_eq!(
+      topics.publish("feed", Opcode::Binary, data.clone(), None),
+      0
+    );
+
+    assert!(!dropping.is_closing());
+    dropping.written(5);
+    assert!(dropping.send(Opcode::Binary, data));
+
+    assert!(closing.is_closing() && closing.is_overflowed());
+    closing_queue.try_recv().unwrap();
+    let close = closing_queue.try_recv();
+    assert!(matches!(close, Ok(Outgoing::Close(payload)) if payload[..2] == [0x03, 0xf0]));
+  }
+}